@@ -6,18 +6,44 @@
 
 use std::collections::HashMap;
 
+use log::warn;
 use serde::Deserialize;
+use serde_json::value::RawValue;
 
 use super::types::*;
 use crate::obsid::Obsid;
 
-pub(super) fn parse_asvo_json(json: &str) -> Result<AsvoJobVec, serde_json::error::Error> {
-    let strings: Vec<DummyJob> = serde_json::from_str(json)?;
-    let vec = strings
-        .into_iter()
-        .map(|dj| dj.convert_to_real_job())
-        .collect::<Vec<AsvoJob>>();
-    Ok(AsvoJobVec(vec))
+/// Parse a job listing. A job which can't be parsed doesn't stop the others
+/// from being parsed; it is reported in [ParsedJobs::errors] instead. Only a
+/// listing which isn't a JSON array at all is an error.
+pub(super) fn parse_asvo_json(json: &str) -> Result<ParsedJobs, serde_json::error::Error> {
+    // Parse the jobs one by one, keeping each job's record as it was given.
+    let records: Vec<&RawValue> = serde_json::from_str(json)?;
+    let mut parsed = ParsedJobs {
+        jobs: AsvoJobVec(Vec::with_capacity(records.len())),
+        errors: vec![],
+    };
+    for (index, r) in records.into_iter().enumerate() {
+        let result = serde_json::from_str::<DummyJob>(r.get())
+            .map_err(|e| e.to_string())
+            .and_then(DummyJob::convert_to_real_job);
+        match result {
+            Ok(mut job) => {
+                job.details.raw = Some(r.get().to_string());
+                parsed.jobs.0.push(job)
+            }
+            Err(message) => parsed.errors.push(JsonRecordError {
+                index,
+                // Try to identify the job, even though it couldn't be parsed.
+                jobid: serde_json::from_str::<serde_json::Value>(r.get())
+                    .ok()
+                    .and_then(|v| v.pointer("/row/id")?.as_u64())
+                    .and_then(|id| AsvoJobID::try_from(id).ok()),
+                message,
+            }),
+        }
+    }
+    Ok(parsed)
 }
 
 #[allow(dead_code)]
@@ -47,7 +73,11 @@ struct DummyRow {
     id: AsvoJobID,
     job_state: u8,
     job_params: DummyJobParams,
+    error_code: Option<u32>,
     error_text: Option<String>,
+    created: Option<String>,
+    started: Option<String>,
+    completed: Option<String>,
     product: Option<HashMap<String, Vec<DummyProduct>>>,
 }
 
@@ -57,27 +87,40 @@ struct DummyJob {
 }
 
 impl DummyJob {
-    fn convert_to_real_job(self) -> AsvoJob {
-        let new_files = self.row.product.map(|hm| {
-            let mut file_array = vec![];
-            for dumb_product in &hm["files"] {
-                let file_type = dumb_product.r#type.as_str();
-                file_array.push(AsvoFilesArray {
-                    r#type: match file_type {
-                        "acacia" => Delivery::Acacia,
-                        "scratch" => Delivery::Scratch,
-                        _ => panic!("Unsupported delivery type found: {}", file_type),
-                    },
-                    url: dumb_product.url.clone(),
-                    path: dumb_product.r#path.clone(),
-                    size: dumb_product.size,
-                    sha1: dumb_product.sha1.clone(),
-                })
-            }
-            file_array
-        });
-        AsvoJob {
-            obsid: Obsid::validate(self.row.job_params.obs_id.parse().unwrap()).unwrap(),
+    fn convert_to_real_job(self) -> Result<AsvoJob, String> {
+        // A product without any files is treated as no product at all.
+        let new_files = self
+            .row
+            .product
+            .and_then(|mut hm| hm.remove("files"))
+            .map(|files| {
+                let mut file_array = vec![];
+                for dumb_product in &files {
+                    let file_type = dumb_product.r#type.as_str();
+                    file_array.push(AsvoFilesArray {
+                        r#type: match file_type {
+                            "acacia" => Delivery::Acacia,
+                            "scratch" => Delivery::Scratch,
+                            _ => {
+                                warn!("Unsupported delivery type found: {}", file_type);
+                                Delivery::Other(file_type.to_string())
+                            }
+                        },
+                        url: dumb_product.url.clone(),
+                        path: dumb_product.r#path.clone(),
+                        size: dumb_product.size,
+                        sha1: dumb_product.sha1.clone(),
+                    })
+                }
+                file_array
+            });
+        let obs_id = &self.row.job_params.obs_id;
+        let obsid = obs_id
+            .parse()
+            .map_err(|_| format!("Invalid obsid '{}'", obs_id))
+            .and_then(|o| Obsid::validate(o).map_err(|e| e.to_string()))?;
+        Ok(AsvoJob {
+            obsid,
             jobid: self.row.id,
             jtype: match self.row.job_type {
                 0 => AsvoJobType::Conversion,
@@ -85,19 +128,28 @@ impl DummyJob {
                 2 => AsvoJobType::DownloadMetadata,
                 3 => AsvoJobType::DownloadVoltage,
                 4 => AsvoJobType::CancelJob,
-                _ => panic!("Unrecognised job_type!"),
+                t => return Err(format!("Unrecognised job_type {}", t)),
             },
             state: match self.row.job_state {
                 0 => AsvoJobState::Queued,
                 1 => AsvoJobState::Processing,
                 2 => AsvoJobState::Ready,
-                3 => AsvoJobState::Error(self.row.error_text.unwrap()),
+                3 => AsvoJobState::Error(self.row.error_text.unwrap_or_default()),
                 4 => AsvoJobState::Expired,
                 5 => AsvoJobState::Cancelled,
-                _ => panic!("Unrecognised job_state!"),
+                s => return Err(format!("Unrecognised job_state {}", s)),
             },
+            error_code: self.row.error_code,
             files: new_files,
-        }
+            details: AsvoJobDetails {
+                delivery: Some(self.row.job_params.delivery),
+                pawsey_group: self.row.job_params.user_pawsey_group,
+                created: self.row.created,
+                started: self.row.started,
+                completed: self.row.completed,
+                raw: None,
+            },
+        })
     }
 }
 
@@ -128,6 +180,43 @@ pub(super) enum AsvoSubmitJobResponse {
 mod tests {
     use super::*;
 
+    /// A job listing with `n` queued jobs, with job IDs counting up from 1.
+    fn big_listing(n: usize) -> String {
+        let records: Vec<String> = (1..=n)
+            .map(|i| {
+                format!(
+                    r#"{{"action": "INSERT", "table": "jobs", "row": {{"job_type": 1, "job_state": 0, "job_params": {{"delivery": "acacia", "download_type": "vis", "job_type": "download", "obs_id": "1339896408", "priority": 1}}, "error_code": null, "error_text": null, "product": null, "id": {}}}}}"#,
+                    i
+                )
+            })
+            .collect();
+        format!("[{}]", records.join(", "))
+    }
+
+    #[test]
+    fn test_parse_big_listing() {
+        let n = 50_000;
+        let parsed = parse_asvo_json(&big_listing(n)).unwrap();
+        assert!(parsed.errors.is_empty());
+        let jobs = parsed.jobs;
+        assert_eq!(jobs.0.len(), n);
+        // The jobs are in the same order as the listing.
+        assert!(jobs
+            .0
+            .iter()
+            .enumerate()
+            .all(|(i, j)| j.jobid as usize == i + 1));
+
+        // A bad job anywhere in the listing doesn't stop the others from
+        // being parsed.
+        let json = big_listing(n).replacen(r#""id": 40000}"#, r#""id": "oops"}"#, 1);
+        let parsed = parse_asvo_json(&json).unwrap();
+        assert_eq!(parsed.jobs.0.len(), n - 1);
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(parsed.errors[0].index, 39999);
+        assert_eq!(parsed.errors[0].jobid, None);
+    }
+
     #[test]
     fn test_json_job_listing_parse() {
         let json = "[{\"action\": \"INSERT\", \"table\": \"jobs\", \"row\": {\"job_type\": 1, \"job_state\": 2, \"user_id\": 1065, \"job_params\": {\"delivery\": \"acacia\", \"download_type\": \"vis\", \"job_type\": \"download\", \"obs_id\": \"1339896408\", \"priority\": 1, \"user_pawsey_group\": \"mwaops\"}, \"error_code\": null, \"error_text\": null, \"created\": \"2022-06-22T01:56:38.635146\", \"started\": \"2022-06-22T01:57:09.093927\", \"completed\": \"2022-06-22T01:57:24.693448\", \"product\": {\"files\": [{\"type\": \"acacia\", \"url\": \"https://ingest.pawsey.org.au/mwa-asvo/1339896408_575929_vis.tar?AWSAccessKeyId=0f61c75cd1184e5abc76500d71758927&Signature=XwoaCna8vNmMEBXcFji2boZ5yjk%3D&Expires=1656467844\", \"size\": 931112960, \"sha1\": \"12b0933ff3985c82a7303d8e57fa7157fe88353e\"}]}, \"id\": 575929}}]";
@@ -137,11 +226,93 @@ mod tests {
             "result is not ok: {:?}",
             result.err().unwrap()
         );
-        let jobs = result.unwrap();
+        let jobs = result.unwrap().jobs;
         assert_eq!(jobs.0.len(), 1);
         assert_eq!(jobs.0[0].jobid, 575929);
     }
 
+    #[test]
+    fn test_json_job_listing_parse_bad_records() {
+        let good = r#"{"row": {"job_type": 1, "job_state": 0, "job_params": {"delivery": "acacia", "job_type": "download", "obs_id": "1339896408", "priority": 1}, "product": null, "id": 1}}"#;
+        let bad_state = good
+            .replace(r#""job_state": 0"#, r#""job_state": 9"#)
+            .replace(r#""id": 1"#, r#""id": 2"#);
+        let bad_obsid = good
+            .replace("1339896408", "12")
+            .replace(r#""id": 1"#, r#""id": 3"#);
+        let no_files = good
+            .replace(r#""product": null"#, r#""product": {}"#)
+            .replace(r#""id": 1"#, r#""id": 4"#);
+        let json = format!(
+            r#"[{}, {}, {{"error": "oops"}}, {}, {}]"#,
+            good, bad_state, bad_obsid, no_files
+        );
+        let parsed = parse_asvo_json(&json).unwrap();
+        assert_eq!(parsed.jobs.0.len(), 2);
+        assert_eq!(parsed.jobs.0[0].jobid, 1);
+        assert_eq!(parsed.jobs.0[1].jobid, 4);
+        assert_eq!(parsed.jobs.0[1].files, None);
+        assert_eq!(
+            parsed
+                .errors
+                .iter()
+                .map(|e| (e.index, e.jobid))
+                .collect::<Vec<_>>(),
+            vec![(1, Some(2)), (2, None), (3, Some(3))]
+        );
+        assert_eq!(
+            parsed.errors[0].to_string(),
+            "Couldn't parse record 1 (ASVO job ID 2) of the job listing: Unrecognised job_state 9"
+        );
+
+        // A listing which isn't an array is still an error.
+        assert!(parse_asvo_json(r#"{"error": "oops"}"#).is_err());
+    }
+
+    #[test]
+    fn test_json_job_listing_parse_details() {
+        let json = "[{\"action\": \"INSERT\", \"table\": \"jobs\", \"row\": {\"job_type\": 1, \"job_state\": 2, \"user_id\": 1065, \"job_params\": {\"delivery\": \"acacia\", \"download_type\": \"vis\", \"job_type\": \"download\", \"obs_id\": \"1339896408\", \"priority\": 1, \"user_pawsey_group\": \"mwaops\"}, \"error_code\": null, \"error_text\": null, \"created\": \"2022-06-22T01:56:38.635146\", \"started\": \"2022-06-22T01:57:09.093927\", \"completed\": \"2022-06-22T01:57:24.693448\", \"product\": {\"files\": [{\"type\": \"acacia\", \"url\": \"https://ingest.pawsey.org.au/mwa-asvo/1339896408_575929_vis.tar?AWSAccessKeyId=0f61c75cd1184e5abc76500d71758927&Signature=XwoaCna8vNmMEBXcFji2boZ5yjk%3D&Expires=1656467844\", \"size\": 931112960, \"sha1\": \"12b0933ff3985c82a7303d8e57fa7157fe88353e\"}]}, \"id\": 575929}}]";
+        let job = &parse_asvo_json(json).unwrap().jobs.0[0];
+        assert_eq!(job.details.delivery.as_deref(), Some("acacia"));
+        assert_eq!(job.details.pawsey_group.as_deref(), Some("mwaops"));
+        assert_eq!(
+            job.details.created.as_deref(),
+            Some("2022-06-22T01:56:38.635146")
+        );
+        assert_eq!(
+            job.details.completed.as_deref(),
+            Some("2022-06-22T01:57:24.693448")
+        );
+
+        let description = job.describe();
+        assert!(description.contains("Job ID:       575929"));
+        assert!(description.contains("Pawsey group: mwaops"));
+        assert!(description.contains("sha1:     12b0933ff3985c82a7303d8e57fa7157fe88353e"));
+        assert!(serde_json::to_string(job)
+            .unwrap()
+            .contains(r#""pawseyGroup":"mwaops","created":"2022-06-22T01:56:38.635146""#));
+    }
+
+    #[test]
+    fn test_json_job_listing_parse_unknown_delivery() {
+        let json = "[{\"row\": {\"job_type\": 1, \"job_state\": 2, \"job_params\": {\"delivery\": \"dug\", \"download_type\": \"vis\", \"job_type\": \"download\", \"obs_id\": \"1339896408\", \"priority\": 1}, \"error_text\": null, \"product\": {\"files\": [{\"type\": \"dug\", \"path\": \"/somewhere/575929\", \"size\": 931112960, \"sha1\": null}]}, \"id\": 575929}}]";
+        let jobs = parse_asvo_json(json).unwrap().jobs;
+        let files = jobs.0[0].files.as_ref().unwrap();
+        assert_eq!(files[0].r#type, Delivery::Other("dug".to_string()));
+    }
+
+    #[test]
+    fn test_json_job_listing_parse_error_code() {
+        let json = "[{\"row\": {\"job_type\": 1, \"job_state\": 3, \"job_params\": {\"delivery\": \"acacia\", \"download_type\": \"vis\", \"job_type\": \"download\", \"obs_id\": \"1339896408\", \"priority\": 1}, \"error_code\": 42, \"error_text\": \"Observation has no files\", \"product\": null, \"id\": 575929}}]";
+        let jobs = parse_asvo_json(json).unwrap().jobs;
+        assert_eq!(jobs.0[0].error_code, Some(42));
+        assert_eq!(
+            jobs.0[0].describe_state(),
+            "Error [code 42]: Observation has no files"
+        );
+        assert!(jobs.json().unwrap().contains(r#""errorCode":42"#));
+    }
+
     #[test]
     fn test_json_job_submit_response_parse() {
         let json = "{\"job_id\": 308874}";
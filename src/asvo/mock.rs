@@ -0,0 +1,1513 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Tests of [AsvoClient] against a minimal mock of the MWA ASVO web service,
+//! so that they don't need a network connection or an API key.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use sha1::{Digest, Sha1};
+
+use super::tests::{make_tar, test_dir};
+use super::*;
+
+/// A request received by the mock ASVO.
+#[derive(Clone, Debug)]
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    /// Get the value of a header.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get the value of a field of an urlencoded form body.
+    fn form_field(&self, key: &str) -> Option<&str> {
+        self.body
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+    }
+}
+
+/// A mock ASVO. Logins always succeed; every other request is answered with a
+/// status code and body from a handler, which is also given the address of
+/// the mock ASVO (useful for download URLs).
+struct MockAsvo {
+    address: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockAsvo {
+    fn start<H>(handler: H) -> MockAsvo
+    where
+        H: Fn(&str, &Request) -> (u16, Vec<u8>) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let thread_address = address.clone();
+        let thread_requests = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                let request = match read_request(&stream) {
+                    Some(r) => r,
+                    None => continue,
+                };
+                let (code, body) = if request.path == "/api/api_login" {
+                    (200, vec![])
+                } else {
+                    handler(&thread_address, &request)
+                };
+                thread_requests.lock().unwrap().push(request);
+                write_response(stream, code, &body);
+            }
+        });
+
+        MockAsvo { address, requests }
+    }
+
+    /// Get a client that has logged in to this mock ASVO.
+    fn client(&self) -> AsvoClient {
+        AsvoClient::new_with_server(&self.address, "not a real key").unwrap()
+    }
+
+    /// Get the requests made to a path of this mock ASVO.
+    fn requests(&self, path: &str) -> Vec<Request> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = vec![];
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: String::new(),
+    };
+    let content_length = match request.header("content-length") {
+        Some(l) => l.parse().ok()?,
+        None => 0,
+    };
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+    request.body = String::from_utf8_lossy(&body).to_string();
+    Some(request)
+}
+
+fn write_response(mut stream: TcpStream, code: u16, body: &[u8]) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nETag: {}\r\nConnection: close\r\n\r\n",
+        code,
+        body.len(),
+        MOCK_ETAG
+    );
+    let _ = stream.write_all(body);
+}
+
+/// The ETag of every response from the mock ASVO.
+const MOCK_ETAG: &str = "\"mock\"";
+
+/// Serve `data`, honouring a "Range: bytes=N-" or "Range: bytes=N-M" header
+/// (and its "If-Range" header) like the ASVO's file store.
+fn serve_range(request: &Request, data: &[u8]) -> (u16, Vec<u8>) {
+    let range = request
+        .header("range")
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.split_once('-'))
+        .and_then(|(start, end)| {
+            let start = start.parse::<usize>().ok()?;
+            let end = match end {
+                "" => data.len(),
+                end => end.parse::<usize>().ok()? + 1,
+            };
+            Some(start..end.min(data.len()))
+        });
+    let if_range_ok = request.header("if-range").map_or(true, |e| e == MOCK_ETAG);
+    match range {
+        Some(range) if if_range_ok => (206, data[range].to_vec()),
+        _ => (200, data.to_vec()),
+    }
+}
+
+/// A job listing, as the ASVO would return it, with a single ready
+/// visibility download job.
+fn job_listing(jobid: AsvoJobID, url: &str, size: usize, sha1: Option<&str>) -> Vec<u8> {
+    serde_json::Value::Array(vec![job_row(jobid, url, size, sha1)])
+        .to_string()
+        .into_bytes()
+}
+
+/// A ready visibility download job, as it appears in the ASVO's job listing.
+fn job_row(jobid: AsvoJobID, url: &str, size: usize, sha1: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "action": "INSERT",
+        "table": "jobs",
+        "row": {
+            "job_type": 1,
+            "job_state": 2,
+            "job_params": {
+                "delivery": "acacia",
+                "download_type": "vis",
+                "job_type": "download",
+                "obs_id": "1065880128",
+                "priority": 1,
+            },
+            "error_text": null,
+            "product": {"files": [{"type": "acacia", "url": url, "size": size, "sha1": sha1}]},
+            "id": jobid,
+        }
+    })
+}
+
+#[test]
+fn test_mock_get_jobs() {
+    let asvo = MockAsvo::start(|address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(1, &format!("{}/files/1065880128_1.tar", address), 10, None),
+        ),
+        _ => (404, vec![]),
+    });
+    let jobs = asvo.client().get_jobs().unwrap();
+    assert_eq!(jobs.0.len(), 1);
+    assert_eq!(jobs.0[0].jobid, 1);
+    assert_eq!(jobs.0[0].obsid, Obsid::validate(1065880128).unwrap());
+    assert_eq!(jobs.0[0].state, AsvoJobState::Ready);
+    assert_eq!(
+        jobs.0[0].download_urls(),
+        vec![format!("{}/files/1065880128_1.tar", asvo.address)]
+    );
+    assert_eq!(asvo.requests("/api/get_jobs")[0].method, "GET");
+}
+
+#[test]
+fn test_mock_get_jobs_bad_status() {
+    for code in [429, 500] {
+        let asvo = MockAsvo::start(move |_, _| (code, b"go away".to_vec()));
+        match asvo.client().get_jobs() {
+            Err(e @ AsvoError::BadStatus { .. }) => assert_eq!(e.http_code(), Some(code)),
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Expected an error"),
+        }
+    }
+}
+
+#[test]
+fn test_mock_get_jobs_retries() {
+    // The job listing fails twice before it works.
+    let start = |listings: usize| {
+        let attempts = AtomicUsize::new(0);
+        MockAsvo::start(move |_, _| match attempts.fetch_add(1, Ordering::Relaxed) {
+            n if n < listings => (503, b"busy".to_vec()),
+            _ => (200, b"[]".to_vec()),
+        })
+    };
+    let client = |asvo: &MockAsvo, retries| {
+        let opts = ClientOptions {
+            retries,
+            ..Default::default()
+        };
+        AsvoClient::new_with_server_and_options(&asvo.address, "not a real key", &opts).unwrap()
+    };
+
+    let asvo = start(2);
+    assert!(client(&asvo, Some(2)).get_jobs().unwrap().0.is_empty());
+    assert_eq!(asvo.requests("/api/get_jobs").len(), 3);
+
+    let asvo = start(2);
+    assert!(matches!(
+        client(&asvo, Some(1)).get_jobs(),
+        Err(AsvoError::BadStatus { code, .. }) if code == 503
+    ));
+    assert_eq!(asvo.requests("/api/get_jobs").len(), 2);
+
+    // By default, the job listing isn't retried.
+    let asvo = start(2);
+    assert!(client(&asvo, None).get_jobs().is_err());
+    assert_eq!(asvo.requests("/api/get_jobs").len(), 1);
+}
+
+#[test]
+fn test_mock_get_jobs_html_error_page() {
+    let page = format!(
+        "<html>\n  <head><title>502 Bad Gateway</title></head>\n  <body>{}</body>\n</html>",
+        "x".repeat(500)
+    );
+    let asvo = MockAsvo::start(move |_, _| (502, page.clone().into_bytes()));
+    match asvo.client().get_jobs() {
+        Err(AsvoError::ServerUnavailable { status, snippet }) => {
+            assert_eq!(status, 502);
+            assert!(snippet.starts_with("<html> <head><title>502 Bad Gateway</title>"));
+            assert!(snippet.ends_with("..."));
+            assert!(snippet.chars().count() < 210);
+        }
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Expected an error"),
+    }
+
+    // Some proxies even say that everything is fine.
+    let asvo = MockAsvo::start(|_, _| (200, b"<!DOCTYPE html><p>Maintenance</p>".to_vec()));
+    assert!(matches!(
+        asvo.client().get_jobs(),
+        Err(AsvoError::ServerUnavailable { snippet, .. }) if snippet == "<!DOCTYPE html><p>Maintenance</p>"
+    ));
+}
+
+#[test]
+fn test_mock_submit_responses() {
+    let no_files = "Unable to submit job. Observation has no files to download.";
+    let cases = [
+        (r#"{"job_id": 123}"#.to_string(), Some(Some(123))),
+        (
+            r#"{"error": "Job already queued", "error_code": 2, "job_id": 123}"#.to_string(),
+            Some(None),
+        ),
+        (
+            r#"{"error": "Something else", "error_code": 3, "job_id": 123}"#.to_string(),
+            None,
+        ),
+        (
+            format!(r#"{{"error": "{}", "error_code": 0}}"#, no_files),
+            Some(None),
+        ),
+        (r#"{"error": "Invalid", "error_code": 1}"#.to_string(), None),
+        (r#"{"error": "Invalid"}"#.to_string(), None),
+    ];
+    for (body, expected) in cases {
+        let response = body.clone().into_bytes();
+        let asvo = MockAsvo::start(move |_, _| (200, response.clone()));
+        let result = asvo.client().submit_vis(
+            Obsid::validate(1065880128).unwrap(),
+            Delivery::Acacia,
+            None,
+            false,
+        );
+        match (result, expected) {
+            (Ok(j), Some(expected)) => assert_eq!(j, expected, "response: {}", body),
+            (Err(AsvoError::BadRequest { .. }), None) => (),
+            (result, _) => panic!("Unexpected result for {}: {:?}", body, result),
+        }
+    }
+}
+
+#[test]
+fn test_mock_submit_outage() {
+    let cases = [
+        (
+            "Your job cannot be submitted as there is a full outage in progress.",
+            false,
+        ),
+        (
+            "Your job cannot be submitted as there is a partial outage, please use a delivery location other than acacia.",
+            true,
+        ),
+        (
+            "Your job cannot be submitted as the staging server is down and also acacia is unavailable!",
+            false,
+        ),
+    ];
+    for (error, expected) in cases {
+        let response = serde_json::json!({ "error": error, "error_code": 0 })
+            .to_string()
+            .into_bytes();
+        let asvo = MockAsvo::start(move |_, _| (200, response.clone()));
+        let result = asvo.client().submit_vis(
+            Obsid::validate(1065880128).unwrap(),
+            Delivery::Acacia,
+            None,
+            false,
+        );
+        match result {
+            Err(AsvoError::ServiceOutage { partial, message }) => {
+                assert_eq!(partial, expected);
+                assert_eq!(message, error);
+            }
+            r => panic!("unexpected result for {:?}: {:?}", error, r),
+        }
+    }
+}
+
+#[test]
+fn test_mock_submit_form() {
+    let asvo = MockAsvo::start(|_, _| (200, br#"{"job_id": 123}"#.to_vec()));
+    let mut extra_fields = BTreeMap::new();
+    extra_fields.insert("new_param", "1");
+    let result = asvo.client().submit_meta_with_extra(
+        Obsid::validate(1065880128).unwrap(),
+        Delivery::Scratch,
+        Some(DeliveryFormat::Tar),
+        true,
+        &extra_fields,
+    );
+    assert_eq!(result.unwrap(), Some(123));
+
+    let requests = asvo.requests("/api/download_vis_job");
+    assert_eq!(requests.len(), 1);
+    let request = &requests[0];
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.form_field("obs_id"), Some("1065880128"));
+    assert_eq!(request.form_field("delivery"), Some("scratch"));
+    assert_eq!(request.form_field("delivery_format"), Some("tar"));
+    assert_eq!(request.form_field("download_type"), Some("vis_meta"));
+    assert_eq!(request.form_field("allow_resubmit"), Some("true"));
+    assert_eq!(request.form_field("new_param"), Some("1"));
+}
+
+#[test]
+fn test_mock_submit_conv_defaults() {
+    let asvo = MockAsvo::start(|_, _| (200, br#"{"job_id": 123}"#.to_vec()));
+    let mut parameters = BTreeMap::new();
+    parameters.insert("output", "ms");
+    for use_defaults in [true, false] {
+        asvo.client()
+            .submit_conv_with_extra(
+                Obsid::validate(1065880128).unwrap(),
+                Delivery::Acacia,
+                None,
+                &parameters,
+                use_defaults,
+                false,
+                &BTreeMap::new(),
+            )
+            .unwrap();
+    }
+
+    let requests = asvo.requests("/api/conversion_job");
+    assert_eq!(requests.len(), 2);
+    // The user's parameters override the defaults...
+    assert_eq!(requests[0].form_field("output"), Some("ms"));
+    assert_eq!(requests[0].form_field("avg_freq_res"), Some("80"));
+    // ... or replace them entirely.
+    assert_eq!(requests[1].form_field("output"), Some("ms"));
+    assert_eq!(requests[1].form_field("avg_freq_res"), None);
+    assert_eq!(requests[1].form_field("delivery"), Some("acacia"));
+}
+
+#[test]
+fn test_mock_submit_delivery_format_from_env() {
+    let asvo = MockAsvo::start(|_, _| (200, br#"{"job_id": 123}"#.to_vec()));
+    // As if GIANT_SQUID_DELIVERY_FORMAT=tar and no -f argument was given.
+    let delivery_format =
+        DeliveryFormat::validate_with_env(None::<&str>, Ok("tar".to_string())).unwrap();
+    let result = asvo.client().submit_vis(
+        Obsid::validate(1065880128).unwrap(),
+        Delivery::Scratch,
+        delivery_format,
+        false,
+    );
+    assert_eq!(result.unwrap(), Some(123));
+    let requests = asvo.requests("/api/download_vis_job");
+    assert_eq!(requests[0].form_field("delivery_format"), Some("tar"));
+}
+
+#[test]
+fn test_mock_proxy_auth_required() {
+    let asvo = MockAsvo::start(|_, _| (407, vec![]));
+    match asvo.client().get_jobs() {
+        Err(e @ AsvoError::ProxyAuthRequired) => assert_eq!(e.http_code(), Some(407)),
+        _ => panic!("expected a ProxyAuthRequired error"),
+    }
+
+    // Downloads aren't retried, as retrying won't help.
+    let asvo = MockAsvo::start(|address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(1, &format!("{}/files/1065880128_1.tar", address), 10, None),
+        ),
+        _ => (407, vec![]),
+    });
+    let dir = test_dir("mock_proxy_auth_required");
+    let result = asvo.client().download_job(
+        1,
+        &DownloadOptions {
+            hash: false,
+            ..options(&dir)
+        },
+    );
+    assert!(matches!(result, Err(AsvoError::ProxyAuthRequired)));
+    assert_eq!(asvo.requests("/files/1065880128_1.tar").len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_cancel() {
+    let asvo = MockAsvo::start(|_, request| match request.path.as_str() {
+        "/api/cancel_job?job_id=1" => (200, b"{}".to_vec()),
+        "/api/cancel_job?job_id=2" => (404, vec![]),
+        "/api/cancel_job?job_id=3" => (
+            400,
+            br#"{"error_code": 0, "error": "Job is already complete"}"#.to_vec(),
+        ),
+        _ => (500, vec![]),
+    });
+    let client = asvo.client();
+    assert_eq!(client.cancel_asvo_job(1).unwrap(), Some(1));
+    assert_eq!(client.cancel_asvo_job(2).unwrap(), None);
+    assert!(matches!(
+        client.cancel_asvo_job(3),
+        Err(AsvoError::BadRequest { code: 0, message }) if message == "Job is already complete"
+    ));
+    assert!(matches!(
+        client.cancel_asvo_job(4),
+        Err(AsvoError::BadStatus { .. })
+    ));
+    assert_eq!(asvo.requests("/api/cancel_job?job_id=1")[0].method, "GET");
+}
+
+#[test]
+fn test_mock_cancel_jobs() {
+    let asvo = MockAsvo::start(|_, request| match request.path.as_str() {
+        "/api/cancel_job?job_id=1" => (500, vec![]),
+        "/api/cancel_job?job_id=2" => (404, vec![]),
+        "/api/cancel_job?job_id=3" => (200, b"{}".to_vec()),
+        _ => (500, vec![]),
+    });
+    let results = asvo.client().cancel_jobs(&[1, 2, 3]);
+    assert_eq!(
+        results.iter().map(|(jobid, _)| *jobid).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+    // A failure doesn't stop the rest of the jobs from being cancelled.
+    assert!(matches!(results[0].1, Err(AsvoError::BadStatus { .. })));
+    assert!(matches!(results[1].1, Ok(None)));
+    assert!(matches!(results[2].1, Ok(Some(3))));
+}
+
+#[test]
+fn test_mock_download_waits_for_files() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    let listings = AtomicUsize::new(0);
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => {
+            let mut row = job_row(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar.len(),
+                Some(&sha1),
+            );
+            // The job is ready, but its files only turn up in the third
+            // listing; the second has a file without a URL.
+            match listings.fetch_add(1, Ordering::Relaxed) {
+                0 => row["row"]["product"] = serde_json::json!({"files": []}),
+                1 => row["row"]["product"]["files"][0]["url"] = serde_json::Value::Null,
+                _ => (),
+            }
+            (
+                200,
+                serde_json::Value::Array(vec![row]).to_string().into_bytes(),
+            )
+        }
+        "/files/1065880128_1.tar" => (200, tar.clone()),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_waits_for_files");
+    let mut client = asvo.client();
+    client.materializing_delay = Duration::ZERO;
+    let download = client.download_job(1, &options(&dir)).unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128.metafits")]);
+    assert_eq!(asvo.requests("/api/get_jobs").len(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_files_never_listed() {
+    let asvo = MockAsvo::start(|address, request| match request.path.as_str() {
+        "/api/get_jobs" => {
+            let mut row = job_row(1, &format!("{}/files/1065880128_1.tar", address), 10, None);
+            row["row"]["product"] = serde_json::json!({"files": []});
+            (
+                200,
+                serde_json::Value::Array(vec![row]).to_string().into_bytes(),
+            )
+        }
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_files_never_listed");
+    let mut client = asvo.client();
+    client.materializing_delay = Duration::ZERO;
+    assert!(matches!(
+        client.download_job(1, &options(&dir)),
+        Err(AsvoError::ReadyButNoFiles { jobid: 1 })
+    ));
+    assert_eq!(
+        asvo.requests("/api/get_jobs").len(),
+        1 + MATERIALIZING_RETRIES as usize
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Default download options, downloading into `dir`.
+fn options(dir: &Path) -> DownloadOptions {
+    DownloadOptions {
+        download_dir: dir.to_str().unwrap().to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_mock_download_retries_server_errors() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    let file_requests = AtomicUsize::new(0);
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar.len(),
+                Some(&sha1),
+            ),
+        ),
+        // Fail the first download attempt.
+        "/files/1065880128_1.tar" => match file_requests.fetch_add(1, Ordering::Relaxed) {
+            0 => (503, vec![]),
+            _ => (200, tar.clone()),
+        },
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_retries_server_errors");
+    let download = asvo.client().download_job(1, &options(&dir)).unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128.metafits")]);
+    assert_eq!(
+        std::fs::read(dir.join("1065880128.metafits")).unwrap(),
+        b"metafits"
+    );
+    assert_eq!(asvo.requests("/files/1065880128_1.tar").len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_gives_up() {
+    let asvo = MockAsvo::start(|address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            serde_json::json!([
+                job_row(1, &format!("{}/files/1.tar", address), 10, Some("abc")),
+                job_row(2, &format!("{}/files/2.tar", address), 10, Some("abc")),
+            ])
+            .to_string()
+            .into_bytes(),
+        ),
+        _ => (500, vec![]),
+    });
+    let dir = test_dir("mock_download_gives_up");
+    let mut client = asvo.client();
+    client.max_consecutive_failures = 2;
+    let opts = DownloadOptions {
+        concurrency: 2,
+        ..options(&dir)
+    };
+    // Both files failing counts as two failures, so both downloads give up.
+    let results = client.download_jobs(&[1, 2], &opts).unwrap();
+    assert!(results
+        .iter()
+        .all(|r| matches!(r, Err(AsvoError::Unavailable(2)))));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_flaky_file_counts_once() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    let file_requests = AtomicUsize::new(0);
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar.len(),
+                Some(&sha1),
+            ),
+        ),
+        // Fail the first two download attempts.
+        "/files/1065880128_1.tar" => match file_requests.fetch_add(1, Ordering::Relaxed) {
+            0 | 1 => (503, vec![]),
+            _ => (200, tar.clone()),
+        },
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_flaky_file_counts_once");
+    let mut client = asvo.client();
+    client.max_consecutive_failures = 2;
+    let download = client.download_job(1, &options(&dir)).unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128.metafits")]);
+    assert_eq!(asvo.requests("/files/1065880128_1.tar").len(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_no_hash() {
+    let asvo = MockAsvo::start(|address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(1, &format!("{}/files/1065880128_1.tar", address), 10, None),
+        ),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_no_hash");
+    let result = asvo.client().download_job(1, &options(&dir));
+    assert!(matches!(result, Err(AsvoError::NoHash(1))));
+    // Nothing should have been downloaded.
+    assert!(asvo.requests("/files/1065880128_1.tar").is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_file_checksums() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    let url_sha1 = sha1.clone();
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar.len(),
+                Some(&url_sha1),
+            ),
+        ),
+        "/files/1065880128_1.tar" => (200, tar.clone()),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_file_checksums");
+    let opts = DownloadOptions {
+        file_checksums: true,
+        ..options(&dir)
+    };
+    let download = asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(
+        download.checksums,
+        vec![(
+            dir.join("1065880128.metafits"),
+            format!("{:x}", Sha1::digest(b"metafits"))
+        )]
+    );
+
+    // Kept archives are hashed as a whole.
+    let opts = DownloadOptions {
+        keep_tar: true,
+        ..opts
+    };
+    let download = asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(
+        download.checksums,
+        vec![(dir.join("1065880128_1.tar"), sha1)]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_save_job_json() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar.len(),
+                Some(&sha1),
+            ),
+        ),
+        "/files/1065880128_1.tar" => (200, tar.clone()),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_save_job_json");
+    let opts = DownloadOptions {
+        save_job_json: true,
+        ..options(&dir)
+    };
+    let download = asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128.metafits")]);
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("1.json")).unwrap()).unwrap();
+    // The record is saved as the ASVO gave it.
+    assert_eq!(json["table"], "jobs");
+    assert_eq!(json["row"]["id"], 1);
+    assert_eq!(json["row"]["job_params"]["obs_id"], "1065880128");
+    assert!(json["row"]["product"]["files"][0]["sha1"].is_string());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_empty_archive() {
+    let asvo = ranged_archive_asvo(make_tar(&[]));
+    let dir = test_dir("mock_download_empty_archive");
+    let result = asvo.client().download_job(1, &options(&dir));
+    assert!(matches!(result, Err(AsvoError::EmptyDownload { jobid: 1 })));
+    // It isn't worth trying again.
+    assert_eq!(asvo.requests("/files/1065880128_1.tar").len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_truncated_archive() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let file_requests = AtomicUsize::new(0);
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar.len(),
+                None,
+            ),
+        ),
+        // The first response is missing the end-of-archive blocks, which
+        // doesn't stop the archive from being extracted.
+        "/files/1065880128_1.tar" => match file_requests.fetch_add(1, Ordering::Relaxed) {
+            0 => (200, tar[..tar.len() - 1024].to_vec()),
+            _ => (200, tar.clone()),
+        },
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_truncated_archive");
+    let opts = DownloadOptions {
+        hash: false,
+        ..options(&dir)
+    };
+    let download = asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128.metafits")]);
+    // The truncated archive was detected and downloaded again.
+    assert_eq!(asvo.requests("/files/1065880128_1.tar").len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_expired_url() {
+    let tar = make_tar(&[("1065880128.metafits", &[1; 5000])]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    let listings = AtomicUsize::new(0);
+    let data = tar.clone();
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        // The URL in the first listing has expired by the time it is used.
+        "/api/get_jobs" => {
+            let dir = match listings.fetch_add(1, Ordering::Relaxed) {
+                0 => "old",
+                _ => "new",
+            };
+            (
+                200,
+                job_listing(
+                    1,
+                    &format!("{}/{}/1065880128_1.tar", address, dir),
+                    data.len(),
+                    Some(&sha1),
+                ),
+            )
+        }
+        "/old/1065880128_1.tar" => (403, vec![]),
+        "/new/1065880128_1.tar" => serve_range(request, &data),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_expired_url");
+    std::fs::write(dir.join("1065880128_1.tar.part"), &tar[..2000]).unwrap();
+    std::fs::write(dir.join("1065880128_1.tar.part.etag"), MOCK_ETAG).unwrap();
+
+    let opts = DownloadOptions {
+        keep_tar: true,
+        ..options(&dir)
+    };
+    asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(std::fs::read(dir.join("1065880128_1.tar")).unwrap(), tar);
+    assert_eq!(asvo.requests("/old/1065880128_1.tar").len(), 1);
+    // The download was resumed with the fresh URL.
+    let requests = asvo.requests("/new/1065880128_1.tar");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("range"), Some("bytes=2000-"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_no_retries() {
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(1, &format!("{}/files/1065880128_1.tar", address), 10, None),
+        ),
+        _ => (503, vec![]),
+    });
+    let opts = ClientOptions {
+        retries: Some(0),
+        ..Default::default()
+    };
+    let client =
+        AsvoClient::new_with_server_and_options(&asvo.address, "not a real key", &opts).unwrap();
+    let dir = test_dir("mock_download_no_retries");
+    let result = client.download_job(
+        1,
+        &DownloadOptions {
+            hash: false,
+            ..options(&dir)
+        },
+    );
+    assert_eq!(result.unwrap_err().http_code(), Some(503));
+    assert_eq!(asvo.requests("/files/1065880128_1.tar").len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_one_file_mismatches() {
+    let tar1 = make_tar(&[("1.fits", b"one")]);
+    let tar2 = make_tar(&[("2.fits", b"two")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar1));
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => {
+            // The job's second file doesn't match its listed hash.
+            let mut row = job_row(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar1.len(),
+                Some(&sha1),
+            );
+            row["row"]["product"]["files"]
+                .as_array_mut()
+                .unwrap()
+                .push(serde_json::json!({
+                    "type": "acacia",
+                    "url": format!("{}/files/1065880128_2.tar", address),
+                    "size": tar2.len(),
+                    "sha1": "0000000000000000000000000000000000000000",
+                }));
+            (
+                200,
+                serde_json::Value::Array(vec![row]).to_string().into_bytes(),
+            )
+        }
+        "/files/1065880128_1.tar" => (200, tar1.clone()),
+        "/files/1065880128_2.tar" => (200, tar2.clone()),
+        _ => (404, vec![]),
+    });
+    let opts = ClientOptions {
+        retries: Some(0),
+        ..Default::default()
+    };
+    let client =
+        AsvoClient::new_with_server_and_options(&asvo.address, "not a real key", &opts).unwrap();
+    let dir = test_dir("mock_download_one_file_mismatches");
+    let result = client.download_job(1, &options(&dir));
+    // The mismatch is kept, so that callers can tell it from other failures.
+    match result {
+        Err(AsvoError::FilesFailed {
+            jobid: 1,
+            total: 2,
+            failed,
+        }) => {
+            assert_eq!(failed.len(), 1);
+            assert_eq!(failed[0].0, "1065880128_2.tar");
+            assert!(matches!(
+                failed[0].1,
+                AsvoError::HashMismatch { jobid: 1, .. }
+            ));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(std::fs::read(dir.join("1.fits")).unwrap(), b"one");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_some_files_fail() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => {
+            // The job's first file is missing, but its second is fine.
+            let good = format!("{}/files/1065880128_2.tar", address);
+            let mut row = job_row(1, &format!("{}/files/1065880128_1.tar", address), 10, None);
+            row["row"]["product"]["files"].as_array_mut().unwrap().push(
+                serde_json::json!({"type": "acacia", "url": good, "size": tar.len(), "sha1": sha1}),
+            );
+            (
+                200,
+                serde_json::Value::Array(vec![row]).to_string().into_bytes(),
+            )
+        }
+        "/files/1065880128_2.tar" => (200, tar.clone()),
+        _ => (404, vec![]),
+    });
+    let opts = ClientOptions {
+        retries: Some(0),
+        ..Default::default()
+    };
+    let client =
+        AsvoClient::new_with_server_and_options(&asvo.address, "not a real key", &opts).unwrap();
+    let dir = test_dir("mock_download_some_files_fail");
+    let result = client.download_job(
+        1,
+        &DownloadOptions {
+            hash: false,
+            ..options(&dir)
+        },
+    );
+    match result {
+        Err(AsvoError::FilesFailed {
+            jobid: 1,
+            total: 2,
+            failed,
+        }) => {
+            assert_eq!(failed.len(), 1);
+            assert_eq!(failed[0].0, "1065880128_1.tar");
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // The other file was still downloaded.
+    assert_eq!(
+        std::fs::read(dir.join("1065880128.metafits")).unwrap(),
+        b"metafits"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_job_timeout() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => {
+            let mut row = job_row(1, &format!("{}/files/1065880128_1.tar", address), 10, None);
+            row["row"]["product"]["files"].as_array_mut().unwrap().push(serde_json::json!(
+                {"type": "acacia", "url": format!("{}/files/1065880128_2.tar", address), "size": tar.len(), "sha1": null}
+            ));
+            (
+                200,
+                serde_json::Value::Array(vec![row]).to_string().into_bytes(),
+            )
+        }
+        "/files/1065880128_1.tar" | "/files/1065880128_2.tar" => (200, tar.clone()),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_job_timeout");
+    let timeout = std::time::Duration::ZERO;
+    let result = asvo.client().download_job(
+        1,
+        &DownloadOptions {
+            hash: false,
+            job_timeout: Some(timeout),
+            ..options(&dir)
+        },
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+    // The whole job is abandoned, not just its first file.
+    match result {
+        Err(AsvoError::JobTimeout {
+            jobid: 1,
+            timeout: t,
+        }) => assert_eq!(t, timeout),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert!(asvo.requests("/files/1065880128_1.tar").is_empty());
+    assert!(asvo.requests("/files/1065880128_2.tar").is_empty());
+}
+
+#[test]
+fn test_mock_download_stalled() {
+    let tar = make_tar(&[("1065880128.metafits", &[1; 5000])]);
+    // The first response stops part way through, but the connection stays
+    // open.
+    let file_server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let file_url = format!(
+        "http://{}/files/1065880128_1.tar",
+        file_server.local_addr().unwrap()
+    );
+    let data = tar.clone();
+    std::thread::spawn(move || {
+        for (i, stream) in file_server.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let data = data.clone();
+            std::thread::spawn(move || {
+                read_request(&stream);
+                if i == 0 {
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 Mock\r\nContent-Length: {}\r\n\r\n",
+                        data.len()
+                    );
+                    let _ = stream.write_all(&data[..2000]);
+                    std::thread::sleep(Duration::from_secs(5));
+                } else {
+                    write_response(stream, 200, &data);
+                }
+            });
+        }
+    });
+    let asvo = MockAsvo::start(move |_, request| match request.path.as_str() {
+        "/api/get_jobs" => (200, job_listing(1, &file_url, tar.len(), None)),
+        _ => (404, vec![]),
+    });
+
+    let dir = test_dir("mock_download_stalled");
+    let opts = DownloadOptions {
+        hash: false,
+        stall_timeout: Some(Duration::from_millis(500)),
+        ..options(&dir)
+    };
+    let start = std::time::Instant::now();
+    let download = asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128.metafits")]);
+    // The stall was caught well before the first response finished.
+    assert!(start.elapsed() < Duration::from_secs(4));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Set up a mock ASVO with a single ready job whose archive supports ranged
+/// requests.
+fn ranged_archive_asvo(tar: Vec<u8>) -> MockAsvo {
+    let sha1 = format!("{:x}", Sha1::digest(&tar));
+    MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            job_listing(
+                1,
+                &format!("{}/files/1065880128_1.tar", address),
+                tar.len(),
+                Some(&sha1),
+            ),
+        ),
+        "/files/1065880128_1.tar" => serve_range(request, &tar),
+        _ => (404, vec![]),
+    })
+}
+
+#[test]
+fn test_mock_download_slow_but_progressing() {
+    let tar = make_tar(&[("1065880128.metafits", &[1; 5000])]);
+    // The response trickles in, taking much longer than the stall timeout in
+    // total, but never pausing for that long.
+    let file_server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let file_url = format!(
+        "http://{}/files/1065880128_1.tar",
+        file_server.local_addr().unwrap()
+    );
+    let connections = Arc::new(AtomicUsize::new(0));
+    let thread_connections = Arc::clone(&connections);
+    let data = tar.clone();
+    std::thread::spawn(move || {
+        for stream in file_server.incoming() {
+            let mut stream = stream.unwrap();
+            thread_connections.fetch_add(1, Ordering::Relaxed);
+            read_request(&stream);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                data.len()
+            );
+            for chunk in data.chunks(data.len() / 10 + 1) {
+                std::thread::sleep(Duration::from_millis(150));
+                let _ = stream.write_all(chunk);
+                let _ = stream.flush();
+            }
+        }
+    });
+    let asvo = MockAsvo::start(move |_, request| match request.path.as_str() {
+        "/api/get_jobs" => (200, job_listing(1, &file_url, tar.len(), None)),
+        _ => (404, vec![]),
+    });
+
+    let dir = test_dir("mock_download_slow_but_progressing");
+    let opts = DownloadOptions {
+        hash: false,
+        stall_timeout: Some(Duration::from_millis(500)),
+        ..options(&dir)
+    };
+    let start = std::time::Instant::now();
+    let download = asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128.metafits")]);
+    assert!(start.elapsed() > Duration::from_secs(1));
+    // The download wasn't restarted.
+    assert_eq!(connections.load(Ordering::Relaxed), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_keep_tar_resume() {
+    let tar = make_tar(&[("1065880128.metafits", &[1; 5000])]);
+    let asvo = ranged_archive_asvo(tar.clone());
+    let dir = test_dir("mock_keep_tar_resume");
+    std::fs::write(dir.join("1065880128_1.tar.part"), &tar[..2000]).unwrap();
+    std::fs::write(dir.join("1065880128_1.tar.part.etag"), MOCK_ETAG).unwrap();
+
+    let download = asvo
+        .client()
+        .download_job(
+            1,
+            &DownloadOptions {
+                keep_tar: true,
+                ..options(&dir)
+            },
+        )
+        .unwrap();
+    assert_eq!(download.paths, vec![dir.join("1065880128_1.tar")]);
+    assert_eq!(std::fs::read(dir.join("1065880128_1.tar")).unwrap(), tar);
+    assert!(!dir.join("1065880128_1.tar.part").exists());
+    assert!(!dir.join("1065880128_1.tar.part.etag").exists());
+
+    let requests = asvo.requests("/files/1065880128_1.tar");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("range"), Some("bytes=2000-"));
+    assert_eq!(requests[0].header("if-range"), Some(MOCK_ETAG));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_keep_tar_resume_changed_file() {
+    let tar = make_tar(&[("1065880128.metafits", &[1; 5000])]);
+    let asvo = ranged_archive_asvo(tar.clone());
+    let dir = test_dir("mock_keep_tar_resume_changed_file");
+    std::fs::write(dir.join("1065880128_1.tar.part"), [0; 2000]).unwrap();
+    std::fs::write(dir.join("1065880128_1.tar.part.etag"), "\"old\"").unwrap();
+
+    asvo.client()
+        .download_job(
+            1,
+            &DownloadOptions {
+                keep_tar: true,
+                ..options(&dir)
+            },
+        )
+        .unwrap();
+    assert_eq!(std::fs::read(dir.join("1065880128_1.tar")).unwrap(), tar);
+    // The server sent the whole file, because the ETag didn't match.
+    assert_eq!(asvo.requests("/files/1065880128_1.tar").len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_keep_tar_resume_corrupt_partial() {
+    let tar = make_tar(&[("1065880128.metafits", &[1; 5000])]);
+    let asvo = ranged_archive_asvo(tar.clone());
+    let dir = test_dir("mock_keep_tar_resume_corrupt_partial");
+    // A partial download without an ETag can't be checked until the download
+    // is complete.
+    std::fs::write(dir.join("1065880128_1.tar.part"), [0; 2000]).unwrap();
+
+    asvo.client()
+        .download_job(
+            1,
+            &DownloadOptions {
+                keep_tar: true,
+                ..options(&dir)
+            },
+        )
+        .unwrap();
+    assert_eq!(std::fs::read(dir.join("1065880128_1.tar")).unwrap(), tar);
+    // The corrupt partial download was discarded, and the retry started from
+    // scratch.
+    let requests = asvo.requests("/files/1065880128_1.tar");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].header("range"), Some("bytes=2000-"));
+    assert_eq!(requests[1].header("range"), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_keep_tar_verify_partial() {
+    let tar = make_tar(&[("1065880128.metafits", &[1; 5000])]);
+    let asvo = ranged_archive_asvo(tar.clone());
+    let dir = test_dir("mock_keep_tar_verify_partial");
+    let opts = DownloadOptions {
+        keep_tar: true,
+        verify_partial: true,
+        ..options(&dir)
+    };
+
+    // A good partial download is resumed.
+    std::fs::write(dir.join("1065880128_1.tar.part"), &tar[..2000]).unwrap();
+    std::fs::write(dir.join("1065880128_1.tar.part.etag"), MOCK_ETAG).unwrap();
+    asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(std::fs::read(dir.join("1065880128_1.tar")).unwrap(), tar);
+    let requests = asvo.requests("/files/1065880128_1.tar");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].header("range"), Some("bytes=0-1999"));
+    assert_eq!(requests[1].header("range"), Some("bytes=2000-"));
+
+    // A corrupt one is caught before it's resumed, rather than once the
+    // whole archive has been downloaded.
+    std::fs::remove_file(dir.join("1065880128_1.tar")).unwrap();
+    let mut corrupt = tar[..2000].to_vec();
+    corrupt[1999] ^= 0xff;
+    std::fs::write(dir.join("1065880128_1.tar.part"), &corrupt).unwrap();
+    std::fs::write(dir.join("1065880128_1.tar.part.etag"), MOCK_ETAG).unwrap();
+    asvo.client().download_job(1, &opts).unwrap();
+    assert_eq!(std::fs::read(dir.join("1065880128_1.tar")).unwrap(), tar);
+    let requests = asvo.requests("/files/1065880128_1.tar");
+    assert_eq!(requests.len(), 4);
+    assert_eq!(requests[2].header("range"), Some("bytes=0-1999"));
+    assert_eq!(requests[3].header("range"), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_keep_tar_output_template() {
+    let tar = make_tar(&[("1065880128.metafits", b"metafits")]);
+    let asvo = ranged_archive_asvo(tar.clone());
+    let dir = test_dir("mock_keep_tar_output_template");
+    let template = OutputTemplate::from_str("{obsid}/{jobid}_{filename}").unwrap();
+
+    let download = asvo
+        .client()
+        .download_job(
+            1,
+            &DownloadOptions {
+                keep_tar: true,
+                output_template: Some(template),
+                ..options(&dir)
+            },
+        )
+        .unwrap();
+    let expected = dir.join("1065880128/1_1065880128_1.tar");
+    assert_eq!(download.paths, vec![expected.clone()]);
+    assert_eq!(std::fs::read(expected).unwrap(), tar);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_jobs() {
+    let tar1 = make_tar(&[("1.fits", b"one")]);
+    let tar2 = make_tar(&[("2.fits", b"two")]);
+    let sha1 = format!("{:x}", Sha1::digest(&tar1));
+    let sha2 = format!("{:x}", Sha1::digest(&tar2));
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            serde_json::json!([
+                job_row(
+                    1,
+                    &format!("{}/files/1.tar", address),
+                    tar1.len(),
+                    Some(&sha1)
+                ),
+                job_row(
+                    2,
+                    &format!("{}/files/2.tar", address),
+                    tar2.len(),
+                    Some(&sha2)
+                ),
+            ])
+            .to_string()
+            .into_bytes(),
+        ),
+        "/files/1.tar" => (200, tar1.clone()),
+        "/files/2.tar" => (200, tar2.clone()),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_jobs");
+    let opts = DownloadOptions {
+        concurrency: 2,
+        ..options(&dir)
+    };
+
+    let results = asvo.client().download_jobs(&[2, 3, 1], &opts).unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().paths, vec![dir.join("2.fits")]);
+    assert!(matches!(results[1], Err(AsvoError::NoAsvoJob(3))));
+    assert_eq!(results[2].as_ref().unwrap().paths, vec![dir.join("1.fits")]);
+    // The job listing was only fetched once.
+    assert_eq!(asvo.requests("/api/get_jobs").len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_sizes() {
+    let asvo = MockAsvo::start(|address, request| match request.path.as_str() {
+        "/api/get_jobs" => {
+            let mut other_obsid = job_row(2, &format!("{}/files/2.tar", address), 250, None);
+            other_obsid["row"]["job_params"]["obs_id"] = "1065880129".into();
+            let mut queued = job_row(3, &format!("{}/files/3.tar", address), 0, None);
+            queued["row"]["job_params"]["obs_id"] = "1065880130".into();
+            queued["row"]["job_state"] = 0.into();
+            queued["row"]["product"] = serde_json::Value::Null;
+            (
+                200,
+                serde_json::json!([
+                    job_row(1, &format!("{}/files/1.tar", address), 100, None),
+                    other_obsid,
+                    queued,
+                ])
+                .to_string()
+                .into_bytes(),
+            )
+        }
+        _ => (404, vec![]),
+    });
+    let client = asvo.client();
+    let opts = DownloadOptions::default();
+    let obsid = Obsid::validate(1065880128).unwrap();
+
+    // Job 1 is named by both its job ID and its obsid, but is only counted
+    // once; queued job 3 has no files yet, so isn't counted.
+    let sizes = client.download_sizes(&[1, 2, 3], &[obsid], &opts).unwrap();
+    assert_eq!(
+        sizes,
+        vec![
+            (1, obsid, 100),
+            (2, Obsid::validate(1065880129).unwrap(), 250)
+        ]
+    );
+    assert_eq!(
+        client
+            .estimate_download_size(&[1, 2, 3], &[obsid], &opts)
+            .unwrap(),
+        350
+    );
+    assert!(matches!(
+        client.estimate_download_size(&[4], &[], &opts),
+        Err(AsvoError::NoAsvoJob(4))
+    ));
+}
+
+#[test]
+fn test_mock_download_max_total_size() {
+    let tar1 = make_tar(&[("1.fits", b"one")]);
+    let tar2 = make_tar(&[("2.fits", b"two")]);
+    let (size1, size2) = (tar1.len(), tar2.len());
+    let asvo = MockAsvo::start(move |address, request| match request.path.as_str() {
+        "/api/get_jobs" => (
+            200,
+            serde_json::json!([
+                job_row(1, &format!("{}/files/1.tar", address), tar1.len(), None),
+                job_row(2, &format!("{}/files/2.tar", address), tar2.len(), None),
+            ])
+            .to_string()
+            .into_bytes(),
+        ),
+        "/files/1.tar" => (200, tar1.clone()),
+        "/files/2.tar" => (200, tar2.clone()),
+        _ => (404, vec![]),
+    });
+    let dir = test_dir("mock_download_max_total_size");
+    let opts = DownloadOptions {
+        hash: false,
+        max_total_size: Some((size1 + size2 - 1) as u64),
+        ..options(&dir)
+    };
+
+    let client = asvo.client();
+    client.download_job(1, &opts).unwrap();
+    let result = client.download_job(2, &opts);
+    match result {
+        Err(AsvoError::MaxTotalSizeExceeded {
+            jobid: 2,
+            size,
+            used,
+            cap,
+        }) => {
+            assert_eq!(size, size2 as u64);
+            assert_eq!(used, size1 as u64);
+            assert_eq!(cap, (size1 + size2 - 1) as u64);
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // The skipped job wasn't requested.
+    assert!(asvo.requests("/files/2.tar").is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mock_download_obsid_prefer() {
+    let tars: Vec<Vec<u8>> = (1..=3)
+        .map(|i| make_tar(&[(&format!("{}.fits", i), b"data")]))
+        .collect();
+    let asvo = MockAsvo::start(move |address, request| {
+        let file = |i: usize| format!("/files/{}.tar", i);
+        match request.path.as_str() {
+            "/api/get_jobs" => {
+                let mut rows: Vec<_> = tars
+                    .iter()
+                    .enumerate()
+                    .map(|(i, tar)| {
+                        let sha1 = format!("{:x}", Sha1::digest(tar));
+                        job_row(
+                            i as AsvoJobID + 1,
+                            &format!("{}{}", address, file(i + 1)),
+                            tar.len(),
+                            Some(&sha1),
+                        )
+                    })
+                    .collect();
+                // The newest job isn't ready yet.
+                let mut queued = job_row(4, "", 0, None);
+                queued["row"]["job_state"] = 0.into();
+                queued["row"]["product"] = serde_json::Value::Null;
+                rows.push(queued);
+                (200, serde_json::Value::Array(rows).to_string().into_bytes())
+            }
+            path => match (1..=3).find(|&i| file(i) == path) {
+                Some(i) => (200, tars[i - 1].clone()),
+                None => (404, vec![]),
+            },
+        }
+    });
+    let dir = test_dir("mock_download_obsid_prefer");
+    let obsid = Obsid::validate(1065880128).unwrap();
+    let client = asvo.client();
+
+    let result = client.download_obsid(obsid, &options(&dir));
+    assert!(matches!(result, Err(AsvoError::TooManyObsids(o)) if o == obsid));
+
+    for (prefer, expected) in [
+        (JobPreference::Newest, "3.fits"),
+        (JobPreference::Oldest, "1.fits"),
+    ] {
+        let opts = DownloadOptions {
+            prefer,
+            ..options(&dir)
+        };
+        let download = client.download_obsid(obsid, &opts).unwrap();
+        assert_eq!(download.paths, vec![dir.join(expected)]);
+    }
+    assert!(!dir.join("2.fits").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -6,36 +6,70 @@
 
 mod asvo_serde;
 mod error;
+#[cfg(test)]
+mod mock;
 mod types;
 
 use asvo_serde::{parse_asvo_json, AsvoSubmitJobResponse};
 pub use error::AsvoError;
 pub use types::{
-    AsvoJob, AsvoJobID, AsvoJobMap, AsvoJobState, AsvoJobType, AsvoJobVec, Delivery, DeliveryFormat,
+    AsvoDownload, AsvoJob, AsvoJobDetails, AsvoJobField, AsvoJobID, AsvoJobMap, AsvoJobState,
+    AsvoJobType, AsvoJobVec, ClientOptions, ColourScheme, Delivery, DeliveryFormat,
+    DownloadOptions, JobPreference, JsonRecordError, OutputTemplate, ParsedJobs,
 };
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::env::{current_dir, var};
-use std::fs::{rename, File};
-use std::io::{BufRead, BufReader, Read, Write};
-use std::path::Path;
-use std::time::Instant;
-
-use backoff::{retry, Error, ExponentialBackoff};
+use std::fs::{rename, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use backoff::{retry, retry_notify, Error, ExponentialBackoff};
 use log::{debug, error, info, warn};
-use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_RANGE, RANGE};
+use reqwest::StatusCode;
 use sha1::{Digest, Sha1};
-use tar::Archive;
+use tar::{Archive, Entry};
 
 use crate::obsid::Obsid;
+use crate::{format_duration, format_rate};
 
 use self::types::AsvoFilesArray;
 
+/// The MWA ASVO host used unless `MWA_ASVO_HOST` is set.
+const DEFAULT_ASVO_HOST: &str = "asvo.mwatelescope.org:443";
+
+/// Progress is logged when at least this many bytes of a download are left to
+/// read after extraction.
+const DRAIN_PROGRESS_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// How often progress is logged while reading the rest of a download.
+const DRAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many times the job listing is checked again for the files of a job
+/// which is ready, but doesn't have its files listed yet.
+const MATERIALIZING_RETRIES: u32 = 5;
+
+/// A download is considered stalled if no bytes are received for this long,
+/// unless [DownloadOptions::stall_timeout] is set. This is the default
+/// (per-read) timeout of the HTTP client.
+const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a throughput sample is written to [DownloadOptions::perf_log].
+const PERF_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// With [DownloadOptions::verify_partial], up to this many bytes at each end
+/// of a partial download are downloaded again to check it.
+const VERIFY_PARTIAL_BYTES: u64 = 1024 * 1024;
+
 pub fn get_asvo_server_address() -> String {
     format!(
         "https://{}",
-        std::env::var("MWA_ASVO_HOST")
-            .unwrap_or_else(|_| String::from("asvo.mwatelescope.org:443"))
+        std::env::var("MWA_ASVO_HOST").unwrap_or_else(|_| String::from(DEFAULT_ASVO_HOST))
     )
 }
 
@@ -53,16 +87,148 @@ lazy_static::lazy_static! {
     };
 }
 
+/// A conversion job parameter known to giant-squid. The MWA ASVO may accept
+/// others; they're passed on as given.
+pub struct ConversionParameter {
+    /// The parameter's name, e.g. "avg_time_res".
+    pub key: &'static str,
+    /// What the parameter does.
+    pub description: &'static str,
+    /// The values the parameter accepts.
+    pub values: &'static str,
+}
+
+/// The conversion job parameters known to giant-squid. Their defaults, if
+/// any, are in [DEFAULT_CONVERSION_PARAMETERS].
+pub const KNOWN_CONVERSION_PARAMETERS: &[ConversionParameter] = &[
+    ConversionParameter {
+        key: "output",
+        description: "The format of the converted visibilities",
+        values: "uvfits or ms",
+    },
+    ConversionParameter {
+        key: "avg_time_res",
+        description: "The time resolution to average to [s]",
+        values: "a multiple of the observation's time resolution",
+    },
+    ConversionParameter {
+        key: "avg_freq_res",
+        description: "The frequency resolution to average to [kHz]",
+        values: "a multiple of the observation's fine channel width",
+    },
+    ConversionParameter {
+        key: "flag_edge_width",
+        description: "How much to flag at each edge of each coarse channel [kHz]",
+        values: "a multiple of the observation's fine channel width",
+    },
+];
+
 pub struct AsvoClient {
     /// The `reqwest` [Client] used to interface with the ASVO web service.
     client: Client,
+
+    /// The address of the ASVO web service, e.g.
+    /// "https://asvo.mwatelescope.org:443".
+    server: String,
+
+    /// The number of files whose downloads have failed transiently in a row,
+    /// shared across all downloads using this client.
+    consecutive_failures: AtomicU32,
+
+    /// Once the downloads of this many files in a row have failed
+    /// transiently, the ASVO is assumed to be unavailable and all downloads
+    /// are aborted.
+    max_consecutive_failures: u32,
+
+    /// Set when downloads should stop, e.g. because the user pressed Ctrl-C.
+    interrupted: Arc<AtomicBool>,
+
+    /// The maximum number of connections to each host, if limited.
+    max_connections_per_host: Option<usize>,
+
+    /// How long idle connections are kept, if not the default.
+    pool_idle_timeout: Option<Duration>,
+
+    /// The client used for downloads, with the stall timeout that it was
+    /// built with. See [AsvoClient::download_client].
+    download_client: Mutex<Option<(Duration, Client)>>,
+
+    /// Print an equivalent `curl` command for each ASVO API request.
+    print_requests: bool,
+
+    /// How long to wait before checking again for the files of a job which
+    /// is ready, but doesn't have its files listed yet.
+    materializing_delay: Duration,
+
+    /// The total size of the jobs being downloaded or already downloaded by
+    /// this client, counted against [DownloadOptions::max_total_size].
+    reserved_bytes: AtomicU64,
+
+    /// How many times a network operation which failed with a transient error
+    /// is retried. `None` keeps the default behaviour: downloads are retried
+    /// with an exponential backoff, but ASVO API requests aren't retried.
+    retries: Option<u32>,
 }
 
 impl AsvoClient {
     /// Get a new reqwest [Client] which has authenticated with the MWA ASVO.
     /// Uses the `MWA_ASVO_API_KEY` environment variable for login.
     pub fn new() -> Result<AsvoClient, AsvoError> {
+        Self::new_with_options(&ClientOptions::default())
+    }
+
+    /// As [AsvoClient::new], but with the HTTP connections configured by
+    /// `opts`.
+    pub fn new_with_options(opts: &ClientOptions) -> Result<AsvoClient, AsvoError> {
         let api_key = var("MWA_ASVO_API_KEY").map_err(|_| AsvoError::MissingAuthKey)?;
+        Self::new_with_api_key(&api_key, opts)
+    }
+
+    /// As [AsvoClient::new_with_options], but using `api_key` for login
+    /// rather than the `MWA_ASVO_API_KEY` environment variable.
+    pub fn new_with_api_key(api_key: &str, opts: &ClientOptions) -> Result<AsvoClient, AsvoError> {
+        let server = get_asvo_server_address();
+        if server != format!("https://{}", DEFAULT_ASVO_HOST) {
+            // Users permanently pointed at another host (e.g. a local mirror)
+            // can opt out of the warning.
+            if var("GIANT_SQUID_SUPPRESS_HOST_WARNING").is_ok() {
+                info!("Connecting to MWA ASVO host {}", server);
+            } else {
+                warn!(
+                    "Connecting to MWA ASVO non-default host {} (set GIANT_SQUID_SUPPRESS_HOST_WARNING to silence this warning)",
+                    server
+                );
+            }
+        }
+        Self::new_with_server_and_options(&server, api_key, opts)
+    }
+
+    /// Get a new reqwest [Client] which has authenticated with the ASVO web
+    /// service at `server` (e.g. "https://asvo.mwatelescope.org:443"), using
+    /// `api_key` for login. Useful for testing against a mock server.
+    pub fn new_with_server(server: &str, api_key: &str) -> Result<AsvoClient, AsvoError> {
+        Self::new_with_server_and_options(server, api_key, &ClientOptions::default())
+    }
+
+    /// As [AsvoClient::new_with_server], but with the HTTP connections
+    /// configured by `opts`.
+    pub fn new_with_server_and_options(
+        server: &str,
+        api_key: &str,
+        opts: &ClientOptions,
+    ) -> Result<AsvoClient, AsvoError> {
+        // How many consecutive transient download failures are tolerated
+        // before we give up on the ASVO?
+        let max_consecutive_failures = number_from_env("GIANT_SQUID_MAX_FAILURES")?.unwrap_or(10);
+        // How many times are failed network operations retried?
+        let retries = match opts.retries {
+            Some(r) => Some(r),
+            None => number_from_env("GIANT_SQUID_RETRIES")?,
+        };
+        let pool_idle_timeout = match opts.pool_idle_timeout {
+            Some(t) => Some(t),
+            None => number_from_env("GIANT_SQUID_POOL_IDLE_TIMEOUT")?.map(Duration::from_secs),
+        };
 
         // Interfacing with the ASVO server requires specifying the client
         // version. As this is not the manta-ray-client, we need to lie here.
@@ -71,102 +237,260 @@ impl AsvoClient {
             var("MWA_ASVO_VERSION").unwrap_or_else(|_| "mantaray-clientv1.2".to_string());
         // Connect and return the cookie jar.
         debug!("Connecting to ASVO...");
-        let client = ClientBuilder::new()
+        if let Some(max) = opts.max_connections_per_host {
+            debug!("Limiting connections to {} per host", max);
+        }
+        if let Some(timeout) = pool_idle_timeout {
+            debug!("Closing connections after {}s idle", timeout.as_secs_f64());
+        }
+        let client = client_builder(opts.max_connections_per_host, pool_idle_timeout)
             .cookie_store(true)
-            .connection_verbose(true)
-            .danger_accept_invalid_certs(true) // Required for the ASVO.
             .build()?;
-        let response = client
-            .post(format!("{}/api/api_login", get_asvo_server_address()))
-            .basic_auth(client_version, Some(&api_key))
-            .send()?;
-        if response.status().is_success() {
-            debug!("Successfully authenticated with ASVO");
-            Ok(AsvoClient { client })
-        } else {
-            Err(AsvoError::BadStatus {
-                code: response.status(),
-                message: response.text()?,
-            })
+        let login_url = format!("{}/api/api_login", server);
+        if opts.print_requests {
+            eprintln!(
+                "{}",
+                curl_command("POST", &login_url, Some(&client_version), &BTreeMap::new())
+            );
+        }
+        retry_request(retries, "logging in to the MWA ASVO", true, || {
+            let response = send(
+                client
+                    .post(&login_url)
+                    .basic_auth(&client_version, Some(api_key)),
+            )?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(AsvoError::BadStatus {
+                    code: response.status(),
+                    message: response.text()?,
+                })
+            }
+        })?;
+        debug!("Successfully authenticated with ASVO");
+        Ok(AsvoClient {
+            client,
+            server: server.to_string(),
+            consecutive_failures: AtomicU32::new(0),
+            max_consecutive_failures,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            max_connections_per_host: opts.max_connections_per_host,
+            pool_idle_timeout,
+            download_client: Mutex::new(None),
+            print_requests: opts.print_requests,
+            materializing_delay: Duration::from_secs(2),
+            reserved_bytes: AtomicU64::new(0),
+            retries,
+        })
+    }
+
+    /// Get the HTTP client for downloads with the given stall timeout. The
+    /// timeout of a blocking client applies to waiting for a response and to
+    /// each read of its body, not to the whole download (unlike a timeout set
+    /// on a request), so a stalled download is caught, but a slow one isn't.
+    /// The client is kept for the next download with the same timeout.
+    fn download_client(&self, stall_timeout: Duration) -> Result<Client, AsvoError> {
+        let mut cached = self.download_client.lock().expect("lock poisoned");
+        if let Some((timeout, client)) = cached.as_ref() {
+            if *timeout == stall_timeout {
+                return Ok(client.clone());
+            }
         }
+        let client = client_builder(self.max_connections_per_host, self.pool_idle_timeout)
+            .timeout(stall_timeout)
+            .build()?;
+        *cached = Some((stall_timeout, client.clone()));
+        Ok(client)
+    }
+
+    /// Get a flag that interrupts downloads when set (e.g. from a signal
+    /// handler). No new files are downloaded after it is set, and in-flight
+    /// downloads stop after their current write. A partially-extracted file is
+    /// removed, but a partially-downloaded archive is kept as a ".part" file,
+    /// to be resumed by the next download. Interrupted downloads return
+    /// [AsvoError::Interrupted].
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
     }
 
+    /// Get the job listing. Jobs which can't be parsed are skipped, with a
+    /// warning.
     pub fn get_jobs(&self) -> Result<AsvoJobVec, AsvoError> {
+        let parsed = self.get_jobs_with_errors()?;
+        for e in &parsed.errors {
+            warn!("{}", e);
+        }
+        Ok(parsed.jobs)
+    }
+
+    /// As [AsvoClient::get_jobs], but the records of the job listing which
+    /// couldn't be parsed are returned alongside the jobs, rather than being
+    /// logged.
+    pub fn get_jobs_with_errors(&self) -> Result<ParsedJobs, AsvoError> {
+        retry_request(self.retries, "getting the job listing", true, || {
+            self.get_jobs_once()
+        })
+    }
+
+    /// Make a single attempt at [AsvoClient::get_jobs_with_errors].
+    fn get_jobs_once(&self) -> Result<ParsedJobs, AsvoError> {
         debug!("Retrieving job statuses from the ASVO...");
         // Send a GET request to the ASVO.
-        let response = self
-            .client
-            .get(format!("{}/api/get_jobs", get_asvo_server_address()))
-            .send()?;
-        if !response.status().is_success() {
+        let url = format!("{}/api/get_jobs", self.server);
+        self.print_request("GET", &url, &BTreeMap::new());
+        let response = send(self.client.get(url))?;
+        let status = response.status();
+        let html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+            .is_some_and(|c| c.contains("html"));
+        let body = response.text()?;
+        // A proxy in front of the ASVO may serve an HTML error page when the
+        // ASVO is down. That's not worth trying to parse as JSON.
+        if html || body.trim_start().starts_with('<') {
+            return Err(AsvoError::ServerUnavailable {
+                status,
+                snippet: snippet(&body),
+            });
+        }
+        if !status.is_success() {
             return Err(AsvoError::BadStatus {
-                code: response.status(),
-                message: response.text()?,
+                code: status,
+                message: body,
             });
         }
 
-        let body = response.text()?;
         parse_asvo_json(&body).map_err(AsvoError::from)
     }
 
-    /// Download the specified ASVO job ID.
+    /// Download the specified ASVO job ID. Returns what was
+    /// downloaded.
     pub fn download_job(
         &self,
         jobid: AsvoJobID,
-        keep_tar: bool,
-        hash: bool,
-        download_dir: &str,
-    ) -> Result<(), AsvoError> {
-        let mut jobs = self.get_jobs()?;
+        opts: &DownloadOptions,
+    ) -> Result<AsvoDownload, AsvoError> {
+        let jobs = self.get_jobs()?;
         debug!("Attempting to download job {}", jobid);
-        // Filter all jobs but the one we're interested in.
-        jobs.0.retain(|j| j.jobid == jobid);
-        match jobs.0.len() {
-            0 => Err(AsvoError::NoAsvoJob(jobid)),
-            1 => self.download(&jobs.0[0], keep_tar, hash, download_dir),
-            // Hopefully there's never multiples of the same ASVO job ID in a
-            // user's job listing...
-            _ => unreachable!(),
-        }
+        self.download(find_job(&jobs, jobid)?, opts)
     }
 
     /// Download the job associated with an obsid. If more than one job is
-    /// associated with the obsid, we must abort, because we don't know which
-    /// job to download.
+    /// associated with the obsid, the ready job preferred by `opts.prefer` is
+    /// downloaded; by default, we must abort, because we don't know which job
+    /// to download. Returns what was downloaded.
     pub fn download_obsid(
         &self,
         obsid: Obsid,
-        keep_tar: bool,
-        hash: bool,
-        download_dir: &str,
-    ) -> Result<(), AsvoError> {
-        let mut jobs = self.get_jobs()?;
+        opts: &DownloadOptions,
+    ) -> Result<AsvoDownload, AsvoError> {
+        let jobs = self.get_jobs()?;
         debug!("Attempting to download obsid {}", obsid);
-        // Filter all ASVO jobs by obsid. If we don't have exactly one match, we
-        // have to bug out.
-        jobs.0.retain(|j| j.obsid == obsid);
-        match jobs.0.len() {
-            0 => Err(AsvoError::NoObsid(obsid)),
-            1 => self.download(&jobs.0[0], keep_tar, hash, download_dir),
-            _ => Err(AsvoError::TooManyObsids(obsid)),
-        }
+        let job = find_obsid_job(&jobs, obsid, opts.prefer, opts.delivery.as_deref())?;
+        self.download(job, opts)
     }
 
-    /// Private function to actually do the work.
-    fn download(
+    /// How many jobs may be downloaded at once with `opts`; that is,
+    /// `opts.concurrency`, limited by
+    /// [ClientOptions::max_connections_per_host]. Always at least 1.
+    pub fn download_concurrency(&self, opts: &DownloadOptions) -> usize {
+        let concurrency = match self.max_connections_per_host {
+            Some(max) => opts.concurrency.min(max),
+            None => opts.concurrency,
+        };
+        concurrency.max(1)
+    }
+
+    /// Download many ASVO jobs, fetching the job listing only once. Up to
+    /// `opts.concurrency` jobs are downloaded at once, further limited by
+    /// [ClientOptions::max_connections_per_host]. Returns the result of
+    /// each download, in the same order as `jobids`, or an error if the job
+    /// listing couldn't be fetched.
+    pub fn download_jobs(
         &self,
-        job: &AsvoJob,
-        keep_tar: bool,
-        hash: bool,
-        download_dir: &str,
-    ) -> Result<(), AsvoError> {
-        // Is the job ready to download?
-        if job.state != AsvoJobState::Ready {
-            return Err(AsvoError::NotReady {
-                jobid: job.jobid,
-                state: job.state.clone(),
-            });
-        }
+        jobids: &[AsvoJobID],
+        opts: &DownloadOptions,
+    ) -> Result<Vec<Result<AsvoDownload, AsvoError>>, AsvoError> {
+        let jobs = self.get_jobs()?.into_map();
+        let download_one = |jobid: &AsvoJobID| match jobs.0.get(jobid) {
+            None => Err(AsvoError::NoAsvoJob(*jobid)),
+            Some(job) => self.download(job, opts),
+        };
+
+        let concurrency = self.download_concurrency(opts);
+        let next = AtomicUsize::new(0);
+        let mut results = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..concurrency.clamp(1, jobids.len().max(1)))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut results = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            match jobids.get(i) {
+                                Some(jobid) => results.push((i, download_one(jobid))),
+                                None => break results,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("download thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        results.sort_by_key(|(i, _)| *i);
+        Ok(results.into_iter().map(|(_, r)| r).collect())
+    }
+
+    /// Estimate the number of bytes that downloading the given jobs and obsids
+    /// would fetch, fetching the job listing only once. See
+    /// [AsvoClient::download_sizes].
+    pub fn estimate_download_size(
+        &self,
+        jobids: &[AsvoJobID],
+        obsids: &[Obsid],
+        opts: &DownloadOptions,
+    ) -> Result<u64, AsvoError> {
+        let sizes = self.download_sizes(jobids, obsids, opts)?;
+        Ok(sizes.iter().map(|(_, _, size)| size).sum())
+    }
+
+    /// Get the size of each of the given jobs' files, as (job ID, obsid,
+    /// bytes), fetching the job listing only once. Obsids are resolved to jobs
+    /// as they would be by [AsvoClient::download_obsid]. Only ready jobs have
+    /// files to download, so other jobs are skipped, with a warning.
+    pub fn download_sizes(
+        &self,
+        jobids: &[AsvoJobID],
+        obsids: &[Obsid],
+        opts: &DownloadOptions,
+    ) -> Result<Vec<(AsvoJobID, Obsid, u64)>, AsvoError> {
+        let jobs = self.get_jobs()?;
+        ready_job_sizes(&jobs, jobids, obsids, opts)
+    }
+
+    /// Private function to actually do the work.
+    fn download(&self, job: &AsvoJob, opts: &DownloadOptions) -> Result<AsvoDownload, AsvoError> {
+        check_ready(job)?;
+
+        // A job's files may not be listed until shortly after it becomes
+        // ready; give them a chance to turn up. If they never do, there's
+        // something wrong with the ASVO.
+        let refreshed;
+        let job = if files_missing(job) {
+            refreshed = self.wait_for_files(job)?;
+            if refreshed.files.as_ref().map_or(true, |f| f.is_empty()) {
+                return Err(AsvoError::ReadyButNoFiles {
+                    jobid: refreshed.jobid,
+                });
+            }
+            &refreshed
+        } else {
+            job
+        };
 
         // Handle any silly cases.
         let files = match &job.files {
@@ -179,7 +503,41 @@ impl AsvoClient {
             }
         };
 
-        let total_bytes = files.iter().map(|f| f.size).sum();
+        let total_bytes = job.total_size();
+        // Don't start a download that would take the total size of this
+        // client's downloads over the cap.
+        if let Some(cap) = opts.max_total_size {
+            let reserved =
+                self.reserved_bytes
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                        Some(used + total_bytes).filter(|&t| t <= cap)
+                    });
+            if let Err(used) = reserved {
+                return Err(AsvoError::MaxTotalSizeExceeded {
+                    jobid: job.jobid,
+                    size: total_bytes,
+                    used,
+                    cap,
+                });
+            }
+        }
+        let result = self.download_files(job, files, total_bytes, opts);
+        if result.is_err() && opts.max_total_size.is_some() {
+            // Nothing was kept, so the job no longer counts towards the cap.
+            self.reserved_bytes
+                .fetch_sub(total_bytes, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Download each of a ready job's files.
+    fn download_files(
+        &self,
+        job: &AsvoJob,
+        files: &[AsvoFilesArray],
+        total_bytes: u64,
+        opts: &DownloadOptions,
+    ) -> Result<AsvoDownload, AsvoError> {
         info!(
             "Downloading ASVO job ID {} (obsid: {}, type: {}, {})",
             job.jobid,
@@ -188,91 +546,235 @@ impl AsvoClient {
             bytesize::ByteSize(total_bytes).to_string_as(true)
         );
         let start_time = Instant::now();
+        let mut download = AsvoDownload {
+            jobid: job.jobid,
+            obsid: Some(job.obsid),
+            ..Default::default()
+        };
+
+        // Download each file. One file failing doesn't stop the others from
+        // being downloaded; the failures are reported at the end.
+        let mut failed = vec![];
+        for (i, f) in files.iter().enumerate() {
+            if self.interrupted.load(Ordering::Relaxed) {
+                return Err(AsvoError::Interrupted);
+            }
+            match self.download_file(job, f, opts, &mut download, start_time, total_bytes) {
+                Ok(()) => (),
+                // Don't bother with the other files if they can't be
+                // downloaded either.
+                Err(
+                    e @ (AsvoError::Interrupted
+                    | AsvoError::Unavailable(_)
+                    | AsvoError::JobTimeout { .. }),
+                ) => return Err(e),
+                Err(e) if files.len() == 1 => return Err(e),
+                Err(e) => {
+                    let name = f
+                        .output_filename()
+                        .unwrap_or_else(|_| format!("file {}", i + 1));
+                    warn!(
+                        "Couldn't download {} of ASVO job ID {}: {}; continuing with the job's other files",
+                        name, job.jobid, e
+                    );
+                    failed.push((name, e));
+                }
+            }
+        }
+        if !failed.is_empty() {
+            return Err(AsvoError::FilesFailed {
+                jobid: job.jobid,
+                total: files.len(),
+                failed,
+            });
+        }
+
+        if opts.save_job_json {
+            let path = Path::new(&opts.download_dir).join(format!("{}.json", job.jobid));
+            info!(
+                "Saving the record of ASVO job ID {} to {:?}",
+                job.jobid, path
+            );
+            // Prefer the record exactly as the ASVO gave it.
+            let json = match &job.details.raw {
+                Some(raw) => {
+                    serde_json::to_string_pretty(&serde_json::from_str::<serde_json::Value>(raw)?)?
+                }
+                None => serde_json::to_string_pretty(job)?,
+            };
+            std::fs::write(&path, json)?;
+        }
+
+        Ok(download)
+    }
 
-        // Download each file.
-        for f in files {
-            match f.r#type {
-                Delivery::Acacia => match f.url.as_deref() {
-                    Some(url) => {
-                        debug!("Downloading file {:?}", &url);
-
-                        let op = || {
-                            self.try_download(url, keep_tar, hash, f, job, download_dir)
-                                .map_err(|e| match &e {
-                                    &AsvoError::IO(_) => Error::permanent(e),
-                                    _ => Error::transient(e),
-                                })
-                        };
-
-                        if let Err(Error::Permanent(err)) = retry(ExponentialBackoff::default(), op)
-                        {
-                            return Err(err);
+    /// Download one of a job's files, adding it to `download`.
+    fn download_file(
+        &self,
+        job: &AsvoJob,
+        f: &AsvoFilesArray,
+        opts: &DownloadOptions,
+        download: &mut AsvoDownload,
+        start_time: Instant,
+        total_bytes: u64,
+    ) -> Result<(), AsvoError> {
+        match &f.r#type {
+            Delivery::Acacia => match f.url.as_deref() {
+                Some(url) => {
+                    debug!("Downloading file {:?}", &url);
+                    // The URL is presigned, and may expire before the
+                    // download is retried; it is then replaced with a
+                    // fresh URL from the job listing.
+                    let mut url = url.to_string();
+                    let mut attempts = 0;
+                    // Only the first failure of this file counts towards the
+                    // failures shared by all downloads; a single flaky file
+                    // shouldn't make the ASVO look unavailable.
+                    let mut failed_before = false;
+                    let deadline = opts.job_timeout.map(|t| start_time + t);
+
+                    let op = || {
+                        // If other downloads have already given up on the
+                        // ASVO, don't bother trying.
+                        let failures = self.consecutive_failures.load(Ordering::Relaxed);
+                        if failures >= self.max_consecutive_failures {
+                            return Err(Error::permanent(AsvoError::Unavailable(failures)));
                         }
 
-                        info!(
-                            "Completed download in {} (average rate: {}/s)",
-                            if start_time.elapsed().as_secs() > 60 {
-                                format!(
-                                    "{}min{:.2}s",
-                                    start_time.elapsed().as_secs() / 60,
-                                    (start_time.elapsed().as_millis() as f64 / 1e3) % 60.0
-                                )
-                            } else {
-                                format!("{}s", start_time.elapsed().as_millis() as f64 / 1e3)
-                            },
-                            bytesize::ByteSize(
-                                (total_bytes as u128 * 1000 / start_time.elapsed().as_millis())
-                                    as u64
-                            )
-                            .to_string_as(true)
-                        );
-                    }
-                    None => return Err(AsvoError::NoUrl { job_id: job.jobid }),
-                },
-                Delivery::Scratch => {
-                    match &f.path {
-                        Some(path) => {
-                            //If it's a /scratch job, and the files are reachable from the current host, move them into the current working directory
-                            let path_obj = Path::new(&path);
-                            let folder_name = path_obj
-                                .components()
-                                .last()
-                                .unwrap()
-                                .as_os_str()
-                                .to_str()
-                                .unwrap();
-
-                            if !Path::exists(path_obj) {
-                                info!(
-                                    "Files for Job {} are not reachable from the current host.",
-                                    job.jobid
-                                );
-                            } else {
-                                info!("Files for Job {} are reachable from the current host. Copying to current directory.", job.jobid);
-
-                                let mut current_path = current_dir()?;
-                                current_path.push(folder_name);
-                                rename(path, current_path)?;
+                        if deadline.is_some_and(|d| Instant::now() >= d) {
+                            return Err(Error::permanent(job_timeout(job, opts)));
+                        }
+
+                        attempts += 1;
+                        match self.try_download_until(&url, f, job, opts, deadline) {
+                            Ok(downloaded) => {
+                                self.consecutive_failures.store(0, Ordering::Relaxed);
+                                Ok(downloaded)
                             }
+                            Err(
+                                e @ (AsvoError::IO(_)
+                                | AsvoError::ProxyAuthRequired
+                                | AsvoError::NoFilename(_)
+                                | AsvoError::NoHash(_)
+                                | AsvoError::FlattenCollision { .. }
+                                | AsvoError::EmptyDownload { .. }
+                                | AsvoError::JobTimeout { .. }
+                                | AsvoError::Interrupted),
+                            ) => Err(Error::permanent(e)),
+                            Err(e) => {
+                                let failures = if failed_before {
+                                    self.consecutive_failures.load(Ordering::Relaxed)
+                                } else {
+                                    failed_before = true;
+                                    self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1
+                                };
+                                if failures >= self.max_consecutive_failures {
+                                    error!("Last error: {}", e);
+                                    return Err(Error::permanent(AsvoError::Unavailable(failures)));
+                                }
+                                if self.retries.is_some_and(|r| attempts > r) {
+                                    return Err(Error::permanent(e));
+                                }
+                                if let AsvoError::UrlExpired { .. } = e {
+                                    warn!("{}; getting a fresh URL from the job listing", e);
+                                    url = self.refresh_url(job, f).map_err(Error::permanent)?;
+                                }
+                                Err(Error::transient(e))
+                            }
+                        }
+                    };
+
+                    match retry(ExponentialBackoff::default(), op) {
+                        Ok((mut paths, mut checksums)) => {
+                            download.paths.append(&mut paths);
+                            download.checksums.append(&mut checksums);
+                        }
+                        Err(Error::Permanent(err)) | Err(Error::Transient { err, .. }) => {
+                            return Err(err)
+                        }
+                    }
+
+                    download.bytes += f.size;
+                    info!(
+                        "Completed download in {} (average rate: {})",
+                        format_duration(start_time.elapsed()),
+                        format_rate(total_bytes, start_time.elapsed())
+                    );
+                }
+                None => return Err(AsvoError::NoUrl { job_id: job.jobid }),
+            },
+            Delivery::Scratch => {
+                match &f.path {
+                    Some(path) => {
+                        //If it's a /scratch job, and the files are reachable from the current host, move them into the current working directory
+                        let path_obj = Path::new(&path);
+                        let folder_name = f.output_filename()?;
+
+                        if !Path::exists(path_obj) {
+                            info!(
+                                "Files for Job {} are not reachable from the current host.",
+                                job.jobid
+                            );
+                        } else {
+                            info!("Files for Job {} are reachable from the current host. Copying to current directory.", job.jobid);
+
+                            let mut current_path = current_dir()?;
+                            current_path.push(folder_name);
+                            rename(path, &current_path)?;
+                            download.bytes += f.size;
+                            download.paths.push(current_path);
                         }
-                        None => return Err(AsvoError::NoPath { job_id: job.jobid }),
                     }
+                    None => return Err(AsvoError::NoPath { job_id: job.jobid }),
                 }
             }
+            Delivery::Other(d) => {
+                return Err(AsvoError::UnsupportedDelivery {
+                    job_id: job.jobid,
+                    delivery: d.clone(),
+                })
+            }
         }
-
         Ok(())
     }
 
+    /// Download (and, unless keeping archives, extract) one of a job's files,
+    /// returning the local paths and, if
+    /// [`DownloadOptions::file_checksums`] is set, the SHA-1 hashes of the
+    /// written files.
+    #[allow(clippy::type_complexity)]
     pub fn try_download(
         &self,
         url: &str,
-        keep_tar: bool,
-        hash: bool,
         f: &AsvoFilesArray,
         job: &AsvoJob,
-        download_dir: &str,
-    ) -> Result<(), AsvoError> {
+        opts: &DownloadOptions,
+    ) -> Result<(Vec<PathBuf>, Vec<(PathBuf, String)>), AsvoError> {
+        self.try_download_until(url, f, job, opts, None)
+    }
+
+    /// As [AsvoClient::try_download], but the download is abandoned with
+    /// [AsvoError::JobTimeout] if it's still going at `deadline`.
+    #[allow(clippy::type_complexity)]
+    fn try_download_until(
+        &self,
+        url: &str,
+        f: &AsvoFilesArray,
+        job: &AsvoJob,
+        opts: &DownloadOptions,
+        deadline: Option<Instant>,
+    ) -> Result<(Vec<PathBuf>, Vec<(PathBuf, String)>), AsvoError> {
+        let DownloadOptions {
+            keep_tar,
+            hash,
+            flatten,
+            preserve_mtime,
+            preserve_permissions,
+            file_checksums,
+            ref output_template,
+            ref download_dir,
+            ..
+        } = *opts;
         // How big should our in-memory download buffer be [MiB]?
         let buffer_size = match var("GIANT_SQUID_BUF_SIZE") {
             Ok(s) => s.parse()?,
@@ -280,73 +782,377 @@ impl AsvoClient {
         } * 1024
             * 1024;
 
-        // parse out path from url
-        let url_obj = reqwest::Url::parse(url).unwrap();
-        let out_path = Path::new(url_obj.path_segments().unwrap().last().unwrap());
-
-        let response = self.client.get(url).send()?;
+        // If we were told to hash the download, there must be an upstream hash
+        // to compare against. Check this before wasting a download.
+        if hash && f.sha1.is_none() {
+            return Err(AsvoError::NoHash(job.jobid));
+        }
 
-        let mut tee = tee_readwrite::TeeReader::new(response, Sha1::new(), false);
+        // Get the output file name from the url.
+        let out_name = f.output_filename()?;
+        let out_path = match output_template {
+            Some(t) => {
+                Path::new(download_dir).join(t.render(job.obsid, job.jobid, Path::new(&out_name)))
+            }
+            None => Path::new(download_dir).join(&out_name),
+        };
+        info!(
+            "Downloading archive {} of ASVO job ID {}",
+            out_name, job.jobid
+        );
+        // Archives are written to a ".part" file, which is only renamed once
+        // the download is complete (and verified), so an interrupted download
+        // can be resumed. The server's ETag for the archive is kept alongside,
+        // so that we can check that the partial download is of the same file.
+        let part_path = PathBuf::from(format!("{}.part", out_path.display()));
+        let etag_path = PathBuf::from(format!("{}.part.etag", out_path.display()));
+
+        // Each read (including waiting for the response) times out if no
+        // bytes are received in time, so a stalled download is caught, but a
+        // slow one isn't. See [AsvoClient::download_client].
+        let stall_timeout = opts.stall_timeout.unwrap_or(DEFAULT_STALL_TIMEOUT);
+        let read_error = |e: io::Error| {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                job_timeout(job, opts)
+            } else if is_stall(&e) {
+                AsvoError::Stalled {
+                    jobid: job.jobid,
+                    file: out_name.clone(),
+                    timeout: stall_timeout,
+                }
+            } else {
+                self.interrupted_or(e)
+            }
+        };
 
+        let mut hasher = Sha1::new();
+        let mut request = self.download_client(stall_timeout)?.get(url);
+        let mut resume_from = 0;
         if keep_tar {
+            if let Some(parent) = out_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            resume_from = std::fs::metadata(&part_path).map_or(0, |m| m.len());
+            if resume_from >= f.size {
+                // The partial download can't be right; start again.
+                resume_from = 0;
+            }
+            if resume_from > 0
+                && opts.verify_partial
+                && !self.verify_partial(url, job, &part_path, resume_from, stall_timeout)?
+            {
+                warn!(
+                    "{:?} doesn't match the archive on the server; downloading it again",
+                    part_path
+                );
+                resume_from = 0;
+            }
+            if resume_from > 0 {
+                info!("Resuming {:?} from byte {}", part_path, resume_from);
+                // Hash the bytes we already have, so that the final hash
+                // covers the whole archive.
+                std::io::copy(&mut File::open(&part_path)?, &mut hasher)?;
+                request = request.header(RANGE, format!("bytes={}-", resume_from));
+                match std::fs::read_to_string(&etag_path) {
+                    Ok(etag) => request = request.header(IF_RANGE, etag.trim()),
+                    Err(_) => warn!(
+                        "Can't check that {:?} is a part of the same archive; it will only be verified once the download is complete",
+                        part_path
+                    ),
+                }
+            }
+        }
+
+        let response = send(request)?;
+        if response.status() == StatusCode::FORBIDDEN {
+            // Leave any partial download to be resumed with a fresh URL.
+            return Err(AsvoError::UrlExpired { jobid: job.jobid });
+        }
+        let response = response.error_for_status()?;
+        let resuming = response.status() == StatusCode::PARTIAL_CONTENT;
+        // The file name is taken from the URL, but the server may name the
+        // archive differently (e.g. if it was renamed since a previous run).
+        // Don't append to a partial download of a differently-named archive.
+        let served_name = response
+            .headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_disposition_filename);
+        if let Some(served_name) = served_name.filter(|n| *n != out_name) {
+            if resuming {
+                let _ = std::fs::remove_file(&part_path);
+                let _ = std::fs::remove_file(&etag_path);
+                return Err(AsvoError::ArchiveNameMismatch {
+                    jobid: job.jobid,
+                    expected: out_name,
+                    actual: served_name,
+                });
+            }
+            warn!(
+                "The MWA ASVO named the archive for ASVO job ID {} {}, but it's being saved as {}",
+                job.jobid, served_name, out_name
+            );
+        }
+        if resume_from > 0 && !resuming {
+            info!("Couldn't resume {:?}; downloading it again", part_path);
+            hasher = Sha1::new();
+        }
+        if keep_tar && !resuming {
+            match response.headers().get(ETAG) {
+                Some(etag) => std::fs::write(&etag_path, etag.as_bytes())?,
+                None => {
+                    let _ = std::fs::remove_file(&etag_path);
+                }
+            }
+        }
+        let response = InterruptibleReader {
+            inner: response,
+            interrupted: &self.interrupted,
+            deadline,
+        };
+        let perf_log = match &opts.perf_log {
+            Some(path) => Some(open_perf_log(path)?),
+            None => None,
+        };
+        let response = SamplingReader {
+            inner: response,
+            log: perf_log,
+            jobid: job.jobid,
+            file: &out_name,
+            interval: PERF_SAMPLE_INTERVAL,
+            last_sample: Instant::now(),
+            bytes: 0,
+        };
+        let mut tee = CountingReader {
+            inner: tee_readwrite::TeeReader::new(response, hasher, false),
+            count: if resuming { resume_from } else { 0 },
+        };
+
+        let mut checksums = vec![];
+        let paths = if keep_tar {
             // Simply dump the response to the appropriate file name. Use a
             // buffer to avoid doing frequent writes.
 
             info!("Writing archive to {:?}", out_path);
 
-            let mut out_file = File::create(out_path)?;
+            let mut out_file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(resuming)
+                .truncate(!resuming)
+                .open(&part_path)?;
             let mut file_buf = BufReader::with_capacity(buffer_size, tee.by_ref());
 
-            loop {
-                let buffer = file_buf.fill_buf()?;
-                out_file.write_all(buffer)?;
+            let result = loop {
+                let buffer = match file_buf.fill_buf() {
+                    Ok(b) => b,
+                    Err(e) => break Err(e),
+                };
+                if let Err(e) = out_file.write_all(buffer) {
+                    break Err(e);
+                }
 
                 let length = buffer.len();
                 file_buf.consume(length);
                 if length == 0 {
-                    break;
+                    break Ok(());
                 }
+            };
+            if let Err(e) = result {
+                // Leave the partial download to be resumed later.
+                return Err(read_error(e));
             }
+            vec![out_path.clone()]
         } else {
             // Stream-untar the response.
             let unpack_path = Path::new(download_dir);
             info!("Untarring to {:?}", unpack_path);
-            let mut tar = Archive::new(&mut tee);
-            tar.set_preserve_mtime(false);
-            tar.unpack(unpack_path)?;
-        }
+            unpack_archive(
+                &mut tee,
+                unpack_path,
+                flatten,
+                preserve_mtime,
+                preserve_permissions,
+                output_template.as_ref(),
+                job,
+                file_checksums.then_some(&mut checksums),
+            )
+            .map_err(|e| match e {
+                AsvoError::IO(e) => read_error(e),
+                e => e,
+            })?
+        };
 
         // If we were told to hash the download, compare our hash against
         // the upstream hash. Stream untarring may not read all of the
         // bytes; read the tee to the end.
-        {
-            let mut final_bytes = vec![];
-            tee.read_to_end(&mut final_bytes)?;
-        }
-
-        if hash {
-            match &f.sha1 {
-                Some(sha) => {
-                    debug!("Upstream hash: {}", sha);
-                    let (_, hasher) = tee.into_inner();
-                    let hash = format!("{:x}", hasher.finalize());
-                    debug!("Our hash: {}", &hash);
-                    if !hash.eq_ignore_ascii_case(sha) {
-                        return Err(AsvoError::HashMismatch {
-                            jobid: job.jobid,
-                            file: url.to_string(),
-                            calculated_hash: hash,
-                            expected_hash: sha.to_string(),
-                        });
-                    }
-                }
-                _ => {
-                    panic!("Product does not include a hash to compare.")
+        drain(&mut tee, f.size, &out_name).map_err(read_error)?;
+
+        // A truncated (or overlong) archive is cheaply caught by its size.
+        let count = tee.count;
+        let (_, hasher) = tee.inner.into_inner();
+        if f.size > 0 && count != f.size {
+            if keep_tar && count > f.size {
+                // Don't resume from a corrupt archive.
+                let _ = std::fs::remove_file(&part_path);
+                let _ = std::fs::remove_file(&etag_path);
+            }
+            return Err(AsvoError::SizeMismatch {
+                jobid: job.jobid,
+                file: url.to_string(),
+                expected: f.size,
+                actual: count,
+            });
+        }
+
+        // The archive's hash is also wanted for checksums of kept archives.
+        let our_hash =
+            (hash || (keep_tar && file_checksums)).then(|| format!("{:x}", hasher.finalize()));
+        if let (true, Some(sha), Some(our_hash)) = (hash, &f.sha1, &our_hash) {
+            debug!("Upstream hash: {}", sha);
+            debug!("Our hash: {}", our_hash);
+            if !our_hash.eq_ignore_ascii_case(sha) {
+                if keep_tar {
+                    // Don't resume from a corrupt archive.
+                    let _ = std::fs::remove_file(&part_path);
+                    let _ = std::fs::remove_file(&etag_path);
                 }
+                return Err(AsvoError::HashMismatch {
+                    jobid: job.jobid,
+                    file: url.to_string(),
+                    calculated_hash: our_hash.clone(),
+                    expected_hash: sha.to_string(),
+                });
             }
         }
 
-        Ok(())
+        // An archive without any files is almost certainly an upstream
+        // problem; don't let it pass as a successful download.
+        if !keep_tar && paths.is_empty() {
+            return Err(AsvoError::EmptyDownload { jobid: job.jobid });
+        }
+
+        if keep_tar {
+            rename(&part_path, &out_path)?;
+            let _ = std::fs::remove_file(&etag_path);
+            if let (true, Some(our_hash)) = (file_checksums, our_hash) {
+                checksums.push((out_path, our_hash));
+            }
+        }
+
+        Ok((paths, checksums))
+    }
+
+    /// Check that a partial download of `len` bytes matches the archive at
+    /// `url`, by downloading its first and last (up to)
+    /// [VERIFY_PARTIAL_BYTES] bytes again. The ASVO doesn't provide hashes of
+    /// parts of an archive, so this is the best we can do without downloading
+    /// the whole partial again. Returns false if the bytes differ, or if the
+    /// server won't serve them as a byte range.
+    fn verify_partial(
+        &self,
+        url: &str,
+        job: &AsvoJob,
+        part_path: &Path,
+        len: u64,
+        timeout: Duration,
+    ) -> Result<bool, AsvoError> {
+        let n = len.min(VERIFY_PARTIAL_BYTES);
+        let mut starts = vec![0];
+        if len > n {
+            starts.push(len - n);
+        }
+        let mut part = File::open(part_path)?;
+        for start in starts {
+            debug!(
+                "Checking bytes {}-{} of {:?} against the server",
+                start,
+                start + n - 1,
+                part_path
+            );
+            let mut ours = vec![0; n as usize];
+            part.seek(SeekFrom::Start(start))?;
+            part.read_exact(&mut ours)?;
+            let request = self
+                .download_client(timeout)?
+                .get(url)
+                .header(RANGE, format!("bytes={}-{}", start, start + n - 1));
+            let mut response = send(request)?;
+            match response.status() {
+                StatusCode::PARTIAL_CONTENT => (),
+                StatusCode::FORBIDDEN => return Err(AsvoError::UrlExpired { jobid: job.jobid }),
+                _ => return Ok(false),
+            }
+            // Read (rather than getting the bytes all at once), so that the
+            // timeout applies to each read.
+            let mut theirs = Vec::with_capacity(ours.len());
+            response.read_to_end(&mut theirs)?;
+            if theirs != ours {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Get a fresh URL for a job's file from the job listing, e.g. because
+    /// its presigned URL has expired.
+    fn refresh_url(&self, job: &AsvoJob, f: &AsvoFilesArray) -> Result<String, AsvoError> {
+        let name = f.output_filename()?;
+        let job = self
+            .get_jobs()?
+            .0
+            .into_iter()
+            .find(|j| j.jobid == job.jobid)
+            .ok_or(AsvoError::NoAsvoJob(job.jobid))?;
+        for f in job.files.unwrap_or_default() {
+            if f.output_filename()? == name {
+                return f.url.ok_or(AsvoError::NoUrl { job_id: job.jobid });
+            }
+        }
+        Err(AsvoError::NoFiles(job.jobid))
+    }
+
+    /// Check the job listing again (a few times, with a delay) for a ready
+    /// job which doesn't have all of its files listed. Returns the last
+    /// listing of the job, whether or not its files turned up.
+    fn wait_for_files(&self, job: &AsvoJob) -> Result<AsvoJob, AsvoError> {
+        let jobid = job.jobid;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            info!(
+                "ASVO job ID {} is ready, but its files aren't listed yet; checking again ({}/{})",
+                jobid, attempt, MATERIALIZING_RETRIES
+            );
+            std::thread::sleep(self.materializing_delay);
+            let job = self
+                .get_jobs()?
+                .0
+                .into_iter()
+                .find(|j| j.jobid == jobid)
+                .ok_or(AsvoError::NoAsvoJob(jobid))?;
+            if !files_missing(&job) || attempt >= MATERIALIZING_RETRIES {
+                return Ok(job);
+            }
+        }
+    }
+
+    /// Print an equivalent `curl` command for an ASVO API request, if
+    /// requested.
+    fn print_request(&self, method: &str, url: &str, form: &BTreeMap<&str, &str>) {
+        if self.print_requests {
+            eprintln!("{}", curl_command(method, url, None, form));
+        }
+    }
+
+    /// If downloads have been interrupted, an IO error is most likely due to
+    /// the interruption; report it as such.
+    fn interrupted_or(&self, e: std::io::Error) -> AsvoError {
+        if self.interrupted.load(Ordering::Relaxed) {
+            AsvoError::Interrupted
+        } else {
+            AsvoError::IO(e)
+        }
     }
 
     /// Submit an ASVO job for visibility download.
@@ -356,6 +1162,25 @@ impl AsvoClient {
         delivery: Delivery,
         delivery_format: Option<DeliveryFormat>,
         allow_resubmit: bool,
+    ) -> Result<Option<AsvoJobID>, AsvoError> {
+        self.submit_vis_with_extra(
+            obsid,
+            delivery,
+            delivery_format,
+            allow_resubmit,
+            &BTreeMap::new(),
+        )
+    }
+
+    /// As [AsvoClient::submit_vis], but `extra_fields` are also added to
+    /// the submission, overriding any known fields with the same names.
+    pub fn submit_vis_with_extra(
+        &self,
+        obsid: Obsid,
+        delivery: Delivery,
+        delivery_format: Option<DeliveryFormat>,
+        allow_resubmit: bool,
+        extra_fields: &BTreeMap<&str, &str>,
     ) -> Result<Option<AsvoJobID>, AsvoError> {
         debug!("Submitting a vis job to ASVO");
 
@@ -375,6 +1200,7 @@ impl AsvoClient {
 
         form.insert("download_type", "vis");
         form.insert("allow_resubmit", &allow_resubmit_str);
+        insert_extra_fields(&mut form, extra_fields);
         self.submit_asvo_job(&AsvoJobType::DownloadVisibilities, form)
     }
 
@@ -389,6 +1215,32 @@ impl AsvoClient {
         from_channel: Option<i32>,
         to_channel: Option<i32>,
         allow_resubmit: bool,
+    ) -> Result<Option<AsvoJobID>, AsvoError> {
+        self.submit_volt_with_extra(
+            obsid,
+            delivery,
+            offset,
+            duration,
+            from_channel,
+            to_channel,
+            allow_resubmit,
+            &BTreeMap::new(),
+        )
+    }
+
+    /// As [AsvoClient::submit_volt], but `extra_fields` are also added to
+    /// the submission, overriding any known fields with the same names.
+    #[allow(clippy::too_many_arguments)]
+    pub fn submit_volt_with_extra(
+        &self,
+        obsid: Obsid,
+        delivery: Delivery,
+        offset: i32,
+        duration: i32,
+        from_channel: Option<i32>,
+        to_channel: Option<i32>,
+        allow_resubmit: bool,
+        extra_fields: &BTreeMap<&str, &str>,
     ) -> Result<Option<AsvoJobID>, AsvoError> {
         debug!("Submitting a voltage job to ASVO");
 
@@ -424,10 +1276,12 @@ impl AsvoClient {
 
         form.insert("download_type", "volt");
         form.insert("allow_resubmit", &allow_resubmit_str);
+        insert_extra_fields(&mut form, extra_fields);
         self.submit_asvo_job(&AsvoJobType::DownloadVoltage, form)
     }
 
-    /// Submit an ASVO job for conversion.
+    /// Submit an ASVO job for conversion. [DEFAULT_CONVERSION_PARAMETERS] are
+    /// used for any conversion parameters not in `parameters`.
     pub fn submit_conv(
         &self,
         obsid: Obsid,
@@ -435,6 +1289,32 @@ impl AsvoClient {
         delivery_format: Option<DeliveryFormat>,
         parameters: &BTreeMap<&str, &str>,
         allow_resubmit: bool,
+    ) -> Result<Option<AsvoJobID>, AsvoError> {
+        self.submit_conv_with_extra(
+            obsid,
+            delivery,
+            delivery_format,
+            parameters,
+            true,
+            allow_resubmit,
+            &BTreeMap::new(),
+        )
+    }
+
+    /// As [AsvoClient::submit_conv], but `extra_fields` are also added to
+    /// the submission, overriding any known fields with the same names. If
+    /// `use_defaults` isn't set, [DEFAULT_CONVERSION_PARAMETERS] aren't used;
+    /// only `parameters` are sent.
+    #[allow(clippy::too_many_arguments)]
+    pub fn submit_conv_with_extra(
+        &self,
+        obsid: Obsid,
+        delivery: Delivery,
+        delivery_format: Option<DeliveryFormat>,
+        parameters: &BTreeMap<&str, &str>,
+        use_defaults: bool,
+        allow_resubmit: bool,
+        extra_fields: &BTreeMap<&str, &str>,
     ) -> Result<Option<AsvoJobID>, AsvoError> {
         debug!("Submitting a conversion job to ASVO");
 
@@ -445,8 +1325,10 @@ impl AsvoClient {
 
         let mut form = BTreeMap::new();
         form.insert("obs_id", obsid_str.as_str());
-        for (&k, &v) in DEFAULT_CONVERSION_PARAMETERS.iter() {
-            form.insert(k, v);
+        if use_defaults {
+            for (&k, &v) in DEFAULT_CONVERSION_PARAMETERS.iter() {
+                form.insert(k, v);
+            }
         }
 
         // Add the user's conversion parameters. If the user has specified an
@@ -465,6 +1347,7 @@ impl AsvoClient {
         }
 
         form.insert("allow_resubmit", &allow_resubmit_str);
+        insert_extra_fields(&mut form, extra_fields);
 
         self.submit_asvo_job(&AsvoJobType::Conversion, form)
     }
@@ -476,6 +1359,25 @@ impl AsvoClient {
         delivery: Delivery,
         delivery_format: Option<DeliveryFormat>,
         allow_resubmit: bool,
+    ) -> Result<Option<AsvoJobID>, AsvoError> {
+        self.submit_meta_with_extra(
+            obsid,
+            delivery,
+            delivery_format,
+            allow_resubmit,
+            &BTreeMap::new(),
+        )
+    }
+
+    /// As [AsvoClient::submit_meta], but `extra_fields` are also added to
+    /// the submission, overriding any known fields with the same names.
+    pub fn submit_meta_with_extra(
+        &self,
+        obsid: Obsid,
+        delivery: Delivery,
+        delivery_format: Option<DeliveryFormat>,
+        allow_resubmit: bool,
+        extra_fields: &BTreeMap<&str, &str>,
     ) -> Result<Option<AsvoJobID>, AsvoError> {
         debug!("Submitting a metafits job to ASVO");
 
@@ -495,10 +1397,65 @@ impl AsvoClient {
 
         form.insert("download_type", "vis_meta");
         form.insert("allow_resubmit", &allow_resubmit_str);
+        insert_extra_fields(&mut form, extra_fields);
         self.submit_asvo_job(&AsvoJobType::DownloadMetadata, form)
     }
 
-    /// This low-level function actually submits jobs to the ASVO.
+    /// Cancel an ASVO job. The return can either be:
+    /// Ok(Some(jobid)) - this is when the job is cancelled
+    /// Ok(None) - this is when the job isn't in the user's queue
+    /// Err() - this is when the ASVO refuses to cancel the job (e.g. it has
+    /// already completed), or we hit another error
+    pub fn cancel_asvo_job(&self, jobid: AsvoJobID) -> Result<Option<AsvoJobID>, AsvoError> {
+        debug!("Cancelling ASVO job {}", jobid);
+        let url = format!("{}/api/cancel_job?job_id={}", self.server, jobid);
+        self.print_request("GET", &url, &BTreeMap::new());
+        let response = retry_request(self.retries, "cancelling a job", false, || {
+            send(self.client.get(&url))
+        })?;
+
+        let status = response.status();
+        let response_text = response.text()?;
+        match status {
+            StatusCode::OK => Ok(Some(jobid)),
+            StatusCode::NOT_FOUND => {
+                warn!("ASVO job ID {} wasn't found in your list of jobs.", jobid);
+                Ok(None)
+            }
+            StatusCode::BAD_REQUEST => match serde_json::from_str(&response_text) {
+                Ok(AsvoSubmitJobResponse::ErrorWithCode { error_code, error }) => {
+                    Err(AsvoError::BadRequest {
+                        code: error_code,
+                        message: error,
+                    })
+                }
+                _ => Err(AsvoError::BadRequest {
+                    code: 999,
+                    message: response_text,
+                }),
+            },
+            code => Err(AsvoError::BadStatus {
+                code,
+                message: response_text,
+            }),
+        }
+    }
+
+    /// Cancel many ASVO jobs. Every job is attempted, even if cancelling an
+    /// earlier one fails; the result of each (as for
+    /// [AsvoClient::cancel_asvo_job]) is returned alongside its job ID, in the
+    /// same order as `jobids`.
+    pub fn cancel_jobs(
+        &self,
+        jobids: &[AsvoJobID],
+    ) -> Vec<(AsvoJobID, Result<Option<AsvoJobID>, AsvoError>)> {
+        jobids
+            .iter()
+            .map(|&jobid| (jobid, self.cancel_asvo_job(jobid)))
+            .collect()
+    }
+
+    /// This low-level function actually submits jobs to the ASVO.
     /// The return can either be:
     /// Ok(Some(jobid)) - this is when a new job is submitted
     /// Ok(None) - this is when an existing job is resubmitted
@@ -517,11 +1474,11 @@ impl AsvoClient {
         };
 
         // Send a POST request to the ASVO.
-        let response = self
-            .client
-            .post(format!("{}/api/{}", get_asvo_server_address(), api_path))
-            .form(&form)
-            .send()?;
+        let url = format!("{}/api/{}", self.server, api_path);
+        self.print_request("POST", &url, &form);
+        let response = retry_request(self.retries, "submitting a job", false, || {
+            send(self.client.post(&url).form(&form))
+        })?;
 
         let code = response.status().as_u16();
         let response_text = &response.text()?;
@@ -561,6 +1518,11 @@ impl AsvoClient {
                 {
                     error!("{}", error.as_str());
                     Ok(None)
+                } else if let Some(partial) = outage(&error) {
+                    Err(AsvoError::ServiceOutage {
+                        partial,
+                        message: error,
+                    })
                 } else {
                     Err(AsvoError::BadRequest {
                         code: error_code,
@@ -582,14 +1544,1414 @@ impl AsvoClient {
     }
 }
 
+/// Get a [ClientBuilder] with the settings shared by all of our HTTP clients.
+fn client_builder(
+    max_connections_per_host: Option<usize>,
+    pool_idle_timeout: Option<Duration>,
+) -> ClientBuilder {
+    let mut builder = ClientBuilder::new()
+        .connection_verbose(true)
+        .danger_accept_invalid_certs(true); // Required for the ASVO.
+    if let Some(max) = max_connections_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(timeout) = pool_idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    builder
+}
+
+/// Read a number from the environment variable `var_name`, if it's set.
+fn number_from_env<T: std::str::FromStr>(var_name: &str) -> Result<Option<T>, AsvoError> {
+    match var(var_name) {
+        Ok(value) => match value.trim().parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(AsvoError::InvalidNumberVar {
+                var: var_name.to_string(),
+                value,
+            }),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Whether a job submission error message says that the MWA ASVO is having
+/// an outage. `Some(true)` is a partial outage (jobs can still be submitted
+/// with another delivery location), `Some(false)` is a full outage, and `None`
+/// means that the error is something else.
+fn outage(message: &str) -> Option<bool> {
+    let reason = message.strip_prefix("Your job cannot be submitted as ")?;
+    if reason.contains("partial outage") {
+        Some(true)
+    } else if reason.contains("outage") || reason.contains("down") || reason.contains("unavailable")
+    {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Run a request to the ASVO, retrying it up to `retries` times (with an
+/// exponential backoff) if it fails with a transient error. `what` describes
+/// the request for logging. Requests which aren't idempotent are only retried
+/// if they failed to connect, as the server may otherwise have acted on them.
+fn retry_request<T, F>(
+    retries: Option<u32>,
+    what: &str,
+    idempotent: bool,
+    mut request: F,
+) -> Result<T, AsvoError>
+where
+    F: FnMut() -> Result<T, AsvoError>,
+{
+    let retries = retries.unwrap_or(0);
+    let mut attempts = 0;
+    let op = || {
+        attempts += 1;
+        request().map_err(|e| {
+            if attempts <= retries && is_transient_request_error(&e, idempotent) {
+                Error::transient(e)
+            } else {
+                Error::permanent(e)
+            }
+        })
+    };
+    let backoff = ExponentialBackoff {
+        max_elapsed_time: None,
+        ..Default::default()
+    };
+    retry_notify(backoff, op, |e, wait| {
+        warn!(
+            "Error {} ({}); retrying in {}",
+            what,
+            e,
+            format_duration(wait)
+        )
+    })
+    .map_err(|e| match e {
+        Error::Permanent(e) | Error::Transient { err: e, .. } => e,
+    })
+}
+
+/// Could retrying a request to the ASVO which failed with this error succeed?
+fn is_transient_request_error(e: &AsvoError, idempotent: bool) -> bool {
+    let transient_status =
+        |s: StatusCode| s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS;
+    match e {
+        AsvoError::Reqwest(e) if e.is_connect() => true,
+        _ if !idempotent => false,
+        AsvoError::Reqwest(e) => e.is_timeout() || e.status().is_some_and(transient_status),
+        AsvoError::BadStatus { code, .. } => transient_status(*code),
+        AsvoError::ServerUnavailable { .. } => true,
+        _ => false,
+    }
+}
+
+/// Send a request, detecting a network proxy which wants credentials. A proxy
+/// for HTTPS requests refuses to open the tunnel, so this shows up as an error
+/// connecting, rather than as a response.
+fn send(request: RequestBuilder) -> Result<Response, AsvoError> {
+    let response = request.send().map_err(|e| {
+        if is_proxy_auth_error(&e) {
+            AsvoError::ProxyAuthRequired
+        } else {
+            AsvoError::Reqwest(e)
+        }
+    })?;
+    if response.status() == StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+        return Err(AsvoError::ProxyAuthRequired);
+    }
+    Ok(response)
+}
+
+/// Is this error from a proxy refusing to open a tunnel without credentials?
+fn is_proxy_auth_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if e.to_string()
+            .to_ascii_lowercase()
+            .contains("proxy authentication required")
+        {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// A short, single-line excerpt of a response body, for error messages.
+fn snippet(body: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    let text = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((i, _)) => format!("{}...", &text[..i]),
+        None => text,
+    }
+}
+
+/// A reader that fails as soon as the `interrupted` flag is set, or once the
+/// `deadline` (if any) has passed, so that downloads can be stopped between
+/// writes.
+struct InterruptibleReader<'a, R: Read> {
+    inner: R,
+    interrupted: &'a AtomicBool,
+    deadline: Option<Instant>,
+}
+
+impl<R: Read> Read for InterruptibleReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.interrupted.load(Ordering::Relaxed) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "download interrupted",
+            ));
+        }
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "download timed out",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Open a throughput log for appending, writing the CSV header if it's new.
+fn open_perf_log(path: &Path) -> io::Result<File> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "unix_time,jobid,file,bytes,bytes_per_sec")?;
+    }
+    Ok(file)
+}
+
+/// A reader that appends a throughput sample to a CSV log (if any) every
+/// `interval`, and once the inner reader is exhausted. Each sample is the
+/// number of bytes read since the last sample, and their rate.
+struct SamplingReader<'a, R: Read> {
+    inner: R,
+    log: Option<File>,
+    jobid: AsvoJobID,
+    file: &'a str,
+    interval: Duration,
+    last_sample: Instant,
+    bytes: u64,
+}
+
+impl<R: Read> SamplingReader<'_, R> {
+    fn sample(&mut self) {
+        let Some(log) = self.log.as_mut() else {
+            return;
+        };
+        let elapsed = self.last_sample.elapsed();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let rate = self.bytes as f64 / elapsed.as_secs_f64().max(1e-3);
+        // One write per sample, so concurrent downloads don't interleave
+        // their lines. Logging problems shouldn't stop the download.
+        let line = format!(
+            "{:.3},{},{},{},{:.0}\n",
+            now.as_secs_f64(),
+            self.jobid,
+            self.file,
+            self.bytes,
+            rate
+        );
+        if let Err(e) = log.write_all(line.as_bytes()) {
+            warn!("Couldn't write a throughput sample: {}", e);
+            self.log = None;
+        }
+        self.last_sample = Instant::now();
+        self.bytes = 0;
+    }
+}
+
+impl<R: Read> Read for SamplingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes += n as u64;
+        if (n == 0 && self.bytes > 0) || self.last_sample.elapsed() >= self.interval {
+            self.sample();
+        }
+        Ok(n)
+    }
+}
+
+/// A reader that counts the bytes read through it.
+struct CountingReader<R: Read> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Find the job with the given ID in a job listing.
+fn find_job(jobs: &AsvoJobVec, jobid: AsvoJobID) -> Result<&AsvoJob, AsvoError> {
+    // Hopefully there's never multiples of the same ASVO job ID in a user's
+    // job listing...
+    jobs.0
+        .iter()
+        .find(|j| j.jobid == jobid)
+        .ok_or(AsvoError::NoAsvoJob(jobid))
+}
+
+/// Find the job associated with an obsid in a job listing. If a `delivery` is
+/// given, only the obsid's jobs with that delivery are considered. If more
+/// than one job is associated with the obsid, the ready job preferred by
+/// `prefer` is chosen; by default, there's no way to choose, and this is an
+/// error.
+fn find_obsid_job<'a>(
+    jobs: &'a AsvoJobVec,
+    obsid: Obsid,
+    prefer: JobPreference,
+    delivery: Option<&str>,
+) -> Result<&'a AsvoJob, AsvoError> {
+    let mut matches: Vec<&AsvoJob> = jobs.0.iter().filter(|j| j.obsid == obsid).collect();
+    if let Some(delivery) = delivery {
+        let any = !matches.is_empty();
+        matches.retain(|j| {
+            j.delivery()
+                .is_some_and(|d| d.eq_ignore_ascii_case(delivery))
+        });
+        if any && matches.is_empty() {
+            return Err(AsvoError::NoObsidDelivery {
+                obsid,
+                delivery: delivery.to_string(),
+            });
+        }
+    }
+    match matches[..] {
+        [] => Err(AsvoError::NoObsid(obsid)),
+        [job] => Ok(job),
+        _ => {
+            let ready = matches.iter().filter(|j| j.state == AsvoJobState::Ready);
+            let job = match prefer {
+                JobPreference::Error => None,
+                JobPreference::Newest => ready.max_by_key(|j| j.jobid),
+                JobPreference::Oldest => ready.min_by_key(|j| j.jobid),
+            };
+            match job {
+                Some(job) => {
+                    info!(
+                        "Obsid {} has {} jobs; downloading the {} ready job, ASVO job ID {}",
+                        obsid,
+                        matches.len(),
+                        prefer,
+                        job.jobid
+                    );
+                    Ok(job)
+                }
+                None => Err(AsvoError::TooManyObsids(obsid)),
+            }
+        }
+    }
+}
+
+/// Get the size of each of the given ready jobs, as (job ID, obsid, bytes). A
+/// job named by both its ID and its obsid is only counted once.
+fn ready_job_sizes(
+    jobs: &AsvoJobVec,
+    jobids: &[AsvoJobID],
+    obsids: &[Obsid],
+    opts: &DownloadOptions,
+) -> Result<Vec<(AsvoJobID, Obsid, u64)>, AsvoError> {
+    let mut selected: Vec<&AsvoJob> = vec![];
+    for &jobid in jobids {
+        selected.push(find_job(jobs, jobid)?);
+    }
+    for &obsid in obsids {
+        selected.push(find_obsid_job(
+            jobs,
+            obsid,
+            opts.prefer,
+            opts.delivery.as_deref(),
+        )?);
+    }
+
+    let mut sizes: Vec<(AsvoJobID, Obsid, u64)> = vec![];
+    for job in selected {
+        if sizes.iter().any(|(jobid, _, _)| *jobid == job.jobid) {
+            continue;
+        }
+        if let Err(e) = check_ready(job) {
+            warn!("{}; not counting its size", e);
+            continue;
+        }
+        sizes.push((job.jobid, job.obsid, job.total_size()));
+    }
+    Ok(sizes)
+}
+
+/// The error for a job whose download took longer than
+/// [DownloadOptions::job_timeout].
+fn job_timeout(job: &AsvoJob, opts: &DownloadOptions) -> AsvoError {
+    AsvoError::JobTimeout {
+        jobid: job.jobid,
+        timeout: opts.job_timeout.unwrap_or_default(),
+    }
+}
+
+/// Is the job ready to download?
+fn check_ready(job: &AsvoJob) -> Result<(), AsvoError> {
+    if job.state != AsvoJobState::Ready {
+        return Err(AsvoError::NotReady {
+            jobid: job.jobid,
+            state: job.state.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Is this job ready, but without all of its files listed? The ASVO may take a
+/// moment to list a job's files after it becomes ready.
+fn files_missing(job: &AsvoJob) -> bool {
+    job.state == AsvoJobState::Ready
+        && match &job.files {
+            None => true,
+            Some(files) => {
+                files.is_empty()
+                    || files
+                        .iter()
+                        .any(|f| f.r#type == Delivery::Acacia && f.url.is_none())
+            }
+        }
+}
+
+/// Get the file name from a `Content-Disposition` header value, e.g.
+/// `attachment; filename="1065880128_vis.tar"`.
+fn content_disposition_filename(value: &str) -> Option<String> {
+    value.split(';').find_map(|part| {
+        let (key, name) = part.trim().split_once('=')?;
+        if key.trim().eq_ignore_ascii_case("filename") {
+            Some(name.trim().trim_matches('"').to_string()).filter(|n| !n.is_empty())
+        } else {
+            None
+        }
+    })
+}
+
+/// Get a `curl` command equivalent to an ASVO API request. Logging in (when
+/// `login_user` is given) saves the session cookie to "cookies.txt", which
+/// other requests use. The API key is never included.
+fn curl_command(
+    method: &str,
+    url: &str,
+    login_user: Option<&str>,
+    form: &BTreeMap<&str, &str>,
+) -> String {
+    // Quote for a POSIX shell.
+    let quote = |s: &str| format!("'{}'", s.replace('\'', r"'\''"));
+    let mut command = vec!["curl".to_string()];
+    match login_user {
+        Some(user) => {
+            command.push("-c cookies.txt -u".to_string());
+            command.push(quote(&format!("{}:<MWA_ASVO_API_KEY>", user)));
+        }
+        None => command.push("-b cookies.txt".to_string()),
+    }
+    if method != "GET" {
+        command.push(format!("-X {}", method));
+    }
+    command.push(quote(url));
+    for (k, v) in form {
+        command.push("--data-urlencode".to_string());
+        command.push(quote(&format!("{}={}", k, v)));
+    }
+    command.join(" ")
+}
+
+/// Read the rest of a download, which stream untarring may not have consumed
+/// (e.g. the padding at the end of an archive), so that it is hashed and
+/// counted. `size` is the expected size of the whole download. If much is left
+/// to read, progress is logged periodically, so that finishing a big download
+/// doesn't look like a hang.
+fn drain<R: Read>(
+    reader: &mut CountingReader<R>,
+    size: u64,
+    name: &str,
+) -> Result<(), std::io::Error> {
+    let remaining = size.saturating_sub(reader.count);
+    let verbose = remaining >= DRAIN_PROGRESS_THRESHOLD;
+    if verbose {
+        info!(
+            "Finalising {}: verifying the remaining {} MiB",
+            name,
+            remaining / 1024 / 1024
+        );
+    }
+    let mut buf = vec![0; 1024 * 1024];
+    let mut last_report = Instant::now();
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        if verbose && last_report.elapsed() >= DRAIN_PROGRESS_INTERVAL {
+            info!(
+                "Finalising {}: {:.1}% read",
+                name,
+                reader.count as f64 / size as f64 * 100.0
+            );
+            last_report = Instant::now();
+        }
+    }
+    if verbose {
+        info!("Finalised {}", name);
+    }
+    Ok(())
+}
+
+/// Add user-specified fields to a job submission form, after the known
+/// fields. This allows new ASVO parameters to be used before giant-squid
+/// supports them; a user-specified field overrides a known field with the same
+/// name.
+fn insert_extra_fields<'a>(
+    form: &mut BTreeMap<&'a str, &'a str>,
+    extra_fields: &BTreeMap<&'a str, &'a str>,
+) {
+    for (&k, &v) in extra_fields.iter() {
+        debug!("Adding extra form field {}={}", k, v);
+        if let Some(old) = form.insert(k, v) {
+            warn!("Extra form field {}={} overrides {}={}", k, v, k, old);
+        }
+    }
+}
+
+/// Stream-untar an archive into `unpack_path`, returning the paths of the
+/// extracted files. If `flatten` is set, every file is extracted directly into
+/// `unpack_path`, ignoring any directories in the archive. If an
+/// `output_template` is given, it determines the path of each file (relative
+/// to `unpack_path`). Unless `preserve_mtime` is set, extracted files get the
+/// current time as their modification time. Extracted files get the permission
+/// bits of their entries; only if `preserve_permissions` is set are the
+/// setuid, setgid and sticky bits kept too. If `checksums` is given, the
+/// SHA-1 hash of each extracted regular file is computed as it's written, and
+/// added to it.
+#[allow(clippy::too_many_arguments)]
+fn unpack_archive<R: Read>(
+    reader: R,
+    unpack_path: &Path,
+    flatten: bool,
+    preserve_mtime: bool,
+    preserve_permissions: bool,
+    output_template: Option<&OutputTemplate>,
+    job: &AsvoJob,
+    mut checksums: Option<&mut Vec<(PathBuf, String)>>,
+) -> Result<Vec<PathBuf>, AsvoError> {
+    let mut tar = Archive::new(reader);
+    tar.set_preserve_mtime(preserve_mtime);
+    tar.set_preserve_permissions(preserve_permissions);
+
+    let mut paths = vec![];
+    let mut names = HashSet::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !flatten && output_template.is_none() && !entry_type.is_file() {
+            // Let tar handle directories, links and the like.
+            let path = unpack_path.join(entry.path()?);
+            if entry.unpack_in(unpack_path)? && !entry_type.is_dir() {
+                paths.push(path);
+            }
+            continue;
+        }
+
+        // Directories are created as needed for the files inside them.
+        if entry_type.is_dir() {
+            continue;
+        }
+        let name = if flatten {
+            let name = match entry.path()?.file_name() {
+                Some(name) => PathBuf::from(name),
+                None => continue,
+            };
+            if !names.insert(name.clone()) {
+                return Err(AsvoError::FlattenCollision {
+                    jobid: job.jobid,
+                    file: name.to_string_lossy().to_string(),
+                });
+            }
+            name
+        } else {
+            // Like `unpack_in`, ignore leading "/"s and "./"s.
+            entry
+                .path()?
+                .components()
+                .filter(|c| !matches!(c, Component::RootDir | Component::CurDir))
+                .collect()
+        };
+        // Like `unpack_in`, refuse to write outside of the unpack path.
+        if name
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            warn!(
+                "Job {}: skipping {} as it isn't a plain relative path",
+                job.jobid,
+                name.display()
+            );
+            continue;
+        }
+        let path = match output_template {
+            Some(t) => unpack_path.join(t.render(job.obsid, job.jobid, &name)),
+            None => unpack_path.join(name),
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        info!(
+            "Job {}: extracting {} ({})",
+            job.jobid,
+            path.display(),
+            bytesize::ByteSize(entry.size()).to_string_as(true)
+        );
+        let mut hasher = checksums.is_some().then(Sha1::new);
+        let result = if entry_type.is_file() {
+            write_entry(
+                &mut entry,
+                &path,
+                preserve_mtime,
+                preserve_permissions,
+                hasher.as_mut(),
+            )
+        } else {
+            hasher = None;
+            entry.unpack(&path).map(|_| ())
+        };
+        if let Err(e) = result {
+            // Don't leave a truncated file behind.
+            let _ = std::fs::remove_file(&path);
+            return Err(e.into());
+        }
+        if let (Some(checksums), Some(hasher)) = (checksums.as_deref_mut(), hasher) {
+            checksums.push((path.clone(), format!("{:x}", hasher.finalize())));
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Write the contents of a regular file in a tar archive to `path`, like
+/// [Entry::unpack], but retrying writes which fail with transient IO errors.
+/// Networked filesystems (e.g. NFS) at HPC sites occasionally stall, and
+/// without retrying here, the whole download would have to start again. If a
+/// `hasher` is given, the file's contents are also fed to it.
+fn write_entry<R: Read>(
+    entry: &mut Entry<R>,
+    path: &Path,
+    preserve_mtime: bool,
+    preserve_permissions: bool,
+    mut hasher: Option<&mut Sha1>,
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    let mut buf = vec![0; 1024 * 1024];
+    let mut offset = 0;
+    loop {
+        let n = match entry.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        write_chunk_retrying(&mut file, offset, &buf[..n], path)?;
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(&buf[..n]);
+        }
+        offset += n as u64;
+    }
+
+    // Match what tar does when unpacking.
+    if preserve_mtime {
+        if let Ok(mtime) = entry.header().mtime() {
+            let mtime = filetime::FileTime::from_unix_time(mtime.max(1) as i64, 0);
+            filetime::set_file_handle_times(&file, Some(mtime), Some(mtime))?;
+        }
+    }
+    #[cfg(unix)]
+    if let Ok(mode) = entry.header().mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = if preserve_permissions {
+            mode & 0o7777
+        } else {
+            mode & 0o777
+        };
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Write `chunk` at `offset` in `file`, retrying transient IO errors with a
+/// short backoff. Each attempt writes the whole chunk again, so it doesn't
+/// matter if a failed attempt wrote some of it.
+fn write_chunk_retrying<W: Write + Seek>(
+    file: &mut W,
+    offset: u64,
+    chunk: &[u8],
+    path: &Path,
+) -> io::Result<()> {
+    let op = || {
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.write_all(chunk))
+            .map_err(|e| {
+                if is_transient_io_error(&e) {
+                    Error::transient(e)
+                } else {
+                    Error::permanent(e)
+                }
+            })
+    };
+    let backoff = ExponentialBackoff {
+        initial_interval: Duration::from_millis(100),
+        max_elapsed_time: Some(Duration::from_secs(30)),
+        ..Default::default()
+    };
+    retry_notify(backoff, op, |e, wait| {
+        warn!(
+            "Transient error writing {} ({}); retrying in {}",
+            path.display(),
+            e,
+            format_duration(wait)
+        )
+    })
+    .map_err(|e| match e {
+        Error::Permanent(e) | Error::Transient { err: e, .. } => e,
+    })
+}
+
+/// Is this IO error likely to go away by itself, e.g. a networked filesystem
+/// stalling? Running out of disk space (or quota) is not transient.
+fn is_transient_io_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    ) || matches!(e.raw_os_error(), Some(libc::EIO) | Some(libc::ESTALE))
+}
+
+/// Did a download's read fail because no bytes were received in time? The
+/// error from the HTTP client may be wrapped in other (e.g. tar) errors.
+fn is_stall(e: &io::Error) -> bool {
+    let mut next: Option<&(dyn std::error::Error + 'static)> = e.get_ref().map(|e| e as _);
+    while let Some(e) = next {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout();
+        }
+        // The source of an IO error skips the error that it wraps.
+        next = match e.downcast_ref::<io::Error>() {
+            Some(e) => e.get_ref().map(|e| e as _),
+            None => e.source(),
+        };
+    }
+    false
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
+    use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
 
     use crate::AsvoError;
     use crate::Delivery;
     use crate::DeliveryFormat;
-    use crate::{AsvoClient, Obsid};
+    use crate::{AsvoClient, Obsid, DEFAULT_CONVERSION_PARAMETERS, KNOWN_CONVERSION_PARAMETERS};
+
+    use std::str::FromStr;
+
+    use super::{
+        check_ready, content_disposition_filename, curl_command, drain, find_job, find_obsid_job,
+        insert_extra_fields, is_proxy_auth_error, is_transient_io_error, open_perf_log, outage,
+        unpack_archive, write_chunk_retrying, CountingReader, InterruptibleReader, SamplingReader,
+    };
+    use crate::{AsvoJob, AsvoJobState, AsvoJobType, AsvoJobVec, JobPreference, OutputTemplate};
+
+    /// Make a tar archive in memory with the given (path, contents) entries.
+    pub(super) fn make_tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Make a ready job for obsid 1065880128 with job ID 1.
+    fn test_job() -> AsvoJob {
+        AsvoJob {
+            obsid: Obsid::validate(1065880128).unwrap(),
+            jobid: 1,
+            jtype: AsvoJobType::DownloadVisibilities,
+            state: AsvoJobState::Ready,
+            error_code: None,
+            files: None,
+            details: Default::default(),
+        }
+    }
+
+    /// A job listing with jobs 1 (ready) and 2 (queued) for obsid 1065880128,
+    /// and job 3 (ready) for obsid 1065880129.
+    fn test_jobs() -> AsvoJobVec {
+        let other_obsid = Obsid::validate(1065880129).unwrap();
+        AsvoJobVec(vec![
+            test_job(),
+            AsvoJob {
+                jobid: 2,
+                state: AsvoJobState::Queued,
+                ..test_job()
+            },
+            AsvoJob {
+                jobid: 3,
+                obsid: other_obsid,
+                ..test_job()
+            },
+        ])
+    }
+
+    #[test]
+    fn test_sampling_reader() {
+        let path = std::env::temp_dir().join("giant_squid_sampling_reader.csv");
+        let _ = std::fs::remove_file(&path);
+        let mut reader = SamplingReader {
+            inner: Cursor::new(vec![0; 10]),
+            log: Some(open_perf_log(&path).unwrap()),
+            jobid: 1,
+            file: "1065880128_1.tar",
+            interval: std::time::Duration::from_secs(3600),
+            last_sample: std::time::Instant::now(),
+            bytes: 0,
+        };
+        let mut buf = [0; 4];
+        while reader.read(&mut buf).unwrap() > 0 {}
+        // Reopening the log doesn't repeat the header.
+        drop(reader);
+        open_perf_log(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // With a long interval, only the final sample is written.
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", contents);
+        assert_eq!(lines[0], "unix_time,jobid,file,bytes,bytes_per_sec");
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(fields[1..4], ["1", "1065880128_1.tar", "10"]);
+        assert!(fields[0].parse::<f64>().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_is_proxy_auth_error() {
+        // As reqwest reports a proxy refusing to open a tunnel.
+        let tunnel = io::Error::new(io::ErrorKind::Other, "proxy authentication required");
+        let e = io::Error::new(io::ErrorKind::Other, tunnel);
+        assert!(is_proxy_auth_error(&e));
+        let e = io::Error::new(io::ErrorKind::Other, "connection refused");
+        assert!(!is_proxy_auth_error(&e));
+    }
+
+    #[test]
+    fn test_default_conversion_parameters_are_known() {
+        for key in DEFAULT_CONVERSION_PARAMETERS.keys() {
+            assert!(
+                KNOWN_CONVERSION_PARAMETERS.iter().any(|p| p.key == *key),
+                "{} isn't a known conversion parameter",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_job() {
+        let jobs = test_jobs();
+        assert_eq!(find_job(&jobs, 2).unwrap().jobid, 2);
+        assert!(matches!(find_job(&jobs, 4), Err(AsvoError::NoAsvoJob(4))));
+        assert!(matches!(
+            find_job(&AsvoJobVec(vec![]), 1),
+            Err(AsvoError::NoAsvoJob(1))
+        ));
+    }
+
+    #[test]
+    fn test_find_obsid_job() {
+        let jobs = test_jobs();
+        let obsid = Obsid::validate(1065880128).unwrap();
+        let other_obsid = Obsid::validate(1065880129).unwrap();
+        let missing_obsid = Obsid::validate(1065880130).unwrap();
+
+        // One match.
+        let job = find_obsid_job(&jobs, other_obsid, JobPreference::Error, None).unwrap();
+        assert_eq!(job.jobid, 3);
+        // No matches.
+        assert!(matches!(
+            find_obsid_job(&jobs, missing_obsid, JobPreference::Newest, None),
+            Err(AsvoError::NoObsid(o)) if o == missing_obsid
+        ));
+        // Many matches, with no way to choose.
+        assert!(matches!(
+            find_obsid_job(&jobs, obsid, JobPreference::Error, None),
+            Err(AsvoError::TooManyObsids(o)) if o == obsid
+        ));
+        // Many matches; only ready jobs are preferred.
+        for prefer in [JobPreference::Newest, JobPreference::Oldest] {
+            let job = find_obsid_job(&jobs, obsid, prefer, None).unwrap();
+            assert_eq!(job.jobid, 1);
+        }
+        // Many matches, but none ready.
+        let queued = AsvoJobVec(
+            jobs.0
+                .into_iter()
+                .map(|j| AsvoJob {
+                    state: AsvoJobState::Queued,
+                    ..j
+                })
+                .collect(),
+        );
+        assert!(matches!(
+            find_obsid_job(&queued, obsid, JobPreference::Newest, None),
+            Err(AsvoError::TooManyObsids(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_obsid_job_delivery() {
+        let obsid = Obsid::validate(1065880128).unwrap();
+        let with_delivery = |jobid, delivery: &str| {
+            let mut job = AsvoJob {
+                jobid,
+                ..test_job()
+            };
+            job.details.delivery = Some(delivery.to_string());
+            job
+        };
+        let jobs = AsvoJobVec(vec![
+            with_delivery(1, "acacia"),
+            with_delivery(2, "scratch"),
+            with_delivery(3, "scratch"),
+        ]);
+
+        let job = find_obsid_job(&jobs, obsid, JobPreference::Error, Some("acacia")).unwrap();
+        assert_eq!(job.jobid, 1);
+        // Still ambiguous.
+        assert!(matches!(
+            find_obsid_job(&jobs, obsid, JobPreference::Error, Some("scratch")),
+            Err(AsvoError::TooManyObsids(_))
+        ));
+        let job = find_obsid_job(&jobs, obsid, JobPreference::Newest, Some("scratch")).unwrap();
+        assert_eq!(job.jobid, 3);
+        assert!(matches!(
+            find_obsid_job(&jobs, obsid, JobPreference::Error, Some("dug")),
+            Err(AsvoError::NoObsidDelivery { delivery, .. }) if delivery == "dug"
+        ));
+    }
+
+    #[test]
+    fn test_check_ready() {
+        let jobs = test_jobs();
+        assert!(check_ready(find_job(&jobs, 1).unwrap()).is_ok());
+        assert!(matches!(
+            check_ready(find_job(&jobs, 2).unwrap()),
+            Err(AsvoError::NotReady {
+                jobid: 2,
+                state: AsvoJobState::Queued
+            })
+        ));
+    }
+
+    /// Make an empty, unique directory for a test to unpack into.
+    pub(super) fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("giant_squid_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_unpack_archive_nested() {
+        let dir = test_dir("unpack_archive_nested");
+        let tar = make_tar(&[("a/b/1.fits", b"one"), ("a/2.fits", b"two")]);
+        let paths = unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            false,
+            false,
+            None,
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(paths, vec![dir.join("a/b/1.fits"), dir.join("a/2.fits")]);
+        assert!(dir.join("a/b/1.fits").exists());
+        assert!(dir.join("a/2.fits").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_archive_file_before_dir() {
+        let dir = test_dir("unpack_archive_file_before_dir");
+        // Tar doesn't promise that directories come before the files in them.
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "a/b/1.fits", &b"one"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, "a/b/", &[][..]).unwrap();
+        let tar = builder.into_inner().unwrap();
+        let paths = unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            false,
+            false,
+            None,
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(paths, vec![dir.join("a/b/1.fits")]);
+        assert_eq!(std::fs::read(dir.join("a/b/1.fits")).unwrap(), b"one");
+        assert!(dir.join("a/b").is_dir());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_archive_checksums() {
+        let dir = test_dir("unpack_archive_checksums");
+        let tar = make_tar(&[("a/b/1.fits", b"one"), ("a/2.fits", b"two")]);
+        let mut checksums = vec![];
+        unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            false,
+            false,
+            None,
+            &test_job(),
+            Some(&mut checksums),
+        )
+        .unwrap();
+        assert_eq!(
+            checksums,
+            vec![
+                (
+                    dir.join("a/b/1.fits"),
+                    "fe05bcdcdc4928012781a5f1a2a77cbb5398e106".to_string()
+                ),
+                (
+                    dir.join("a/2.fits"),
+                    "ad782ecdac770fc6eb9a62e44f90873fb97fb26b".to_string()
+                ),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_archive_flatten() {
+        let dir = test_dir("unpack_archive_flatten");
+        let tar = make_tar(&[("a/b/1.fits", b"one"), ("a/2.fits", b"two")]);
+        let paths = unpack_archive(
+            tar.as_slice(),
+            &dir,
+            true,
+            false,
+            false,
+            None,
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(paths, vec![dir.join("1.fits"), dir.join("2.fits")]);
+        assert_eq!(std::fs::read(dir.join("1.fits")).unwrap(), b"one");
+        assert_eq!(std::fs::read(dir.join("2.fits")).unwrap(), b"two");
+        assert!(!Path::new(&dir.join("a")).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_archive_output_template() {
+        let dir = test_dir("unpack_archive_output_template");
+        let tar = make_tar(&[("a/b/1.fits", b"one"), ("a/2.fits", b"two")]);
+        let template = OutputTemplate::from_str("{obsid}/{jobid}_{filename}").unwrap();
+        let paths = unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            false,
+            false,
+            Some(&template),
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join("1065880128/1_a/b/1.fits"),
+                dir.join("1065880128/1_a/2.fits")
+            ]
+        );
+        assert_eq!(
+            std::fs::read(dir.join("1065880128/1_a/b/1.fits")).unwrap(),
+            b"one"
+        );
+
+        // With --flatten, {filename} has no directories.
+        let paths = unpack_archive(
+            tar.as_slice(),
+            &dir,
+            true,
+            false,
+            false,
+            Some(&template),
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join("1065880128/1_1.fits"),
+                dir.join("1065880128/1_2.fits")
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_archive_preserve_mtime() {
+        let dir = test_dir("unpack_archive_preserve_mtime");
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_mtime(1_000_000_000);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "1.fits", b"one".as_slice())
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+        let mtime = |p: &Path| {
+            std::fs::metadata(p)
+                .unwrap()
+                .modified()
+                .unwrap()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+
+        unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            true,
+            false,
+            None,
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(mtime(&dir.join("1.fits")), 1_000_000_000);
+
+        std::fs::remove_file(dir.join("1.fits")).unwrap();
+        unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            false,
+            false,
+            None,
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert!(mtime(&dir.join("1.fits")) > 1_000_000_000);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_archive_preserve_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("unpack_archive_preserve_permissions");
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o4755);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "run.sh", b"one".as_slice())
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+
+        unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            false,
+            false,
+            None,
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(mode(&dir.join("run.sh")), 0o755);
+
+        std::fs::remove_file(dir.join("run.sh")).unwrap();
+        unpack_archive(
+            tar.as_slice(),
+            &dir,
+            false,
+            false,
+            true,
+            None,
+            &test_job(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(mode(&dir.join("run.sh")), 0o4755);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_archive_flatten_collision() {
+        let dir = test_dir("unpack_archive_flatten_collision");
+        let tar = make_tar(&[("a/1.fits", b"one"), ("b/1.fits", b"two")]);
+        let result = unpack_archive(
+            tar.as_slice(),
+            &dir,
+            true,
+            false,
+            false,
+            None,
+            &test_job(),
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(AsvoError::FlattenCollision { jobid: 1, .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A reader that sets `interrupted` once `limit` bytes have been read.
+    struct Tripwire<'a> {
+        data: &'a [u8],
+        limit: usize,
+        interrupted: &'a AtomicBool,
+    }
+
+    impl Read for Tripwire<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.limit);
+            let n = self.data.take(n as u64).read(buf)?;
+            self.data = &self.data[n..];
+            self.limit -= n;
+            if self.limit == 0 {
+                self.interrupted.store(true, Ordering::Relaxed);
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_unpack_archive_interrupted() {
+        let dir = test_dir("unpack_archive_interrupted");
+        let big = vec![1; 10_000];
+        let tar = make_tar(&[("1.fits", b"one"), ("2.fits", &big)]);
+        // Interrupt part way through the second file.
+        let interrupted = AtomicBool::new(false);
+        let reader = InterruptibleReader {
+            inner: Tripwire {
+                data: &tar,
+                limit: 4096,
+                interrupted: &interrupted,
+            },
+            interrupted: &interrupted,
+            deadline: None,
+        };
+        let result = unpack_archive(reader, &dir, false, false, false, None, &test_job(), None);
+        assert!(matches!(result, Err(AsvoError::IO(_))));
+        assert!(dir.join("1.fits").exists());
+        assert!(!dir.join("2.fits").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A writer which fails its first `failures` writes with `error` after
+    /// writing half of the data, like a flaky networked filesystem.
+    struct FlakyWriter {
+        inner: Cursor<Vec<u8>>,
+        failures: usize,
+        error: fn() -> io::Error,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                self.inner.write_all(&buf[..buf.len() / 2])?;
+                return Err((self.error)());
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FlakyWriter {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_write_chunk_retrying() {
+        let mut writer = FlakyWriter {
+            inner: Cursor::new(b"abc".to_vec()),
+            failures: 2,
+            error: || io::Error::from(io::ErrorKind::TimedOut),
+        };
+        write_chunk_retrying(&mut writer, 3, b"defghi", Path::new("test")).unwrap();
+        assert_eq!(writer.inner.into_inner(), b"abcdefghi");
+
+        // A full disk isn't going to fix itself.
+        let mut writer = FlakyWriter {
+            inner: Cursor::new(vec![]),
+            failures: 1,
+            error: || io::Error::from_raw_os_error(libc::ENOSPC),
+        };
+        let result = write_chunk_retrying(&mut writer, 0, b"abc", Path::new("test"));
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::ENOSPC));
+        assert_eq!(writer.failures, 0);
+    }
+
+    #[test]
+    fn test_outage() {
+        assert_eq!(
+            outage("Your job cannot be submitted as there is a full outage in progress."),
+            Some(false)
+        );
+        assert_eq!(
+            outage("Your job cannot be submitted as there is a partial outage, please use a delivery location other than acacia."),
+            Some(true)
+        );
+        assert_eq!(
+            outage("Your job cannot be submitted as the staging server is down and also acacia is unavailable!"),
+            Some(false)
+        );
+        assert_eq!(outage("Observation 1065880128 does not exist"), None);
+        assert_eq!(
+            outage("Unable to submit job. Observation has no files to download."),
+            None
+        );
+    }
+
+    #[test]
+    fn test_content_disposition_filename() {
+        assert_eq!(
+            content_disposition_filename(r#"attachment; filename="1065880128_vis.tar""#),
+            Some("1065880128_vis.tar".to_string())
+        );
+        assert_eq!(
+            content_disposition_filename("attachment;FILENAME=1065880128_vis.tar"),
+            Some("1065880128_vis.tar".to_string())
+        );
+        assert_eq!(content_disposition_filename("attachment"), None);
+        assert_eq!(
+            content_disposition_filename("attachment; filename=\"\""),
+            None
+        );
+    }
+
+    #[test]
+    fn test_curl_command() {
+        assert_eq!(
+            curl_command(
+                "POST",
+                "https://asvo.example/api/api_login",
+                Some("mantaray-clientv1.2"),
+                &BTreeMap::new()
+            ),
+            "curl -c cookies.txt -u 'mantaray-clientv1.2:<MWA_ASVO_API_KEY>' -X POST 'https://asvo.example/api/api_login'"
+        );
+        assert_eq!(
+            curl_command(
+                "GET",
+                "https://asvo.example/api/get_jobs",
+                None,
+                &BTreeMap::new()
+            ),
+            "curl -b cookies.txt 'https://asvo.example/api/get_jobs'"
+        );
+        let mut form = BTreeMap::new();
+        form.insert("obs_id", "1065880128");
+        form.insert("note", "it's");
+        assert_eq!(
+            curl_command(
+                "POST",
+                "https://asvo.example/api/download_vis_job",
+                None,
+                &form
+            ),
+            r"curl -b cookies.txt -X POST 'https://asvo.example/api/download_vis_job' --data-urlencode 'note=it'\''s' --data-urlencode 'obs_id=1065880128'"
+        );
+    }
+
+    #[test]
+    fn test_drain() {
+        let data = vec![7; 3 * 1024 * 1024 + 5];
+        let mut reader = CountingReader {
+            inner: &data[..],
+            count: 0,
+        };
+        let mut start = [0; 100];
+        reader.read_exact(&mut start).unwrap();
+        drain(&mut reader, data.len() as u64, "test").unwrap();
+        assert_eq!(reader.count, data.len() as u64);
+        assert_eq!(reader.inner.len(), 0);
+    }
+
+    #[test]
+    fn test_is_transient_io_error() {
+        assert!(is_transient_io_error(&io::Error::from_raw_os_error(
+            libc::EIO
+        )));
+        assert!(is_transient_io_error(&io::Error::from(
+            io::ErrorKind::TimedOut
+        )));
+        assert!(!is_transient_io_error(&io::Error::from_raw_os_error(
+            libc::ENOSPC
+        )));
+        assert!(!is_transient_io_error(&io::Error::from_raw_os_error(
+            libc::EDQUOT
+        )));
+        assert!(!is_transient_io_error(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+    }
 
     #[test]
     fn test_create_asvo_client() {
@@ -597,6 +2959,22 @@ mod tests {
         assert!(client.is_ok());
     }
 
+    #[test]
+    fn test_insert_extra_fields() {
+        let mut form = BTreeMap::new();
+        form.insert("obs_id", "1065880128");
+        form.insert("delivery", "acacia");
+        let mut extra_fields = BTreeMap::new();
+        extra_fields.insert("new_param", "1");
+        extra_fields.insert("delivery", "scratch");
+        insert_extra_fields(&mut form, &extra_fields);
+
+        assert_eq!(form.len(), 3);
+        assert_eq!(form.get("obs_id"), Some(&"1065880128"));
+        assert_eq!(form.get("new_param"), Some(&"1"));
+        assert_eq!(form.get("delivery"), Some(&"scratch"));
+    }
+
     #[test]
     fn test_get_jobs() {
         let client = AsvoClient::new();
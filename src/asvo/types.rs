@@ -4,7 +4,12 @@
 
 //! ASVO data types.
 
-use std::{collections::BTreeMap, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    ops::RangeBounds,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
 
 use log::warn;
 use prettytable::{row, Cell, Row, Table};
@@ -73,6 +78,213 @@ impl FromStr for AsvoJobState {
     }
 }
 
+/// A single field of an ASVO job, which can be printed by itself.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AsvoJobField {
+    JobID,
+    Obsid,
+    Type,
+    State,
+    Size,
+    Delivery,
+}
+
+impl FromStr for AsvoJobField {
+    type Err = AsvoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match _sanitize_identifier(s).as_str() {
+            "jobid" => Ok(AsvoJobField::JobID),
+            "obsid" => Ok(AsvoJobField::Obsid),
+            "type" | "jobtype" => Ok(AsvoJobField::Type),
+            "state" | "jobstate" => Ok(AsvoJobField::State),
+            "size" | "filesize" => Ok(AsvoJobField::Size),
+            "delivery" => Ok(AsvoJobField::Delivery),
+            _ => Err(AsvoError::InvalidJobField { str: s.to_string() }),
+        }
+    }
+}
+
+/// The colours used to style job tables; see [AsvoJobVec::list_with_style].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ColourScheme {
+    /// Green for ready jobs, and red for failed jobs.
+    #[default]
+    Default,
+    /// Blue for ready jobs, and yellow for failed jobs, avoiding red-green
+    /// contrast.
+    Deuteranopia,
+    /// No colours; bold for ready jobs, and underlined for failed jobs.
+    Mono,
+}
+
+impl FromStr for ColourScheme {
+    type Err = AsvoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match _sanitize_identifier(s).as_str() {
+            "default" => Ok(ColourScheme::Default),
+            "deuteranopia" => Ok(ColourScheme::Deuteranopia),
+            "mono" => Ok(ColourScheme::Mono),
+            _ => Err(AsvoError::InvalidColourScheme { str: s.to_string() }),
+        }
+    }
+}
+
+impl std::fmt::Display for ColourScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ColourScheme::Default => "default",
+                ColourScheme::Deuteranopia => "deuteranopia",
+                ColourScheme::Mono => "mono",
+            }
+        )
+    }
+}
+
+/// Get the `prettytable` style spec for a job's state in a job table.
+fn get_job_state_table_style(state: &AsvoJobState, scheme: ColourScheme) -> &'static str {
+    match (scheme, state) {
+        (ColourScheme::Default, AsvoJobState::Queued) => "Fm",
+        (ColourScheme::Default, AsvoJobState::Processing) => "Fb",
+        (ColourScheme::Default, AsvoJobState::Ready) => "Fg",
+        (ColourScheme::Default, _) => "Fr",
+        (ColourScheme::Deuteranopia, AsvoJobState::Queued) => "Fw",
+        (ColourScheme::Deuteranopia, AsvoJobState::Processing) => "Fc",
+        (ColourScheme::Deuteranopia, AsvoJobState::Ready) => "bFb",
+        (ColourScheme::Deuteranopia, _) => "bFy",
+        (ColourScheme::Mono, AsvoJobState::Ready) => "b",
+        (ColourScheme::Mono, AsvoJobState::Queued | AsvoJobState::Processing) => "",
+        (ColourScheme::Mono, _) => "u",
+    }
+}
+
+/// Get the `prettytable` style spec for a job's type in a job table.
+fn get_job_type_table_style(jtype: &AsvoJobType, scheme: ColourScheme) -> &'static str {
+    match (scheme, jtype) {
+        (ColourScheme::Mono, _) => "",
+        (_, AsvoJobType::Conversion) => "Fb",
+        (_, AsvoJobType::DownloadVisibilities) => "Fb",
+        (_, AsvoJobType::DownloadMetadata) => "Fy",
+        (_, AsvoJobType::DownloadVoltage) => "Fm",
+        (ColourScheme::Default, AsvoJobType::CancelJob) => "Fr",
+        (ColourScheme::Deuteranopia, AsvoJobType::CancelJob) => "Fw",
+    }
+}
+
+/// Which job to download when more than one ready job is associated with an
+/// obsid.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum JobPreference {
+    /// Don't guess; fail with [AsvoError::TooManyObsids].
+    #[default]
+    Error,
+    /// The job with the highest job ID.
+    Newest,
+    /// The job with the lowest job ID.
+    Oldest,
+}
+
+impl FromStr for JobPreference {
+    type Err = AsvoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match _sanitize_identifier(s).as_str() {
+            "error" => Ok(JobPreference::Error),
+            "newest" => Ok(JobPreference::Newest),
+            "oldest" => Ok(JobPreference::Oldest),
+            _ => Err(AsvoError::InvalidJobPreference { str: s.to_string() }),
+        }
+    }
+}
+
+impl std::fmt::Display for JobPreference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                JobPreference::Error => "error",
+                JobPreference::Newest => "newest",
+                JobPreference::Oldest => "oldest",
+            }
+        )
+    }
+}
+
+/// A template for the paths of downloaded files, relative to the download
+/// directory, e.g. "{obsid}/{filename}". The available placeholders are
+/// `{obsid}`, `{jobid}` and `{filename}` (the file's name in the archive, or
+/// the archive's name if it isn't being extracted); `{filename}` is required.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct OutputTemplate(String);
+
+impl OutputTemplate {
+    /// Get the path of a file of a job from this template.
+    pub fn render(&self, obsid: Obsid, jobid: AsvoJobID, filename: &Path) -> PathBuf {
+        // Substitute the filename last, in case it contains something that
+        // looks like a placeholder.
+        PathBuf::from(
+            self.0
+                .replace("{obsid}", &obsid.to_string())
+                .replace("{jobid}", &jobid.to_string())
+                .replace("{filename}", &filename.to_string_lossy()),
+        )
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = AsvoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| AsvoError::InvalidOutputTemplate {
+            template: s.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let mut rest = s;
+        let mut has_filename = false;
+        while let Some(start) = rest.find('{') {
+            if rest[..start].contains('}') {
+                return Err(invalid("a '}' isn't opened"));
+            }
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => return Err(invalid("a '{' isn't closed")),
+            };
+            match &rest[start + 1..end] {
+                "filename" => has_filename = true,
+                "obsid" | "jobid" => (),
+                p => {
+                    return Err(invalid(&format!(
+                    "unknown placeholder '{{{}}}'; expected {{obsid}}, {{jobid}} or {{filename}}",
+                    p
+                )))
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(invalid("a '}' isn't opened"));
+        }
+        if !has_filename {
+            return Err(invalid("it must contain {filename}"));
+        }
+        if Path::new(s)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(invalid(
+                "it must be a relative path without '.' or '..' components",
+            ));
+        }
+        Ok(OutputTemplate(s.to_string()))
+    }
+}
+
 /// A single file provided by an ASVO job.
 #[derive(Serialize, PartialEq, Eq, Debug, Clone)]
 pub struct AsvoFilesArray {
@@ -88,6 +300,160 @@ pub struct AsvoFilesArray {
     pub sha1: Option<String>,
 }
 
+impl AsvoFilesArray {
+    /// Get the name of this file, i.e. the last segment of its URL (for
+    /// Acacia delivery) or path (for /scratch delivery).
+    pub fn output_filename(&self) -> Result<String, AsvoError> {
+        let name = match self.r#type {
+            Delivery::Acacia => self
+                .url
+                .as_deref()
+                .and_then(|url| reqwest::Url::parse(url).ok())
+                .and_then(|url| url.path_segments()?.next_back().map(|s| s.to_string())),
+            Delivery::Scratch => self.path.as_deref().and_then(|path| {
+                std::path::Path::new(path)
+                    .file_name()?
+                    .to_str()
+                    .map(|s| s.to_string())
+            }),
+            Delivery::Other(_) => None,
+        };
+        match name {
+            Some(name) if !name.is_empty() => Ok(name),
+            _ => Err(AsvoError::NoFilename(
+                self.url
+                    .clone()
+                    .or_else(|| self.path.clone())
+                    .unwrap_or_default(),
+            )),
+        }
+    }
+}
+
+/// What was downloaded for an ASVO job.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct AsvoDownload {
+    /// The ID of the downloaded job.
+    pub jobid: AsvoJobID,
+    /// The obsid of the downloaded job.
+    pub obsid: Option<Obsid>,
+    /// The number of bytes downloaded.
+    pub bytes: u64,
+    /// The local paths of the downloaded files.
+    pub paths: Vec<PathBuf>,
+    /// The SHA-1 hashes of the downloaded files (or of the archives, if they
+    /// were kept), if [`DownloadOptions::file_checksums`] was set.
+    pub checksums: Vec<(PathBuf, String)>,
+}
+
+/// Options for downloading ASVO jobs.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DownloadOptions {
+    /// Keep the downloaded archives, rather than extracting them.
+    pub keep_tar: bool,
+    /// Verify downloads against the upstream SHA-1 hash.
+    pub hash: bool,
+    /// Extract all files directly into the download directory, ignoring any
+    /// directories in the archive.
+    pub flatten: bool,
+    /// Keep the modification times of files in the archive when extracting.
+    pub preserve_mtime: bool,
+    /// Keep the setuid, setgid and sticky bits of files in the archive when
+    /// extracting; otherwise, only the permission bits are kept.
+    pub preserve_permissions: bool,
+    /// Compute the SHA-1 hash of each downloaded file (of each extracted file
+    /// as it's written, or of each kept archive), for
+    /// [`AsvoDownload::checksums`].
+    pub file_checksums: bool,
+    /// After a job is downloaded, save its record from the job listing as
+    /// pretty-printed JSON to "<jobid>.json" in the download directory.
+    pub save_job_json: bool,
+    /// A template for the paths of downloaded files.
+    pub output_template: Option<OutputTemplate>,
+    /// The directory to download into.
+    pub download_dir: String,
+    /// Which job to download when an obsid has more than one ready job. Only
+    /// used by [`crate::asvo::AsvoClient::download_obsid`].
+    pub prefer: JobPreference,
+    /// Only consider an obsid's jobs with this delivery (e.g. "acacia" or
+    /// "scratch"), for obsids with jobs delivered to more than one place.
+    /// Only used by [`crate::asvo::AsvoClient::download_obsid`].
+    pub delivery: Option<String>,
+    /// The maximum number of jobs to download at once. Only used by
+    /// [`crate::asvo::AsvoClient::download_jobs`].
+    pub concurrency: usize,
+    /// The maximum total size of the jobs downloaded by a client [bytes]. A
+    /// job which would take the total over this is skipped with
+    /// [`crate::asvo::AsvoError::MaxTotalSizeExceeded`]. `None` means no
+    /// limit.
+    pub max_total_size: Option<u64>,
+    /// Retry a file's download if no bytes of it are received for this long.
+    /// This applies to each read, not the whole download. `None` uses the
+    /// HTTP client's default of 30 seconds.
+    pub stall_timeout: Option<std::time::Duration>,
+    /// Before resuming a partial download (with `keep_tar`), check its first
+    /// and last bytes against the same bytes downloaded again, and start
+    /// again if they differ. Otherwise, a partial download is trusted until
+    /// the whole archive can be verified.
+    pub verify_partial: bool,
+    /// Abandon a job's download if it takes longer than this in total, with
+    /// [`crate::asvo::AsvoError::JobTimeout`]. `None` means no limit.
+    pub job_timeout: Option<std::time::Duration>,
+    /// Append periodic throughput samples of each file's download to this CSV
+    /// file, with the columns "unix_time,jobid,file,bytes,bytes_per_sec".
+    pub perf_log: Option<PathBuf>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            keep_tar: false,
+            hash: true,
+            flatten: false,
+            preserve_mtime: false,
+            preserve_permissions: false,
+            file_checksums: false,
+            save_job_json: false,
+            output_template: None,
+            download_dir: ".".to_string(),
+            prefer: JobPreference::Error,
+            delivery: None,
+            concurrency: 1,
+            max_total_size: None,
+            stall_timeout: None,
+            verify_partial: false,
+            job_timeout: None,
+            perf_log: None,
+        }
+    }
+}
+
+/// Options for the HTTP connections made by an
+/// [`crate::asvo::AsvoClient`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// The maximum number of connections to each host, e.g. the Acacia host
+    /// serving a job's files. This caps both the idle connections kept open
+    /// for reuse and the number of jobs downloaded at once by
+    /// [`crate::asvo::AsvoClient::download_jobs`]. `None` means no limit.
+    pub max_connections_per_host: Option<usize>,
+    /// Print an equivalent `curl` command to stderr for each request made to
+    /// the ASVO API (with the API key redacted).
+    pub print_requests: bool,
+    /// How many times a network operation (logging in, listing, submitting
+    /// or cancelling jobs, or downloading a file) which failed with a
+    /// transient error is retried. If `None`, the `GIANT_SQUID_RETRIES`
+    /// environment variable is used; if that isn't set either, downloads are
+    /// retried with an exponential backoff, and other operations aren't
+    /// retried.
+    pub retries: Option<u32>,
+    /// How long an idle connection is kept open for reuse before it's
+    /// closed. If `None`, the `GIANT_SQUID_POOL_IDLE_TIMEOUT` environment
+    /// variable (in seconds) is used; if that isn't set either, the HTTP
+    /// client's default of 90 seconds is used.
+    pub pool_idle_timeout: Option<std::time::Duration>,
+}
+
 /// A simple type alias. Not using a newtype, because that would produce
 /// unnecessary complexity.
 pub type AsvoJobID = u32;
@@ -102,7 +468,180 @@ pub struct AsvoJob {
     pub jtype: AsvoJobType,
     #[serde(rename = "jobState")]
     pub state: AsvoJobState,
+    /// The ASVO's numeric code for why the job failed, if it did.
+    #[serde(rename = "errorCode", skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<u32>,
     pub files: Option<Vec<AsvoFilesArray>>,
+    #[serde(flatten)]
+    pub details: AsvoJobDetails,
+}
+
+/// Extra details of an ASVO job, which are mostly useful for troubleshooting.
+/// Timestamps are as given by the ASVO, e.g. "2022-06-22T01:56:38.635146".
+#[derive(Serialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AsvoJobDetails {
+    /// The delivery requested when the job was submitted, e.g. "acacia".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<String>,
+    /// The Pawsey group of the user who submitted the job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pawsey_group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<String>,
+    /// The job's record in the job listing, exactly as given by the ASVO.
+    #[serde(skip)]
+    pub raw: Option<String>,
+}
+
+impl AsvoJob {
+    /// Get a single field of this job as a string. The size is the total
+    /// number of bytes of the job's files.
+    pub fn field(&self, field: AsvoJobField) -> String {
+        match field {
+            AsvoJobField::JobID => self.jobid.to_string(),
+            AsvoJobField::Obsid => self.obsid.to_string(),
+            AsvoJobField::Type => self.jtype.to_string(),
+            AsvoJobField::State => self.describe_state(),
+            AsvoJobField::Size => match &self.files {
+                None => "".to_string(),
+                Some(_) => self.total_size().to_string(),
+            },
+            AsvoJobField::Delivery => self.file_deliveries(),
+        }
+    }
+
+    /// The deliveries of this job's files, e.g. "acacia", or "acacia+scratch"
+    /// if its files were delivered to more than one place. Empty if the job
+    /// has no files.
+    pub fn file_deliveries(&self) -> String {
+        let mut deliveries: Vec<String> = vec![];
+        for f in self.files.iter().flatten() {
+            let d = f.r#type.to_string();
+            if !deliveries.contains(&d) {
+                deliveries.push(d);
+            }
+        }
+        deliveries.join("+")
+    }
+
+    /// The delivery of this job, e.g. "acacia": the delivery requested when it
+    /// was submitted or, if that isn't known, the delivery of its files.
+    pub fn delivery(&self) -> Option<String> {
+        self.details.delivery.clone().or_else(|| {
+            self.files
+                .as_ref()
+                .and_then(|v| v.first())
+                .map(|f| f.r#type.to_string())
+        })
+    }
+
+    /// Describe the state of this job, including the error code of failed
+    /// jobs, e.g. "Error [code 42]: Observation has no files".
+    pub fn describe_state(&self) -> String {
+        match (&self.state, self.error_code) {
+            (AsvoJobState::Error(e), Some(code)) => format!("Error [code {}]: {}", code, e),
+            (state, _) => state.to_string(),
+        }
+    }
+
+    /// Describe everything known about this job over multiple lines, for
+    /// troubleshooting.
+    pub fn describe(&self) -> String {
+        let or_none = |s: &Option<String>| s.clone().unwrap_or_else(|| "-".to_string());
+        let mut lines = vec![
+            format!("Job ID:       {}", self.jobid),
+            format!("Obsid:        {}", self.obsid),
+            format!("Job type:     {}", self.jtype),
+            format!("Job state:    {}", self.describe_state()),
+            format!("Delivery:     {}", or_none(&self.details.delivery)),
+            format!("Pawsey group: {}", or_none(&self.details.pawsey_group)),
+            format!("Created:      {}", or_none(&self.details.created)),
+            format!("Started:      {}", or_none(&self.details.started)),
+            format!("Completed:    {}", or_none(&self.details.completed)),
+        ];
+        match &self.files {
+            Some(files) if !files.is_empty() => {
+                lines.push(format!(
+                    "Files:        {} ({})",
+                    files.len(),
+                    bytesize::ByteSize(self.total_size()).to_string_as(true)
+                ));
+                for f in files {
+                    lines.push(format!(
+                        "  - {}",
+                        f.url
+                            .as_ref()
+                            .or(f.path.as_ref())
+                            .map_or("-", |s| s.as_str())
+                    ));
+                    lines.push(format!("    delivery: {}", f.r#type));
+                    lines.push(format!("    size:     {} bytes", f.size));
+                    lines.push(format!("    sha1:     {}", or_none(&f.sha1)));
+                }
+            }
+            _ => lines.push("Files:        -".to_string()),
+        }
+        lines.join("\n")
+    }
+
+    /// Get the (signed) download URLs of this job's files. Files without a
+    /// URL (e.g. those delivered to /scratch) are skipped, so this is empty
+    /// unless the job is ready.
+    pub fn download_urls(&self) -> Vec<&str> {
+        match &self.files {
+            None => vec![],
+            Some(v) => v.iter().filter_map(|f| f.url.as_deref()).collect(),
+        }
+    }
+
+    /// Get the total number of bytes of this job's files.
+    pub fn total_size(&self) -> u64 {
+        match &self.files {
+            None => 0,
+            Some(v) => v.iter().map(|f| f.size).sum(),
+        }
+    }
+}
+
+/// A job listing, as parsed by [`crate::asvo::AsvoClient::get_jobs_with_errors`].
+pub struct ParsedJobs {
+    /// The jobs which could be parsed.
+    pub jobs: AsvoJobVec,
+    /// The records of the listing which couldn't be parsed as jobs.
+    pub errors: Vec<JsonRecordError>,
+}
+
+/// A record of a job listing which couldn't be parsed as a job.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct JsonRecordError {
+    /// The index of the record in the listing.
+    pub index: usize,
+    /// The job ID of the record, if it could be found.
+    pub jobid: Option<AsvoJobID>,
+    /// Why the record couldn't be parsed.
+    pub message: String,
+}
+
+impl std::fmt::Display for JsonRecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.jobid {
+            Some(jobid) => write!(
+                f,
+                "Couldn't parse record {} (ASVO job ID {}) of the job listing: {}",
+                self.index, jobid, self.message
+            ),
+            None => write!(
+                f,
+                "Couldn't parse record {} of the job listing: {}",
+                self.index, self.message
+            ),
+        }
+    }
 }
 
 /// A vector of ASVO jobs.
@@ -111,8 +650,23 @@ pub struct AsvoJob {
 pub struct AsvoJobVec(pub Vec<AsvoJob>);
 
 impl AsvoJobVec {
-    /// Render a slice of `AsvoJob` in a pretty-printed table.
+    /// Render a slice of `AsvoJob` in a pretty-printed table, styled only if
+    /// stdout is a terminal.
     pub fn list(self) {
+        self.list_with_colour(None)
+    }
+
+    /// Render a slice of `AsvoJob` in a pretty-printed table. `Some(true)`
+    /// forces the styling even if stdout isn't a terminal (e.g. when piping
+    /// to `less -R`), `Some(false)` disables it, and `None` styles the table
+    /// only if stdout is a terminal.
+    pub fn list_with_colour(self, colour: Option<bool>) {
+        self.list_with_style(colour, ColourScheme::Default)
+    }
+
+    /// As [AsvoJobVec::list_with_colour], but with the colours of the table
+    /// chosen by `scheme`.
+    pub fn list_with_style(self, colour: Option<bool>, scheme: ColourScheme) {
         if self.0.is_empty() {
             println!("You have no jobs.");
         } else {
@@ -130,21 +684,10 @@ impl AsvoJobVec {
                 table.add_row(Row::new(vec![
                     Cell::new(j.jobid.to_string().as_str()),
                     Cell::new(j.obsid.to_string().as_str()),
-                    Cell::new(j.jtype.to_string().as_str()).style_spec(match j.jtype {
-                        AsvoJobType::Conversion => "Fb",
-                        AsvoJobType::DownloadVisibilities => "Fb",
-                        AsvoJobType::DownloadMetadata => "Fy",
-                        AsvoJobType::DownloadVoltage => "Fm",
-                        AsvoJobType::CancelJob => "Fr",
-                    }),
-                    Cell::new(j.state.to_string().as_str()).style_spec(match j.state {
-                        AsvoJobState::Queued => "Fm",
-                        AsvoJobState::Processing => "Fb",
-                        AsvoJobState::Ready => "Fg",
-                        AsvoJobState::Error(_) => "Fr",
-                        AsvoJobState::Expired => "Fr",
-                        AsvoJobState::Cancelled => "Fr",
-                    }),
+                    Cell::new(j.jtype.to_string().as_str())
+                        .style_spec(get_job_type_table_style(&j.jtype, scheme)),
+                    Cell::new(j.describe_state().as_str())
+                        .style_spec(get_job_state_table_style(&j.state, scheme)),
                     Cell::new(
                         match &j.files {
                             None => "".to_string(),
@@ -158,16 +701,53 @@ impl AsvoJobVec {
                         }
                         .as_str(),
                     ),
-                    Cell::new(
-                        match j.files {
-                            None => "".to_string(),
-                            Some(v) => v.first().unwrap().r#type.to_string(),
-                        }
-                        .as_str(),
-                    ),
+                    Cell::new(&j.file_deliveries()),
                 ]));
             }
-            table.printstd();
+            match colour {
+                None => table.printstd(),
+                Some(force) => {
+                    let _ = if force {
+                        table.print_tty(true)
+                    } else {
+                        table.print(&mut std::io::stdout())
+                    };
+                }
+            }
+        }
+    }
+
+    /// Render the jobs as a GitHub-flavoured Markdown table, with the same
+    /// columns as [AsvoJobVec::list], e.g. for pasting into an issue.
+    pub fn markdown(self) -> String {
+        // Pipes would end a cell early (e.g. in an error message).
+        let escape = |s: String| s.replace('|', "\\|");
+        let mut out =
+            "| Job ID | Obsid | Job Type | Job State | File Size | Delivery |\n".to_string();
+        out.push_str("| --- | --- | --- | --- | --- | --- |\n");
+        for j in self.0 {
+            let size = match &j.files {
+                None => "".to_string(),
+                Some(_) => bytesize::ByteSize(j.total_size()).to_string_as(true),
+            };
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} |\n",
+                j.jobid,
+                j.obsid,
+                j.jtype,
+                escape(j.describe_state()),
+                size,
+                j.field(AsvoJobField::Delivery)
+            ));
+        }
+        out
+    }
+
+    /// Print a single field of each `AsvoJob`, one per line. Useful for
+    /// scripting.
+    pub fn list_field(self, field: AsvoJobField) {
+        for j in self.0 {
+            println!("{}", j.field(field));
         }
     }
 
@@ -180,6 +760,69 @@ impl AsvoJobVec {
         serde_json::to_string(&AsvoJobMap::from(self).0)
     }
 
+    /// As [AsvoJobVec::json], but with only the given fields of each job, to
+    /// keep the output small. The fields are named as in the full JSON; the
+    /// size is the total size of the job's files in bytes. Selecting the state
+    /// also includes the `errorCode` of failed jobs.
+    pub fn json_fields(self, fields: &[AsvoJobField]) -> Result<String, serde_json::Error> {
+        let mut map = BTreeMap::new();
+        for j in self.0 {
+            let size = j.total_size();
+            let mut full = match serde_json::to_value(&j)? {
+                serde_json::Value::Object(o) => o,
+                _ => unreachable!("jobs are serialised as objects"),
+            };
+            full.insert("size".to_string(), size.into());
+            let mut reduced = serde_json::Map::new();
+            for field in fields {
+                let keys: &[&str] = match field {
+                    AsvoJobField::JobID => &["jobId"],
+                    AsvoJobField::Obsid => &["obsid"],
+                    AsvoJobField::Type => &["jobType"],
+                    AsvoJobField::State => &["jobState", "errorCode"],
+                    AsvoJobField::Size => &["size"],
+                    AsvoJobField::Delivery => &["delivery"],
+                };
+                for &key in keys {
+                    if let Some(v) = full.remove(key) {
+                        reduced.insert(key.to_string(), v);
+                    }
+                }
+            }
+            map.insert(j.jobid, reduced);
+        }
+        serde_json::to_string(&map)
+    }
+
+    /// Get the final state of each ASVO job in JSON form, as an array of
+    /// `{jobid, obsid, state, files: [{url, path, size, sha1}]}` objects. Files
+    /// are only listed for ready jobs, so download URLs can be used directly.
+    pub fn final_state_json(self) -> Result<String, serde_json::Error> {
+        let report: Vec<JobReport> = self
+            .0
+            .iter()
+            .map(|j| JobReport {
+                jobid: j.jobid,
+                obsid: j.obsid,
+                state: j.describe_state(),
+                error_code: j.error_code,
+                files: match (&j.state, &j.files) {
+                    (AsvoJobState::Ready, Some(files)) => files
+                        .iter()
+                        .map(|f| FileReport {
+                            url: f.url.as_deref(),
+                            path: f.path.as_deref(),
+                            size: f.size,
+                            sha1: f.sha1.as_deref(),
+                        })
+                        .collect(),
+                    _ => vec![],
+                },
+            })
+            .collect();
+        serde_json::to_string(&report)
+    }
+
     /// Convert the vector to a map.
     ///
     /// If the situation should arise that your job listing has an ASVO job ID
@@ -189,6 +832,34 @@ impl AsvoJobVec {
         AsvoJobMap::from(self)
     }
 
+    /// Get the ID of the most recent job (i.e. with the highest job ID)
+    /// associated with an obsid, if there is one.
+    pub fn latest_jobid(&self, obsid: Obsid) -> Option<AsvoJobID> {
+        self.0
+            .iter()
+            .filter(|j| j.obsid == obsid)
+            .map(|j| j.jobid)
+            .max()
+    }
+
+    /// Split the jobs into those which are ready to download, and those which
+    /// aren't (e.g. queued, processing or failed). The order of the jobs is
+    /// kept.
+    pub fn partition_ready(self) -> (AsvoJobVec, AsvoJobVec) {
+        let (ready, not_ready) = self
+            .0
+            .into_iter()
+            .partition(|j| j.state == AsvoJobState::Ready);
+        (AsvoJobVec(ready), AsvoJobVec(not_ready))
+    }
+
+    /// Keep only the jobs whose job IDs are in `range`. As job IDs increase
+    /// monotonically, this is a way of finding jobs submitted around the same
+    /// time.
+    pub fn retain_jobid_range(self, range: impl RangeBounds<AsvoJobID>) -> Self {
+        self.retain(|j| range.contains(&j.jobid))
+    }
+
     /// filter out any jobs that don't match jobids
     pub fn retain(mut self, predicate: impl Fn(&AsvoJob) -> bool) -> Self {
         // if we wanted to use a nightly:
@@ -198,6 +869,28 @@ impl AsvoJobVec {
     }
 }
 
+/// The final state of an ASVO job; see [AsvoJobVec::final_state_json].
+#[derive(Serialize)]
+struct JobReport<'a> {
+    jobid: AsvoJobID,
+    obsid: Obsid,
+    state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<u32>,
+    files: Vec<FileReport<'a>>,
+}
+
+/// A downloadable file of a ready ASVO job; see [AsvoJobVec::final_state_json].
+#[derive(Serialize)]
+struct FileReport<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    size: u64,
+    sha1: Option<&'a str>,
+}
+
 /// A `BTreeMap` of ASVO job IDs against their jobs. Useful for efficiently
 /// isolating specific jobs.
 ///
@@ -257,13 +950,13 @@ impl std::fmt::Display for AsvoJob {
             obsid=self.obsid,
             jobid=self.jobid,
             type=self.jtype,
-            state=self.state,
+            state=self.describe_state(),
             files=self.files,
         )
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
 pub enum Delivery {
     /// "Deliver" the ASVO job to "the cloud" so it can be downloaded from
     /// anywhere.
@@ -272,6 +965,10 @@ pub enum Delivery {
     /// Deliver the ASVO job to the /scratch filesystem at the Pawsey
     /// Supercomputing Centre.
     Scratch,
+
+    /// A delivery type reported by the ASVO that giant-squid doesn't know
+    /// about. Jobs with this delivery can be listed, but not downloaded.
+    Other(String),
 }
 
 impl Delivery {
@@ -306,6 +1003,7 @@ impl std::fmt::Display for Delivery {
             match self {
                 Delivery::Acacia => "acacia",
                 Delivery::Scratch => "scratch",
+                Delivery::Other(d) => d,
             }
         )
     }
@@ -318,14 +1016,89 @@ pub enum DeliveryFormat {
 }
 
 impl DeliveryFormat {
+    /// Get the delivery format from `d` (e.g. a command-line argument), or, if
+    /// that's not given, the `GIANT_SQUID_DELIVERY_FORMAT` environment
+    /// variable. `None` means the ASVO's default format is used.
     pub fn validate<S: AsRef<str>>(d: Option<S>) -> Result<Option<DeliveryFormat>, AsvoError> {
-        match (d, std::env::var("GIANT_SQUID_DELIVERY_FORMAT")) {
+        Self::validate_with_env(d, std::env::var("GIANT_SQUID_DELIVERY_FORMAT"))
+    }
+
+    /// As [DeliveryFormat::validate], but the delivery format for this type of
+    /// job in its environment variable (e.g.
+    /// `GIANT_SQUID_CONV_DELIVERY_FORMAT` for conversion jobs) takes
+    /// precedence over `GIANT_SQUID_DELIVERY_FORMAT`. Either variable may be
+    /// "none", to use the ASVO's default format.
+    pub fn validate_for<S: AsRef<str>>(
+        d: Option<S>,
+        job_type: &AsvoJobType,
+    ) -> Result<Option<DeliveryFormat>, AsvoError> {
+        let global = std::env::var("GIANT_SQUID_DELIVERY_FORMAT");
+        match Self::env_var_for(job_type) {
+            Some(var) => {
+                let type_env = std::env::var(var);
+                Self::validate_with_envs(d, var, type_env, global)
+            }
+            None => Self::validate_with_env(d, global),
+        }
+    }
+
+    /// The environment variable with the default delivery format for a type
+    /// of job, if it can have one.
+    fn env_var_for(job_type: &AsvoJobType) -> Option<&'static str> {
+        match job_type {
+            AsvoJobType::Conversion => Some("GIANT_SQUID_CONV_DELIVERY_FORMAT"),
+            AsvoJobType::DownloadVisibilities => Some("GIANT_SQUID_VIS_DELIVERY_FORMAT"),
+            AsvoJobType::DownloadMetadata => Some("GIANT_SQUID_META_DELIVERY_FORMAT"),
+            // Voltage jobs don't take a delivery format.
+            AsvoJobType::DownloadVoltage | AsvoJobType::CancelJob => None,
+        }
+    }
+
+    /// As [DeliveryFormat::validate_for], but with the values of the
+    /// environment variables given: `type_env` is the value of `var`, and
+    /// `env` is the value of `GIANT_SQUID_DELIVERY_FORMAT`.
+    pub(crate) fn validate_with_envs<S: AsRef<str>>(
+        d: Option<S>,
+        var: &str,
+        type_env: Result<String, std::env::VarError>,
+        env: Result<String, std::env::VarError>,
+    ) -> Result<Option<DeliveryFormat>, AsvoError> {
+        if d.is_none() {
+            let invalid = |value: String| AsvoError::InvalidDeliveryFormatVar {
+                var: var.to_string(),
+                value,
+            };
+            match type_env {
+                Ok(v) => {
+                    return match v.as_str() {
+                        "tar" => Ok(Some(DeliveryFormat::Tar)),
+                        "none" => Ok(None),
+                        _ => Err(invalid(v)),
+                    }
+                }
+                Err(std::env::VarError::NotUnicode(v)) => {
+                    return Err(invalid(v.to_string_lossy().to_string()))
+                }
+                Err(std::env::VarError::NotPresent) => (),
+            }
+        }
+        Self::validate_with_env(d, env)
+    }
+
+    /// As [DeliveryFormat::validate], but with the value of the environment
+    /// variable given in `env`.
+    pub(crate) fn validate_with_env<S: AsRef<str>>(
+        d: Option<S>,
+        env: Result<String, std::env::VarError>,
+    ) -> Result<Option<DeliveryFormat>, AsvoError> {
+        match (d, env) {
             (Some(d), _) => match d.as_ref() {
                 "tar" => Ok(Some(DeliveryFormat::Tar)),
                 d => Err(AsvoError::InvalidDeliveryFormat(d.to_string())),
             },
             (None, Ok(d)) => match d.as_str() {
                 "tar" => Ok(Some(DeliveryFormat::Tar)),
+                "none" => Ok(None),
                 d => Err(AsvoError::InvalidDeliveryFormatEnv(d.to_string())),
             },
             (None, Err(std::env::VarError::NotPresent)) => Ok(None),
@@ -352,6 +1125,69 @@ impl std::fmt::Display for DeliveryFormat {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_delivery_format_validate_with_env() {
+        use std::env::VarError;
+
+        let validate = DeliveryFormat::validate_with_env::<&str>;
+        assert_eq!(
+            validate(None, Ok("tar".to_string())).unwrap(),
+            Some(DeliveryFormat::Tar)
+        );
+        assert_eq!(validate(None, Err(VarError::NotPresent)).unwrap(), None);
+        // The argument takes precedence over the environment variable.
+        assert_eq!(
+            validate(Some("tar"), Ok("zip".to_string())).unwrap(),
+            Some(DeliveryFormat::Tar)
+        );
+        assert!(matches!(
+            validate(None, Ok("zip".to_string())),
+            Err(AsvoError::InvalidDeliveryFormatEnv(s)) if s == "zip"
+        ));
+        assert!(matches!(
+            validate(Some("zip"), Err(VarError::NotPresent)),
+            Err(AsvoError::InvalidDeliveryFormat(s)) if s == "zip"
+        ));
+    }
+
+    #[test]
+    fn test_delivery_format_validate_with_envs() {
+        use std::env::VarError;
+
+        let var = "GIANT_SQUID_META_DELIVERY_FORMAT";
+        let validate = |d, type_env: Result<&str, VarError>, env: Result<&str, VarError>| {
+            DeliveryFormat::validate_with_envs::<&str>(
+                d,
+                var,
+                type_env.map(str::to_string),
+                env.map(str::to_string),
+            )
+        };
+        // The argument takes precedence over everything.
+        assert_eq!(
+            validate(Some("tar"), Ok("none"), Ok("none")).unwrap(),
+            Some(DeliveryFormat::Tar)
+        );
+        // The job type's variable takes precedence over the global one.
+        assert_eq!(validate(None, Ok("none"), Ok("tar")).unwrap(), None);
+        assert_eq!(
+            validate(None, Ok("tar"), Err(VarError::NotPresent)).unwrap(),
+            Some(DeliveryFormat::Tar)
+        );
+        assert_eq!(
+            validate(None, Err(VarError::NotPresent), Ok("tar")).unwrap(),
+            Some(DeliveryFormat::Tar)
+        );
+        assert_eq!(
+            validate(None, Err(VarError::NotPresent), Ok("none")).unwrap(),
+            None
+        );
+        assert!(matches!(
+            validate(None, Ok("zip"), Ok("tar")),
+            Err(AsvoError::InvalidDeliveryFormatVar { var: v, value }) if v == var && value == "zip"
+        ));
+    }
+
     #[test]
     fn test_asvo_job_state_fromstr() {
         assert!(matches!(
@@ -364,6 +1200,382 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_output_filename() {
+        let mut f = AsvoFilesArray {
+            r#type: Delivery::Acacia,
+            url: Some("https://ingest.pawsey.org.au/mwa-asvo/1339896408_575929_vis.tar?AWSAccessKeyId=0f61c75cd1184e5abc76500d71758927".to_string()),
+            path: None,
+            size: 0,
+            sha1: None,
+        };
+        assert_eq!(f.output_filename().unwrap(), "1339896408_575929_vis.tar");
+
+        f.url = Some("not a url".to_string());
+        assert!(matches!(f.output_filename(), Err(AsvoError::NoFilename(_))));
+
+        f.url = Some("https://ingest.pawsey.org.au/".to_string());
+        assert!(matches!(f.output_filename(), Err(AsvoError::NoFilename(_))));
+
+        f.r#type = Delivery::Scratch;
+        f.path = Some("/scratch/mwaops/asvo/575929".to_string());
+        assert_eq!(f.output_filename().unwrap(), "575929");
+
+        f.path = None;
+        assert!(matches!(f.output_filename(), Err(AsvoError::NoFilename(_))));
+    }
+
+    #[test]
+    fn test_download_urls_and_total_size() {
+        let file = |url: Option<&str>, size| AsvoFilesArray {
+            r#type: Delivery::Acacia,
+            url: url.map(|u| u.to_string()),
+            path: None,
+            size,
+            sha1: None,
+        };
+        let mut job = AsvoJob {
+            obsid: Obsid::validate(1065880128).unwrap(),
+            jobid: 1,
+            jtype: AsvoJobType::DownloadVisibilities,
+            state: AsvoJobState::Ready,
+            error_code: None,
+            files: None,
+            details: Default::default(),
+        };
+        assert!(job.download_urls().is_empty());
+        assert_eq!(job.total_size(), 0);
+
+        job.files = Some(vec![
+            file(Some("https://example.com/1.tar"), 100),
+            file(None, 20),
+            file(Some("https://example.com/2.tar"), 3),
+        ]);
+        assert_eq!(
+            job.download_urls(),
+            vec!["https://example.com/1.tar", "https://example.com/2.tar"]
+        );
+        assert_eq!(job.total_size(), 123);
+    }
+
+    #[test]
+    fn test_file_deliveries() {
+        let file = |r#type| AsvoFilesArray {
+            r#type,
+            url: None,
+            path: None,
+            size: 0,
+            sha1: None,
+        };
+        let mut job = AsvoJob {
+            obsid: Obsid::validate(1065880128).unwrap(),
+            jobid: 1,
+            jtype: AsvoJobType::DownloadVisibilities,
+            state: AsvoJobState::Ready,
+            error_code: None,
+            files: None,
+            details: Default::default(),
+        };
+        assert_eq!(job.file_deliveries(), "");
+
+        job.files = Some(vec![]);
+        assert_eq!(job.file_deliveries(), "");
+
+        job.files = Some(vec![file(Delivery::Acacia), file(Delivery::Acacia)]);
+        assert_eq!(job.file_deliveries(), "acacia");
+
+        job.files = Some(vec![
+            file(Delivery::Acacia),
+            file(Delivery::Scratch),
+            file(Delivery::Acacia),
+        ]);
+        assert_eq!(job.file_deliveries(), "acacia+scratch");
+        assert_eq!(job.field(AsvoJobField::Delivery), "acacia+scratch");
+    }
+
+    #[test]
+    fn test_final_state_json() {
+        let file = AsvoFilesArray {
+            r#type: Delivery::Acacia,
+            url: Some("https://example.com/1065880128_1.tar".to_string()),
+            path: None,
+            size: 100,
+            sha1: Some("abc".to_string()),
+        };
+        let jobs = AsvoJobVec(vec![
+            AsvoJob {
+                obsid: Obsid::validate(1065880128).unwrap(),
+                jobid: 1,
+                jtype: AsvoJobType::DownloadVisibilities,
+                state: AsvoJobState::Ready,
+                error_code: None,
+                files: Some(vec![file.clone()]),
+                details: Default::default(),
+            },
+            AsvoJob {
+                obsid: Obsid::validate(1065880128).unwrap(),
+                jobid: 2,
+                jtype: AsvoJobType::DownloadVisibilities,
+                state: AsvoJobState::Error("oops".to_string()),
+                error_code: Some(42),
+                files: Some(vec![file]),
+                details: Default::default(),
+            },
+        ]);
+        assert_eq!(
+            jobs.final_state_json().unwrap(),
+            r#"[{"jobid":1,"obsid":1065880128,"state":"Ready","files":[{"url":"https://example.com/1065880128_1.tar","size":100,"sha1":"abc"}]},{"jobid":2,"obsid":1065880128,"state":"Error [code 42]: oops","error_code":42,"files":[]}]"#
+        );
+    }
+
+    #[test]
+    fn test_latest_jobid() {
+        let job = |obsid, jobid| AsvoJob {
+            obsid: Obsid::validate(obsid).unwrap(),
+            jobid,
+            jtype: AsvoJobType::DownloadVisibilities,
+            state: AsvoJobState::Queued,
+            error_code: None,
+            files: None,
+            details: Default::default(),
+        };
+        let jobs = AsvoJobVec(vec![
+            job(1065880128, 5),
+            job(1065880128, 12),
+            job(1065880248, 20),
+            job(1065880128, 7),
+        ]);
+        assert_eq!(
+            jobs.latest_jobid(Obsid::validate(1065880128).unwrap()),
+            Some(12)
+        );
+        assert_eq!(
+            jobs.latest_jobid(Obsid::validate(1065880248).unwrap()),
+            Some(20)
+        );
+        assert_eq!(
+            jobs.latest_jobid(Obsid::validate(1065880368).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_colour_scheme_from_str() {
+        assert_eq!(
+            "Deuteranopia".parse::<ColourScheme>().unwrap(),
+            ColourScheme::Deuteranopia
+        );
+        assert_eq!("MONO".parse::<ColourScheme>().unwrap(), ColourScheme::Mono);
+        assert!(matches!(
+            "rainbow".parse::<ColourScheme>(),
+            Err(AsvoError::InvalidColourScheme { .. })
+        ));
+    }
+
+    #[test]
+    fn test_job_state_table_style() {
+        let ready = AsvoJobState::Ready;
+        let error = AsvoJobState::Error("oops".to_string());
+        assert_eq!(
+            get_job_state_table_style(&ready, ColourScheme::Default),
+            "Fg"
+        );
+        assert_eq!(
+            get_job_state_table_style(&error, ColourScheme::Default),
+            "Fr"
+        );
+        // Nothing relies on red-green contrast.
+        for scheme in [ColourScheme::Deuteranopia, ColourScheme::Mono] {
+            for state in [&ready, &error] {
+                let style = get_job_state_table_style(state, scheme);
+                assert!(!style.contains("Fr") && !style.contains("Fg"));
+            }
+            let cancel = get_job_type_table_style(&AsvoJobType::CancelJob, scheme);
+            assert!(!cancel.contains("Fr"));
+        }
+        assert!(!get_job_state_table_style(&ready, ColourScheme::Mono).contains('F'));
+    }
+
+    #[test]
+    fn test_markdown() {
+        let jobs = AsvoJobVec(vec![
+            AsvoJob {
+                obsid: Obsid::validate(1065880128).unwrap(),
+                jobid: 1,
+                jtype: AsvoJobType::DownloadVisibilities,
+                state: AsvoJobState::Ready,
+                error_code: None,
+                files: Some(vec![AsvoFilesArray {
+                    r#type: Delivery::Acacia,
+                    url: Some("https://example.com/1.tar".to_string()),
+                    path: None,
+                    size: 2048,
+                    sha1: None,
+                }]),
+                details: Default::default(),
+            },
+            AsvoJob {
+                obsid: Obsid::validate(1065880248).unwrap(),
+                jobid: 2,
+                jtype: AsvoJobType::Conversion,
+                state: AsvoJobState::Error("bad | input".to_string()),
+                error_code: None,
+                files: None,
+                details: Default::default(),
+            },
+        ]);
+        assert_eq!(
+            jobs.markdown(),
+            "| Job ID | Obsid | Job Type | Job State | File Size | Delivery |\n\
+             | --- | --- | --- | --- | --- | --- |\n\
+             | 1 | 1065880128 | Download Visibilities | Ready | 2.0 kiB | acacia |\n\
+             | 2 | 1065880248 | Conversion | Error: bad \\| input |  |  |\n"
+        );
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let jobs = || {
+            AsvoJobVec(vec![AsvoJob {
+                obsid: Obsid::validate(1065880128).unwrap(),
+                jobid: 1,
+                jtype: AsvoJobType::Conversion,
+                state: AsvoJobState::Error("oops".to_string()),
+                error_code: Some(3),
+                files: None,
+                details: AsvoJobDetails {
+                    delivery: Some("acacia".to_string()),
+                    ..Default::default()
+                },
+            }])
+        };
+        assert_eq!(
+            jobs()
+                .json_fields(&[AsvoJobField::JobID, AsvoJobField::State])
+                .unwrap(),
+            r#"{"1":{"errorCode":3,"jobId":1,"jobState":{"Error":"oops"}}}"#
+        );
+        assert_eq!(
+            jobs()
+                .json_fields(&[AsvoJobField::Size, AsvoJobField::Delivery])
+                .unwrap(),
+            r#"{"1":{"delivery":"acacia","size":0}}"#
+        );
+    }
+
+    #[test]
+    fn test_partition_ready() {
+        let job = |jobid, state| AsvoJob {
+            obsid: Obsid::validate(1065880128).unwrap(),
+            jobid,
+            jtype: AsvoJobType::DownloadVisibilities,
+            state,
+            error_code: None,
+            files: None,
+            details: Default::default(),
+        };
+        let jobs = AsvoJobVec(vec![
+            job(1, AsvoJobState::Ready),
+            job(2, AsvoJobState::Queued),
+            job(3, AsvoJobState::Error("oops".to_string())),
+            job(4, AsvoJobState::Ready),
+        ]);
+        let (ready, not_ready) = jobs.partition_ready();
+        let jobids = |jobs: &AsvoJobVec| jobs.0.iter().map(|j| j.jobid).collect::<Vec<_>>();
+        assert_eq!(jobids(&ready), vec![1, 4]);
+        assert_eq!(jobids(&not_ready), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_retain_jobid_range() {
+        let job = |jobid| AsvoJob {
+            obsid: Obsid::validate(1065880128).unwrap(),
+            jobid,
+            jtype: AsvoJobType::DownloadVisibilities,
+            state: AsvoJobState::Queued,
+            error_code: None,
+            files: None,
+            details: Default::default(),
+        };
+        let jobids = |jobs: AsvoJobVec| jobs.0.iter().map(|j| j.jobid).collect::<Vec<_>>();
+        let jobs = || AsvoJobVec(vec![job(5), job(12), job(20), job(7)]);
+        assert_eq!(jobids(jobs().retain_jobid_range(7..=12)), vec![12, 7]);
+        assert_eq!(jobids(jobs().retain_jobid_range(12..)), vec![12, 20]);
+        assert_eq!(jobids(jobs().retain_jobid_range(..=7)), vec![5, 7]);
+        assert_eq!(jobids(jobs().retain_jobid_range(..)), vec![5, 12, 20, 7]);
+        assert!(jobids(jobs().retain_jobid_range(13..=19)).is_empty());
+    }
+
+    #[test]
+    fn test_job_preference_from_str() {
+        assert_eq!(
+            "Newest".parse::<JobPreference>().unwrap(),
+            JobPreference::Newest
+        );
+        assert_eq!(
+            "oldest".parse::<JobPreference>().unwrap(),
+            JobPreference::Oldest
+        );
+        assert_eq!(
+            "ERROR".parse::<JobPreference>().unwrap(),
+            JobPreference::Error
+        );
+        assert!(matches!(
+            "latest".parse::<JobPreference>(),
+            Err(AsvoError::InvalidJobPreference { .. })
+        ));
+    }
+
+    #[test]
+    fn test_output_template() {
+        let obsid = Obsid::validate(1065880128).unwrap();
+        let t = OutputTemplate::from_str("{obsid}/{jobid}_{filename}").unwrap();
+        assert_eq!(
+            t.render(obsid, 123, Path::new("a/1.fits")),
+            PathBuf::from("1065880128/123_a/1.fits")
+        );
+        let t = OutputTemplate::from_str("{filename}").unwrap();
+        assert_eq!(
+            t.render(obsid, 123, Path::new("{obsid}.fits")),
+            PathBuf::from("{obsid}.fits")
+        );
+
+        for bad in [
+            "{obsid}",
+            "{obsid}/{file}",
+            "{obsid/{filename}",
+            "obsid}/{filename}",
+            "/tmp/{filename}",
+            "../{filename}",
+            "./{filename}",
+        ] {
+            assert!(
+                matches!(
+                    OutputTemplate::from_str(bad),
+                    Err(AsvoError::InvalidOutputTemplate { .. })
+                ),
+                "{} should be invalid",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_asvo_job_field_fromstr() {
+        assert!(matches!(
+            AsvoJobField::from_str("job_id"),
+            Ok(AsvoJobField::JobID)
+        ));
+        assert!(matches!(
+            AsvoJobField::from_str("File Size"),
+            Ok(AsvoJobField::Size)
+        ));
+        assert!(matches!(
+            AsvoJobField::from_str("colour"),
+            Err(AsvoError::InvalidJobField { .. })
+        ));
+    }
+
     #[test]
     fn test_asvo_job_type_fromstr() {
         assert!(matches!(
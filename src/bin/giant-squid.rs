@@ -3,11 +3,15 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::collections::BTreeMap;
-use std::time::Duration;
-
-use anyhow::bail;
-use clap::{AppSettings, ArgAction, Parser};
-use log::{debug, info};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use clap::{AppSettings, ArgAction, Parser, Subcommand, ValueEnum};
+use log::{debug, error, info, warn};
 use simplelog::*;
 
 use mwa_giant_squid::asvo::*;
@@ -35,6 +39,119 @@ lazy_static::lazy_static! {
 #[derive(Parser, Debug)]
 #[clap(author, about = ABOUT, version)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
+struct Cli {
+    /// How to report errors. With "json", errors are printed to stderr as a
+    /// JSON object with the error's kind, message and any associated job ID,
+    /// obsid or HTTP status code.
+    #[clap(long, value_enum, global = true, default_value = "text")]
+    error_format: ErrorFormat,
+
+    /// When to style job tables with colour. With "auto", tables are only
+    /// styled if stdout is a terminal, and the CLICOLOR_FORCE (force colour
+    /// on) and NO_COLOR (force colour off) environment variables are honoured;
+    /// CLICOLOR_FORCE takes precedence.
+    #[clap(
+        long,
+        visible_alias = "color",
+        value_enum,
+        global = true,
+        default_value = "auto"
+    )]
+    colour: ColourChoice,
+
+    /// The colours used to style job tables. Options: default (green for
+    /// ready jobs, red for failed jobs), deuteranopia (blue and yellow,
+    /// avoiding red-green contrast) or mono (bold and underlined, without
+    /// colours).
+    #[clap(
+        long,
+        visible_alias = "color-scheme",
+        name = "SCHEME",
+        global = true,
+        default_value = "default"
+    )]
+    colour_scheme: ColourScheme,
+
+    /// The maximum number of connections to each host, e.g. the Acacia host
+    /// serving a job's files. Idle connections up to this limit are kept open
+    /// and reused, which avoids repeated TLS handshakes on high-latency links;
+    /// 0 disables connection reuse. By default there is no limit.
+    #[clap(long, name = "N", global = true)]
+    max_connections_per_host: Option<usize>,
+
+    /// Print an equivalent curl command to stderr for each request made to
+    /// the MWA ASVO API (logging in, listing, submitting and cancelling jobs).
+    /// The API key is redacted. Requests are still made.
+    #[clap(long, global = true)]
+    print_requests: bool,
+
+    /// How many times to retry a network operation (logging in, listing,
+    /// submitting or cancelling jobs, or downloading a file) which failed with
+    /// a transient error, e.g. a dropped connection. This can also be set with
+    /// the environment variable GIANT_SQUID_RETRIES. By default, downloads
+    /// are retried with an exponential backoff, and other operations aren't
+    /// retried.
+    #[clap(long, name = "RETRIES", global = true)]
+    retries: Option<u32>,
+
+    /// Close connections which have been idle for this many seconds, rather
+    /// than keeping them for reuse, e.g. to avoid stale connections in
+    /// long-running processes. This can also be set with the environment
+    /// variable GIANT_SQUID_POOL_IDLE_TIMEOUT. The default is 90 seconds.
+    #[clap(long, name = "IDLE_SECONDS", global = true)]
+    pool_idle_timeout: Option<u64>,
+
+    /// The column to read job IDs or obsids from, in files of job IDs or
+    /// obsids with a ".csv" extension. The first line of a CSV file must be
+    /// its header.
+    #[clap(long, name = "COLUMN", global = true, default_value = DEFAULT_OBSID_COLUMN)]
+    obsid_column: String,
+
+    /// In files of job IDs or obsids (other than CSV files), only read the
+    /// first whitespace-delimited column of each line, ignoring the rest of
+    /// the line (e.g. notes). By default, everything in the file must be a job
+    /// ID or obsid.
+    #[clap(long, global = true)]
+    first_column: bool,
+
+    /// The MWA ASVO API key to use, instead of the MWA_ASVO_API_KEY
+    /// environment variable. This is insecure, as other users can see it in
+    /// the list of running processes; only use it for throwaway runs.
+    #[clap(long, name = "KEY", global = true, hide = true)]
+    api_key: Option<String>,
+
+    #[clap(subcommand)]
+    command: Args,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColourChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColourChoice {
+    /// Whether to force colour on or off, or leave it to be detected (`None`).
+    fn resolve(self) -> Option<bool> {
+        let env = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+        match self {
+            ColourChoice::Always => Some(true),
+            ColourChoice::Never => Some(false),
+            ColourChoice::Auto if env("CLICOLOR_FORCE").is_some_and(|v| v != "0") => Some(true),
+            ColourChoice::Auto if env("NO_COLOR").is_some() => Some(false),
+            ColourChoice::Auto => None,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
 enum Args {
     /// List ASVO jobs
     #[clap(alias = "l")]
@@ -59,27 +176,193 @@ enum Args {
         #[clap(long, name = "TYPE", value_delimiter = ',')]
         types: Vec<AsvoJobType>,
 
+        /// Print only this field of each job, one per line. Options: jobid,
+        /// obsid, type, state, size (in bytes), delivery.
+        #[clap(long, name = "FIELD", conflicts_with = "json")]
+        field: Option<AsvoJobField>,
+
+        /// With --json, only include these fields of each job, to keep the
+        /// output small. Options: jobid, obsid, type, state, size (in bytes),
+        /// delivery.
+        #[clap(long, name = "FIELDS", value_delimiter = ',', requires = "json")]
+        fields: Vec<AsvoJobField>,
+
+        /// Print the jobs as a GitHub-flavoured Markdown table, e.g. for
+        /// pasting into an issue.
+        #[clap(long, conflicts_with_all = &["json", "FIELD"])]
+        markdown: bool,
+
+        /// Fail if the job IDs or obsids to filter by aren't all of this kind.
+        /// Options: obsids, jobids.
+        #[clap(long, name = "KIND")]
+        expect: Option<ExpectedIds>,
+
+        /// show only jobs with job IDs at least this big. As job IDs
+        /// increase over time, this is a way to find recent jobs.
+        #[clap(long, name = "MIN_JOBID")]
+        jobid_min: Option<AsvoJobID>,
+
+        /// show only jobs with job IDs at most this big.
+        #[clap(long, name = "MAX_JOBID")]
+        jobid_max: Option<AsvoJobID>,
+
+        /// show only jobs which are new since the last time --only-new was
+        /// used, i.e. with job IDs bigger than any seen then. The biggest job
+        /// ID seen is recorded in the marker file.
+        #[clap(long)]
+        only_new: bool,
+
+        /// The marker file used by --only-new. The default is
+        /// "giant-squid/last_listed_jobid" in $XDG_CONFIG_HOME (or
+        /// $HOME/.config).
+        #[clap(long, name = "MARKER", requires = "only-new")]
+        marker: Option<PathBuf>,
+
         /// job IDs or obsids to filter by. Files containing job IDs or
         /// obsids are also accepted.
         #[clap(name = "JOBID_OR_OBSID")]
         jobids_or_obsids: Vec<String>,
     },
 
+    /// Show everything about a single ASVO job
+    Show {
+        /// Print the job as JSON
+        #[clap(short, long)]
+        json: bool,
+
+        /// The verbosity of the program. The default is to print high-level
+        /// information.
+        #[clap(short, long, parse(from_occurrences))]
+        verbosity: u8,
+
+        /// The job ID to show.
+        #[clap(name = "JOBID")]
+        jobid: AsvoJobID,
+    },
+
     /// Download an ASVO job
     #[clap(alias = "d")]
     Download {
-        /// Which dir should downloads be written to.
-        #[clap(short, long, default_value = ".")]
-        download_dir: String,
+        /// Which dir should downloads be written to. The default is ".", but
+        /// this can be overridden with the environment variable
+        /// GIANT_SQUID_DOWNLOAD_DIR.
+        #[clap(short, long)]
+        download_dir: Option<String>,
 
         /// Don't unzip the contents from the ASVO.
         #[clap(short, long)]
         keep_zip: bool,
 
+        /// Before resuming a partial download, download its first and last
+        /// (up to) 1 MiB again and check them against the partial download,
+        /// rather than trusting it until the whole archive is verified.
+        #[clap(long, requires = "keep-zip")]
+        verify_partial: bool,
+
+        /// Extract all files directly into the download dir, ignoring any
+        /// directories in the archive. Fails if two files have the same name.
+        #[clap(long)]
+        flatten: bool,
+
+        /// Keep the modification times of files in the archive when unzipping,
+        /// rather than using the current time.
+        #[clap(long)]
+        preserve_mtime: bool,
+
+        /// Keep the setuid, setgid and sticky bits of files in the archive when
+        /// unzipping. The read, write and execute bits are always kept.
+        #[clap(long)]
+        preserve_permissions: bool,
+
+        /// After each job is downloaded, save its record from the MWA ASVO job
+        /// listing, as the ASVO gave it (including file URLs, hashes and
+        /// timestamps), as "<jobid>.json" in the download dir, for provenance.
+        #[clap(long)]
+        save_job_json: bool,
+
+        /// A template for the paths of downloaded files, relative to the
+        /// download dir, e.g. "{obsid}/{filename}". Available placeholders are
+        /// {obsid}, {jobid} and {filename} (the name of the file in the
+        /// archive, or of the archive with --keep-zip); {filename} is required.
+        #[clap(long, name = "TEMPLATE")]
+        output_template: Option<OutputTemplate>,
+
+        /// Which job to download when an obsid has more than one ready job.
+        /// Options: error (fail, as the obsid is ambiguous), newest or oldest
+        /// (by job ID).
+        #[clap(long, name = "JOB", default_value = "error")]
+        prefer: JobPreference,
+
+        /// When downloading an obsid, only consider its jobs with this
+        /// delivery (e.g. "acacia" or "scratch"), for obsids submitted to more
+        /// than one delivery. Job IDs are downloaded regardless.
+        #[clap(long, name = "DELIVERY")]
+        delivery: Option<String>,
+
+        /// Record each fully downloaded job in this manifest file, and skip
+        /// any jobs (or obsids) already recorded in it. Re-running an
+        /// interrupted batch download with the same manifest doesn't check the
+        /// jobs which have already been downloaded.
+        #[clap(long, name = "MANIFEST")]
+        resume_batch: Option<String>,
+
+        /// Stop starting downloads once the total size of the downloaded jobs
+        /// would exceed this size, e.g. "500GB" or "2 TiB". Jobs which would go
+        /// over the limit are skipped (and listed), and giant-squid exits with
+        /// an error.
+        #[clap(long, name = "SIZE")]
+        max_total_size: Option<bytesize::ByteSize>,
+
+        /// Abort and retry a file's download if nothing has been received for
+        /// this many seconds, e.g. because the connection is half-dead. This
+        /// applies to each read, not the whole download, so slow downloads
+        /// aren't affected, as long as some data keeps arriving. The default
+        /// is 30 seconds.
+        #[clap(long, name = "SECONDS", visible_alias = "timeout-per-file")]
+        stall_timeout: Option<u64>,
+
+        /// Abandon a job's download if it takes longer than this many seconds
+        /// in total, and move on to the next job. The abandoned jobs are
+        /// reported at the end. By default, there's no limit.
+        #[clap(long, name = "JOB_SECONDS")]
+        job_timeout: Option<u64>,
+
+        /// Append the SHA-1 hash of each downloaded file (or archive, with
+        /// --keep-zip) to this file, in the format of `sha1sum`, so the files
+        /// can be checked later with `sha1sum -c`.
+        #[clap(long, name = "CHECKSUM_FILE")]
+        write_checksums: Option<String>,
+
+        /// Check each downloaded file (or archive, with --keep-zip) against
+        /// the SHA-1 hashes in this file, in the format of `sha1sum`, e.g. from
+        /// a collaborator's copy. Files are matched by path or, failing that,
+        /// by name; files which aren't listed aren't checked.
+        #[clap(long, name = "SUMS_FILE")]
+        verify_against: Option<PathBuf>,
+
+        /// Append a throughput sample of each file's download to this CSV file
+        /// every 5 seconds (columns: unix_time, jobid, file, bytes,
+        /// bytes_per_sec), e.g. to graph transfer performance over time.
+        #[clap(long, name = "PERF_LOG")]
+        perf_log: Option<PathBuf>,
+
+        /// After each job is downloaded, print the absolute paths of its
+        /// downloaded files (or archives, with --keep-zip) to stdout, one per
+        /// line, e.g. to pass them to another program. Logs still go to
+        /// stderr.
+        #[clap(long)]
+        print_paths: bool,
+
         /// Don't verify the downloaded contents against the upstream hash.
         #[clap(long)]
         skip_hash: bool,
 
+        /// Keep downloading the other jobs when a job fails verification (its
+        /// hash or size doesn't match), and report all of the jobs which
+        /// failed at the end.
+        #[clap(long, conflicts_with = "skip-hash")]
+        report_all: bool,
+
         // Does nothing: hash check is enabled by default. This is for backwards compatibility
         #[clap(long, hidden = true)]
         hash: bool,
@@ -111,15 +394,59 @@ enum Args {
 
         /// Tell MWA ASVO to deliver the data in a particular format.
         /// Available value(s): `tar`. NOTE: this option does not apply if delivery = `acacia`
-        /// which is always `tar`
+        /// which is always `tar`. The default can be set with the environment
+        /// variable GIANT_SQUID_VIS_DELIVERY_FORMAT, or for all job types,
+        /// GIANT_SQUID_DELIVERY_FORMAT ("none" for the MWA ASVO's default).
         #[clap(short = 'f', long)]
         delivery_format: Option<String>,
 
+        /// The maximum number of jobs to submit at once. Be polite to the
+        /// MWA ASVO!
+        #[clap(long, default_value = "4")]
+        concurrent_submits: usize,
+
+        /// Wait this many milliseconds between successive job submissions, to
+        /// avoid overwhelming the MWA ASVO.
+        #[clap(long, name = "MS", default_value = "0")]
+        submit_delay: u64,
+
+        /// Keep submitting the remaining obsids if a submission fails, and
+        /// report the failures at the end. giant-squid still exits with an
+        /// error if any submission failed.
+        #[clap(long)]
+        continue_on_error: bool,
+
+        /// Print the job ID of each newly-created job to stdout, one per line,
+        /// as soon as each job is submitted, e.g. to pass them to another
+        /// program. Jobs which already existed aren't printed. If a submission
+        /// fails, the jobs created before it have still been printed.
+        #[clap(long)]
+        print_ids: bool,
+
+        /// Append the job ID of each newly-created job to this file, one per
+        /// line, so the jobs can be downloaded later (e.g. on another machine)
+        /// with `giant-squid download FILE`. Jobs which already existed aren't
+        /// written. If a submission fails, the jobs created before it are
+        /// still written.
+        #[clap(long, name = "IDS_FILE")]
+        write_ids: Option<PathBuf>,
+
+        /// Write a summary of the submissions to this file as JSON: the
+        /// number of obsids submitted, which already had a job, which failed
+        /// and which weren't attempted, and the outcome for each obsid.
+        #[clap(long, name = "SUMMARY_FILE")]
+        summary_json: Option<PathBuf>,
+
         /// Do not exit giant-squid until the specified obsids are ready for
         /// download.
         #[clap(short, long)]
         wait: bool,
 
+        /// Download each job into this directory as soon as it is ready, while
+        /// the other jobs continue to be processed. Implies --wait.
+        #[clap(long, name = "DIR")]
+        download: Option<String>,
+
         /// Don't actually submit; print information on what would've happened
         /// instead.
         #[clap(short = 'n', long)]
@@ -130,6 +457,12 @@ enum Args {
         #[clap(short = 'r', long, action=ArgAction::SetTrue)]
         allow_resubmit: bool,
 
+        /// Add an extra field to the job submission, e.g. "--extra key=value".
+        /// Can be given multiple times. Useful for ASVO parameters that
+        /// giant-squid doesn't yet support.
+        #[clap(long, name = "KEY=VALUE", multiple_occurrences = true)]
+        extra: Vec<String>,
+
         /// The verbosity of the program. The default is to print high-level
         /// information.
         #[clap(short, long, parse(from_occurrences))]
@@ -147,6 +480,12 @@ enum Args {
         #[clap(short, long, help = DEFAULT_CONVERSION_PARAMETERS_TEXT.as_str())]
         parameters: Option<String>,
 
+        /// Don't use the default conversion parameters; only send those given
+        /// with --parameters (or in the --from-csv file). "output" (e.g.
+        /// "output=ms") must then be given.
+        #[clap(long)]
+        no_defaults: bool,
+
         /// Tell the MWA ASVO where to deliver the job. The default is "acacia", but
         /// this can be overridden with the environment variable
         /// GIANT_SQUID_DELIVERY.
@@ -155,15 +494,67 @@ enum Args {
 
         /// Tell MWA ASVO to deliver the data in a particular format.
         /// Available value(s): `tar`. NOTE: this option does not apply if delivery = `acacia`
-        /// which is always `tar`
+        /// which is always `tar`. The default can be set with the environment
+        /// variable GIANT_SQUID_CONV_DELIVERY_FORMAT, or for all job types,
+        /// GIANT_SQUID_DELIVERY_FORMAT ("none" for the MWA ASVO's default).
         #[clap(short = 'f', long)]
         delivery_format: Option<String>,
 
+        /// A CSV file of obsids to be submitted, each with their own
+        /// parameters. The header must contain an "obsid" column; a "delivery"
+        /// column is optional, and all other columns are used as conversion
+        /// parameters (e.g. "obsid,avg_freq_res,avg_time_res,delivery"). Values
+        /// in the CSV override those given with --parameters and --delivery.
+        #[clap(long)]
+        from_csv: Option<String>,
+
+        /// The maximum number of jobs to submit at once. Be polite to the
+        /// MWA ASVO!
+        #[clap(long, default_value = "4")]
+        concurrent_submits: usize,
+
+        /// Wait this many milliseconds between successive job submissions, to
+        /// avoid overwhelming the MWA ASVO.
+        #[clap(long, name = "MS", default_value = "0")]
+        submit_delay: u64,
+
+        /// Keep submitting the remaining obsids if a submission fails, and
+        /// report the failures at the end. giant-squid still exits with an
+        /// error if any submission failed.
+        #[clap(long)]
+        continue_on_error: bool,
+
+        /// Print the job ID of each newly-created job to stdout, one per line,
+        /// as soon as each job is submitted, e.g. to pass them to another
+        /// program. Jobs which already existed aren't printed. If a submission
+        /// fails, the jobs created before it have still been printed.
+        #[clap(long)]
+        print_ids: bool,
+
+        /// Append the job ID of each newly-created job to this file, one per
+        /// line, so the jobs can be downloaded later (e.g. on another machine)
+        /// with `giant-squid download FILE`. Jobs which already existed aren't
+        /// written. If a submission fails, the jobs created before it are
+        /// still written.
+        #[clap(long, name = "IDS_FILE")]
+        write_ids: Option<PathBuf>,
+
+        /// Write a summary of the submissions to this file as JSON: the
+        /// number of obsids submitted, which already had a job, which failed
+        /// and which weren't attempted, and the outcome for each obsid.
+        #[clap(long, name = "SUMMARY_FILE")]
+        summary_json: Option<PathBuf>,
+
         /// Do not exit giant-squid until the specified obsids are ready for
         /// download.
         #[clap(short, long)]
         wait: bool,
 
+        /// Download each job into this directory as soon as it is ready, while
+        /// the other jobs continue to be processed. Implies --wait.
+        #[clap(long, name = "DIR")]
+        download: Option<String>,
+
         /// Don't actually submit; print information on what would've happened
         /// instead.
         #[clap(short = 'n', long)]
@@ -174,6 +565,12 @@ enum Args {
         #[clap(short = 'r', long, action=ArgAction::SetTrue)]
         allow_resubmit: bool,
 
+        /// Add an extra field to the job submission, e.g. "--extra key=value".
+        /// Can be given multiple times. Useful for ASVO parameters that
+        /// giant-squid doesn't yet support.
+        #[clap(long, name = "KEY=VALUE", multiple_occurrences = true)]
+        extra: Vec<String>,
+
         /// The verbosity of the program. The default is to print high-level
         /// information.
         #[clap(short, long, parse(from_occurrences))]
@@ -196,15 +593,61 @@ enum Args {
 
         /// Tell MWA ASVO to deliver the data in a particular format.
         /// Available value(s): `tar`. NOTE: this option does not apply if delivery = `acacia`
-        /// which is always `tar`
+        /// which is always `tar`. The default can be set with the environment
+        /// variable GIANT_SQUID_META_DELIVERY_FORMAT, or for all job types,
+        /// GIANT_SQUID_DELIVERY_FORMAT ("none" for the MWA ASVO's default).
         #[clap(short = 'f', long)]
         delivery_format: Option<String>,
 
+        /// The maximum number of jobs to submit at once. Be polite to the
+        /// MWA ASVO!
+        #[clap(long, default_value = "4")]
+        concurrent_submits: usize,
+
+        /// Wait this many milliseconds between successive job submissions, to
+        /// avoid overwhelming the MWA ASVO.
+        #[clap(long, name = "MS", default_value = "0")]
+        submit_delay: u64,
+
+        /// Keep submitting the remaining obsids if a submission fails, and
+        /// report the failures at the end. giant-squid still exits with an
+        /// error if any submission failed.
+        #[clap(long)]
+        continue_on_error: bool,
+
+        /// Print the job ID of each newly-created job to stdout, one per line,
+        /// as soon as each job is submitted, e.g. to pass them to another
+        /// program. Jobs which already existed aren't printed. If a submission
+        /// fails, the jobs created before it have still been printed. Can't be used with
+        /// --download, which prints the paths of the metafits files instead.
+        #[clap(long, conflicts_with = "DIR")]
+        print_ids: bool,
+
+        /// Append the job ID of each newly-created job to this file, one per
+        /// line, so the jobs can be downloaded later (e.g. on another machine)
+        /// with `giant-squid download FILE`. Jobs which already existed aren't
+        /// written. If a submission fails, the jobs created before it are
+        /// still written.
+        #[clap(long, name = "IDS_FILE")]
+        write_ids: Option<PathBuf>,
+
+        /// Write a summary of the submissions to this file as JSON: the
+        /// number of obsids submitted, which already had a job, which failed
+        /// and which weren't attempted, and the outcome for each obsid.
+        #[clap(long, name = "SUMMARY_FILE")]
+        summary_json: Option<PathBuf>,
+
         /// Do not exit giant-squid until the specified obsids are ready for
         /// download.
         #[clap(short, long)]
         wait: bool,
 
+        /// Download each job into this directory as soon as it is ready, while
+        /// the other jobs continue to be processed. The paths of the downloaded
+        /// metafits files are printed to stdout. Implies --wait.
+        #[clap(long, name = "DIR")]
+        download: Option<String>,
+
         /// Don't actually submit; print information on what would've happened
         /// instead.
         #[clap(short = 'n', long)]
@@ -215,6 +658,12 @@ enum Args {
         #[clap(short = 'r', long, action=ArgAction::SetTrue)]
         allow_resubmit: bool,
 
+        /// Add an extra field to the job submission, e.g. "--extra key=value".
+        /// Can be given multiple times. Useful for ASVO parameters that
+        /// giant-squid doesn't yet support.
+        #[clap(long, name = "KEY=VALUE", multiple_occurrences = true)]
+        extra: Vec<String>,
+
         /// The verbosity of the program. The default is to print high-level
         /// information.
         #[clap(short, long, parse(from_occurrences))]
@@ -250,6 +699,38 @@ enum Args {
         #[clap(short = 't', long)]
         to_channel: Option<i32>,
 
+        /// Wait this many milliseconds between successive job submissions, to
+        /// avoid overwhelming the MWA ASVO.
+        #[clap(long, name = "MS", default_value = "0")]
+        submit_delay: u64,
+
+        /// Keep submitting the remaining obsids if a submission fails, and
+        /// report the failures at the end. giant-squid still exits with an
+        /// error if any submission failed.
+        #[clap(long)]
+        continue_on_error: bool,
+
+        /// Print the job ID of each newly-created job to stdout, one per line,
+        /// as soon as each job is submitted, e.g. to pass them to another
+        /// program. Jobs which already existed aren't printed. If a submission
+        /// fails, the jobs created before it have still been printed.
+        #[clap(long)]
+        print_ids: bool,
+
+        /// Append the job ID of each newly-created job to this file, one per
+        /// line, so the jobs can be downloaded later (e.g. on another machine)
+        /// with `giant-squid download FILE`. Jobs which already existed aren't
+        /// written. If a submission fails, the jobs created before it are
+        /// still written.
+        #[clap(long, name = "IDS_FILE")]
+        write_ids: Option<PathBuf>,
+
+        /// Write a summary of the submissions to this file as JSON: the
+        /// number of obsids submitted, which already had a job, which failed
+        /// and which weren't attempted, and the outcome for each obsid.
+        #[clap(long, name = "SUMMARY_FILE")]
+        summary_json: Option<PathBuf>,
+
         /// Do not exit giant-squid until the specified obsids are ready for
         /// download.
         #[clap(short, long)]
@@ -265,6 +746,12 @@ enum Args {
         #[clap(short = 'r', long, action=ArgAction::SetTrue)]
         allow_resubmit: bool,
 
+        /// Add an extra field to the job submission, e.g. "--extra key=value".
+        /// Can be given multiple times. Useful for ASVO parameters that
+        /// giant-squid doesn't yet support.
+        #[clap(long, name = "KEY=VALUE", multiple_occurrences = true)]
+        extra: Vec<String>,
+
         /// The verbosity of the program. The default is to print high-level
         /// information.
         #[clap(short, long, parse(from_occurrences))]
@@ -279,20 +766,122 @@ enum Args {
     /// Wait for ASVO jobs to complete, return the urls
     #[clap(alias = "w")]
     Wait {
-        /// Print the jobs as a simple JSON after waiting
+        /// After waiting, print the final state of each job as JSON, including
+        /// the URLs, sizes and hashes of the files of ready jobs
         #[clap(short, long)]
         json: bool,
 
+        /// While waiting, print a line of JSON to stdout every time a job
+        /// changes state, with the fields "timestamp" (unix seconds),
+        /// "jobid", "obsid", "old_state" and "new_state".
+        #[clap(long)]
+        events_json: bool,
+
         /// The verbosity of the program. The default is to print high-level
         /// information.
         #[clap(short, long, parse(from_occurrences))]
         verbosity: u8,
 
-        /// The jobs to wait for. Files containing jobs are also
-        /// accepted.
+        /// The job IDs or obsids to wait for. An obsid waits for its most
+        /// recent job. Files containing job IDs or obsids are also accepted.
         #[clap(name = "JOB")]
         jobs: Vec<String>,
     },
+
+    /// Cancel ASVO jobs
+    #[clap(alias = "c")]
+    Cancel {
+        /// Print the result of cancelling each job as JSON: "cancelled",
+        /// "not_found" or "validation_error"
+        #[clap(short, long)]
+        json: bool,
+
+        /// Don't actually cancel; list the jobs which would be cancelled
+        /// instead (as JSON with --json).
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+
+        /// The verbosity of the program. The default is to print high-level
+        /// information.
+        #[clap(short, long, parse(from_occurrences))]
+        verbosity: u8,
+
+        /// The job IDs to cancel. Files containing job IDs are also accepted.
+        #[clap(name = "JOBID")]
+        jobs: Vec<String>,
+    },
+
+    /// Print how much data downloading ASVO jobs would fetch
+    #[clap(alias = "du")]
+    Size {
+        /// Print the size of each job and the total as JSON, in bytes.
+        #[clap(short, long)]
+        json: bool,
+
+        /// Which job to count when an obsid has more than one ready job.
+        /// Options: error (fail, as the obsid is ambiguous), newest or oldest
+        /// (by job ID).
+        #[clap(long, name = "JOB", default_value = "error")]
+        prefer: JobPreference,
+
+        /// When given an obsid, only consider its jobs with this delivery
+        /// (e.g. "acacia" or "scratch").
+        #[clap(long, name = "DELIVERY")]
+        delivery: Option<String>,
+
+        /// The verbosity of the program. The default is to print high-level
+        /// information.
+        #[clap(short, long, parse(from_occurrences))]
+        verbosity: u8,
+
+        /// The job IDs or obsids to size up. Files containing job IDs or
+        /// obsids are also accepted.
+        #[clap(name = "JOBID_OR_OBSID")]
+        jobs: Vec<String>,
+    },
+
+    /// List the conversion job parameters known to giant-squid, with their
+    /// defaults
+    ConvParams {
+        /// Print the parameters as JSON.
+        #[clap(short, long)]
+        json: bool,
+    },
+
+    /// Remove partial downloads (".part" files) left by interrupted downloads
+    Clean {
+        /// The download dir to clean, including its subdirectories.
+        #[clap(name = "DIR")]
+        dir: String,
+
+        /// Don't actually remove anything; list the files which would be
+        /// removed instead.
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+
+        /// The verbosity of the program. The default is to print high-level
+        /// information.
+        #[clap(short, long, parse(from_occurrences))]
+        verbosity: u8,
+
+        /// Only remove partial downloads of these jobs' files. Files
+        /// containing job IDs or obsids are also accepted. By default, all
+        /// partial downloads are removed.
+        #[clap(name = "JOBID_OR_OBSID")]
+        jobids_or_obsids: Vec<String>,
+    },
+}
+
+/// Get a client which has authenticated with the MWA ASVO, using `api_key` if
+/// it was given on the command line, or MWA_ASVO_API_KEY otherwise.
+fn connect(opts: &ClientOptions, api_key: Option<&str>) -> Result<AsvoClient, AsvoError> {
+    match api_key {
+        Some(api_key) => {
+            warn!("Passing --api-key is insecure, as other users can see it in the list of running processes; please set MWA_ASVO_API_KEY instead");
+            AsvoClient::new_with_api_key(api_key, opts)
+        }
+        None => AsvoClient::new_with_options(opts),
+    }
 }
 
 fn init_logger(level: u8) {
@@ -300,97 +889,742 @@ fn init_logger(level: u8) {
         .set_time_offset_to_local()
         .expect("Unable to set time offset to local in SimpleLogger")
         .build();
-    match level {
-        0 => SimpleLogger::init(LevelFilter::Info, config).unwrap(),
-        1 => SimpleLogger::init(LevelFilter::Debug, config).unwrap(),
-        _ => SimpleLogger::init(LevelFilter::Trace, config).unwrap(),
+    // Log to stderr, so that stdout can be used for output meant for scripts.
+    let level = match level {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    WriteLogger::init(level, config, std::io::stderr()).unwrap();
+}
+
+/// Log a summary of a batch of downloads.
+fn log_download_summary(num_jobs: usize, total_bytes: u64, elapsed: Duration) {
+    info!(
+        "Downloaded {} jobs, {} in {} (average rate: {})",
+        num_jobs,
+        bytesize::ByteSize(total_bytes).to_string_as(true),
+        format_duration(elapsed),
+        format_rate(total_bytes, elapsed)
+    );
+}
+
+/// Warn about identifiers given to the `list` command that didn't match any
+/// ASVO job, e.g. because of a typo or a missed submission.
+fn warn_unmatched<T: Display>(kind: &str, unmatched: &[T]) {
+    if !unmatched.is_empty() {
+        warn!(
+            "No jobs found for {}: {}",
+            kind,
+            unmatched
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
+/// Create the download directory (and any parents) if it doesn't exist, so
+/// that every download doesn't fail with its own IO error.
+fn create_download_dir(dir: &str) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Download dir {} doesn't exist and couldn't be created", dir))
+}
+
+/// The interrupt flag of the client whose downloads Ctrl-C should stop.
+static INTERRUPT_FLAG: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+extern "C" fn handle_interrupt(_: libc::c_int) {
+    if let Some(flag) = INTERRUPT_FLAG.get() {
+        flag.store(true, Ordering::Relaxed);
+    }
+    // If the user is impatient, a second Ctrl-C kills the program.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Make Ctrl-C stop the client's downloads cleanly, rather than killing the
+/// program part way through writing a file.
+fn install_interrupt_handler(client: &AsvoClient) {
+    let _ = INTERRUPT_FLAG.set(client.interrupt_handle());
+    let handler: extern "C" fn(libc::c_int) = handle_interrupt;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Log what happened to a batch of downloads that was interrupted.
+fn log_interrupted<T: Display>(completed: &[T], interrupted: &[T], not_started: &[T]) {
+    let join = |items: &[T]| {
+        items
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
     };
+    warn!("Downloads were interrupted.");
+    if !completed.is_empty() {
+        info!("Completed: {}", join(completed));
+    }
+    if !interrupted.is_empty() {
+        warn!("Interrupted: {}", join(interrupted));
+    }
+    if !not_started.is_empty() {
+        warn!("Not started: {}", join(not_started));
+    }
+}
+
+/// The outcome of submitting a batch of jobs.
+struct SubmittedJobs {
+    /// The IDs of the newly-created jobs, in the order that the jobs were
+    /// given.
+    jobids: Vec<AsvoJobID>,
+    /// The first error, if any submissions failed but the others were allowed
+    /// to continue.
+    error: Option<AsvoError>,
+}
+
+impl SubmittedJobs {
+    /// Fail if any of the submissions failed. This is deferred so that the
+    /// successfully-submitted jobs can still be waited on.
+    fn finish(self) -> Result<(), AsvoError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Submit jobs, with up to `concurrency` submissions in flight at once and at
+/// least `delay_ms` milliseconds between the starts of successive submissions.
+/// If any submission fails, no more are started and the first error is
+/// returned, unless `continue_on_error` is set, in which case all jobs are
+/// submitted, and the failures are summarised and kept in the returned
+/// [SubmittedJobs]. Either way, if a `summary` path is given, the outcome of
+/// each submission is written there as JSON (see [write_submit_summary]). If
+/// `print_ids` is set, the ID of each newly-created job is printed to stdout as
+/// soon as it's submitted, so that no job is lost if a later submission
+/// fails. Likewise, the IDs of the newly-created jobs are appended to the
+/// `write_ids` file (if given) even if a submission failed.
+#[allow(clippy::too_many_arguments)]
+fn submit_jobs<T, F>(
+    jobs: &[(Obsid, T)],
+    concurrency: usize,
+    delay_ms: u64,
+    continue_on_error: bool,
+    summary: Option<&Path>,
+    print_ids: bool,
+    write_ids: Option<&Path>,
+    submit: F,
+) -> Result<SubmittedJobs, AsvoError>
+where
+    T: Sync,
+    F: Fn(Obsid, &T) -> Result<Option<AsvoJobID>, AsvoError> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let delay = Duration::from_millis(delay_ms);
+    let last_submit: Mutex<Option<Instant>> = Mutex::new(None);
+    let mut results = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..concurrency.clamp(1, jobs.len().max(1)))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut results = vec![];
+                    while !failed.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((o, data)) = jobs.get(i) else {
+                            break;
+                        };
+                        if !delay.is_zero() {
+                            // Hold the lock while sleeping, so that other
+                            // submissions wait their turn.
+                            let mut last = last_submit.lock().expect("lock poisoned");
+                            if let Some(last) = *last {
+                                std::thread::sleep(delay.saturating_sub(last.elapsed()));
+                            }
+                            *last = Some(Instant::now());
+                        }
+                        let result = submit(*o, data);
+                        match &result {
+                            Ok(Some(jobid)) => {
+                                info!("Submitted {} as ASVO job ID {}", o, jobid);
+                                if print_ids {
+                                    println!("{}", jobid);
+                                }
+                            }
+                            // for the none case- the "submit_asvo" function
+                            // will have already provided user some feedback
+                            Ok(None) => (),
+                            Err(e) if continue_on_error => error!("Failed to submit {}: {}", o, e),
+                            Err(_) => failed.store(true, Ordering::Relaxed),
+                        }
+                        results.push((i, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("submission thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    if let Some(path) = summary {
+        write_submit_summary(path, jobs, &results)?;
+    }
+
+    let mut jobids = Vec::with_capacity(results.len());
+    let mut failures = vec![];
+    let mut first_error = None;
+    for (i, result) in results {
+        match result {
+            Ok(Some(jobid)) => jobids.push(jobid),
+            Ok(None) => (),
+            Err(e) => {
+                if !continue_on_error && first_error.is_some() {
+                    error!("Failed to submit {}: {}", jobs[i].0, e);
+                }
+                failures.push(jobs[i].0);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(path) = write_ids {
+        if let Err(e) = append_jobids(path, &jobids) {
+            error!("Couldn't write job IDs to {}: {}", path.display(), e);
+            // Report a failed submission in preference to this.
+            first_error.get_or_insert(AsvoError::IO(e));
+        }
+    }
+    match first_error {
+        None => Ok(SubmittedJobs {
+            jobids,
+            error: None,
+        }),
+        Some(e) if continue_on_error => {
+            error!(
+                "{} of {} obsids were submitted; failed to submit: {}",
+                jobs.len() - failures.len(),
+                jobs.len(),
+                failures
+                    .iter()
+                    .map(|o| o.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            Ok(SubmittedJobs {
+                jobids,
+                error: Some(e),
+            })
+        }
+        Some(e) => Err(e),
+    }
+}
+
+/// Check the files of a download against checksums read from a checksum file.
+/// Files without a checksum are skipped, with a warning.
+fn verify_download(d: &AsvoDownload, checksums: &[(PathBuf, String)]) -> Result<(), AsvoError> {
+    for path in d.paths.iter().filter(|p| p.is_file()) {
+        match find_checksum(checksums, path) {
+            None => warn!(
+                "{} isn't in the checksum file; not checking it",
+                path.display()
+            ),
+            Some(expected) => {
+                let hash = sha1_file(path)?;
+                if !hash.eq_ignore_ascii_case(expected) {
+                    return Err(AsvoError::HashMismatch {
+                        jobid: d.jobid,
+                        file: path.display().to_string(),
+                        calculated_hash: hash,
+                        expected_hash: expected.to_string(),
+                    });
+                }
+                debug!("{} matches the checksum file", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a JSON summary of a batch of submissions to `path`, e.g.
+/// `{"submitted": 1, "already_existed": 1, "failed": 0, "not_attempted": 0,
+/// "obsids": [{"obsid": 1090528304, "outcome": "submitted", "jobid": 325430},
+/// {"obsid": 1090528305, "outcome": "already_existed"}]}`. Failed submissions
+/// have an "error"; obsids which weren't submitted because an earlier
+/// submission failed are "not_attempted".
+fn write_submit_summary<T>(
+    path: &Path,
+    jobs: &[(Obsid, T)],
+    results: &[(usize, Result<Option<AsvoJobID>, AsvoError>)],
+) -> Result<(), AsvoError> {
+    let mut counts = BTreeMap::<&str, usize>::new();
+    let mut outcomes = Vec::with_capacity(jobs.len());
+    for (i, (obsid, _)) in jobs.iter().enumerate() {
+        let mut outcome = serde_json::json!({ "obsid": obsid });
+        let kind = match results.iter().find(|(j, _)| *j == i).map(|(_, r)| r) {
+            Some(Ok(Some(jobid))) => {
+                outcome["jobid"] = (*jobid).into();
+                "submitted"
+            }
+            Some(Ok(None)) => "already_existed",
+            Some(Err(e)) => {
+                outcome["error"] = e.to_string().into();
+                "failed"
+            }
+            None => "not_attempted",
+        };
+        outcome["outcome"] = kind.into();
+        *counts.entry(kind).or_default() += 1;
+        outcomes.push(outcome);
+    }
+    let count = |outcome| counts.get(outcome).copied().unwrap_or(0);
+    let summary = serde_json::json!({
+        "submitted": count("submitted"),
+        "already_existed": count("already_existed"),
+        "failed": count("failed"),
+        "not_attempted": count("not_attempted"),
+        "obsids": outcomes,
+    });
+    std::fs::write(path, summary.to_string())?;
+    Ok(())
+}
+
+/// Something to be downloaded.
+enum DownloadTarget {
+    JobID(AsvoJobID),
+    Obsid(Obsid),
 }
 
-/// Wait for all of the specified job IDs to become ready, then exit.
-fn wait_loop(client: &AsvoClient, jobids: &[AsvoJobID]) -> Result<(), AsvoError> {
+impl Display for DownloadTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadTarget::JobID(j) => write!(f, "job ID {}", j),
+            DownloadTarget::Obsid(o) => write!(f, "obsid {}", o),
+        }
+    }
+}
+
+/// Print a job's state transition to stdout as a single line of JSON.
+fn print_state_event(job: &AsvoJob, old_state: Option<&AsvoJobState>) {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let event = serde_json::json!({
+        "timestamp": timestamp,
+        "jobid": job.jobid,
+        "obsid": job.obsid,
+        "old_state": old_state.map(|s| s.to_string()),
+        "new_state": job.state.to_string(),
+    });
+    println!("{}", event);
+}
+
+/// Wait for all of the specified job IDs to become ready, then exit. If a
+/// download directory is given, each job is downloaded as soon as it is ready,
+/// while the other jobs continue to be processed, and what was downloaded is
+/// returned.
+fn wait_loop(
+    client: &AsvoClient,
+    jobids: &[AsvoJobID],
+    download_dir: Option<&str>,
+    events: bool,
+) -> Result<Vec<AsvoDownload>, anyhow::Error> {
     info!("Waiting for {} jobs to be ready...", jobids.len());
     let mut last_state = BTreeMap::<AsvoJobID, AsvoJobState>::new();
     // Offer the ASVO a kindness by waiting a few seconds, so
     // that the user's queue is hopefully current.
     std::thread::sleep(Duration::from_secs(5));
-    loop {
-        // Get the current state of all jobs. By converting to a map, we avoid
-        // quadratic complexity below. Probably not a big deal, but why not?
-        let jobs = client.get_jobs()?.into_map();
-        let mut any_not_ready = false;
-        // Iterate over all supplied job IDs.
-        for j in jobids {
-            // Find the relevant job in the queue.
-            let job = match jobs.0.get(j) {
-                None => return Err(AsvoError::NoAsvoJob(*j)),
-                Some(job) => job,
-            };
-            // Handle the job's state. If it's ready, there's nothing to do. If
-            // the job is simply queued or in processing, we can say that we're
-            // not ready yet. All other possibilities are handled drastically.
-            match &job.state {
-                AsvoJobState::Ready => (),
-                AsvoJobState::Error(e) => {
-                    return Err(AsvoError::UpstreamError {
-                        jobid: *j,
-                        obsid: job.obsid,
-                        error: e.to_string(),
+    let start_time = Instant::now();
+    let interrupted = client.interrupt_handle();
+    if download_dir.is_some() {
+        install_interrupt_handler(client);
+    }
+    let download_opts = download_dir.map(|dir| DownloadOptions {
+        download_dir: dir.to_string(),
+        ..Default::default()
+    });
+    // Ready jobs are queued for a limited number of download threads, which
+    // report back the result of each download.
+    let (queue, ready) = mpsc::channel::<AsvoJobID>();
+    let ready = Mutex::new(ready);
+    let (report, results) = mpsc::channel();
+    std::thread::scope(|s| {
+        let workers: Vec<_> = match &download_opts {
+            Some(opts) => (0..client.download_concurrency(opts).min(jobids.len().max(1)))
+                .map(|_| {
+                    let (ready, report) = (&ready, report.clone());
+                    s.spawn(move || loop {
+                        let next = ready.lock().expect("lock poisoned").recv();
+                        let Ok(jobid) = next else {
+                            break;
+                        };
+                        let _ = report.send((jobid, client.download_job(jobid, opts)));
                     })
+                })
+                .collect(),
+            None => vec![],
+        };
+        drop(report);
+
+        let mut queued = vec![];
+        let outcome = (|| loop {
+            if interrupted.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            // Get the current state of all jobs. By converting to a map, we avoid
+            // quadratic complexity below. Probably not a big deal, but why not?
+            let jobs = client.get_jobs()?.into_map();
+            let mut any_not_ready = false;
+            // Iterate over all supplied job IDs.
+            for j in jobids {
+                // Find the relevant job in the queue.
+                let job = match jobs.0.get(j) {
+                    None => return Err(AsvoError::NoAsvoJob(*j)),
+                    Some(job) => job,
+                };
+                // Log if there was a change in state. With events, the first
+                // sighting of a job is also reported, with no old state.
+                let old_state = last_state.insert(*j, job.state.clone());
+                if old_state.as_ref().is_some_and(|s| s != &job.state) {
+                    info!("Job {} is {}", j, &job.state);
+                }
+                if events && old_state.as_ref() != Some(&job.state) {
+                    print_state_event(job, old_state.as_ref());
                 }
-                AsvoJobState::Expired => return Err(AsvoError::Expired(*j)),
-                AsvoJobState::Cancelled => return Err(AsvoError::Cancelled(*j)),
-                AsvoJobState::Queued | AsvoJobState::Processing => {
-                    any_not_ready = true;
+                // Handle the job's state. If it's ready, start downloading it
+                // if we were asked to. If the job is simply queued or in
+                // processing, we can say that we're not ready yet. All other
+                // possibilities are handled drastically.
+                match &job.state {
+                    AsvoJobState::Ready => {
+                        if download_opts.is_some() && !queued.contains(j) {
+                            queued.push(*j);
+                            let _ = queue.send(*j);
+                        }
+                    }
+                    AsvoJobState::Error(e) => {
+                        return Err(AsvoError::UpstreamError {
+                            jobid: *j,
+                            obsid: job.obsid,
+                            error: e.to_string(),
+                        })
+                    }
+                    AsvoJobState::Expired => return Err(AsvoError::Expired(*j)),
+                    AsvoJobState::Cancelled => return Err(AsvoError::Cancelled(*j)),
+                    AsvoJobState::Queued | AsvoJobState::Processing => {
+                        any_not_ready = true;
+                    }
                 }
             }
-            // log if there was a change in state.
-            match last_state.insert(*j, job.state.clone()) {
-                Some(last_state) if last_state != job.state => {
-                    info!("Job {} is {}", j, &job.state);
+            // Our lock variable is set if we broke out of the loop.
+            if any_not_ready {
+                // Sleep for a minute, but notice an interrupt promptly.
+                for _ in 0..60 {
+                    if interrupted.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_secs(1));
                 }
-                _ => (),
+            } else {
+                // If we reach here, all jobs are ready.
+                return Ok(());
+            }
+        })();
+        if outcome.is_err() {
+            // Don't wait on in-flight downloads (perhaps for hours) before
+            // reporting the error; stop them, keeping their partial downloads
+            // to be resumed.
+            interrupted.store(true, Ordering::Relaxed);
+        } else if !interrupted.load(Ordering::Relaxed) {
+            info!("All {} ASVO jobs are ready for download.", jobids.len());
+        }
+
+        // Let the download threads finish what's queued, and gather the
+        // results of every download.
+        drop(queue);
+        let panicked = workers
+            .into_iter()
+            .map(|w| w.join())
+            .filter(|r| r.is_err())
+            .count();
+        let mut results: Vec<(AsvoJobID, Result<AsvoDownload, AsvoError>)> =
+            results.into_iter().collect();
+        results.sort_by_key(|(jobid, _)| *jobid);
+        let mut downloaded = Vec::with_capacity(results.len());
+        let mut completed = vec![];
+        let mut cut_short = vec![];
+        let mut failed = vec![];
+        for (jobid, result) in results {
+            match result {
+                Ok(d) => {
+                    downloaded.push(d);
+                    completed.push(jobid);
+                }
+                Err(AsvoError::Interrupted) => cut_short.push(jobid),
+                Err(e) => {
+                    error!("Failed to download job {}: {}", jobid, e);
+                    failed.push((jobid, e));
+                }
+            }
+        }
+        let unreported = |jobs: &[AsvoJobID]| -> Vec<AsvoJobID> {
+            jobs.iter()
+                .filter(|j| {
+                    !completed.contains(j)
+                        && !cut_short.contains(j)
+                        && !failed.iter().any(|(f, _)| f == *j)
+                })
+                .copied()
+                .collect()
+        };
+        if panicked > 0 {
+            // A download thread that panicked didn't report its job.
+            let lost = unreported(&queued);
+            error!(
+                "{} download threads panicked; these jobs weren't downloaded: {}",
+                panicked,
+                lost.iter()
+                    .map(|j| j.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        if outcome.is_err() || interrupted.load(Ordering::Relaxed) {
+            if download_opts.is_some() {
+                let not_started = unreported(jobids);
+                log_interrupted(&completed, &cut_short, &not_started);
+            }
+            outcome?;
+            return Err(AsvoError::Interrupted.into());
+        }
+        if !downloaded.is_empty() {
+            log_download_summary(
+                downloaded.len(),
+                downloaded.iter().map(|d| d.bytes).sum(),
+                start_time.elapsed(),
+            );
+        }
+        if panicked > 0 {
+            bail!("{} download threads panicked", panicked);
+        }
+        if !failed.is_empty() {
+            let summary = format!(
+                "{} of {} downloads failed: {}",
+                failed.len(),
+                queued.len(),
+                failed
+                    .iter()
+                    .map(|(j, _)| j.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            // Keep the first failure as the cause, for its exit code.
+            return Err(anyhow::Error::new(failed.swap_remove(0).1).context(summary));
+        }
+        Ok(downloaded)
+    })
+}
+
+/// Render an error as a JSON object, for machine consumption.
+fn error_to_json(e: &anyhow::Error) -> serde_json::Value {
+    let mut json = serde_json::Map::new();
+    let kind = if let Some(e) = e.downcast_ref::<AsvoError>() {
+        if let Some(jobid) = e.jobid() {
+            json.insert("jobid".to_string(), jobid.into());
+        }
+        if let Some(obsid) = e.obsid() {
+            json.insert("obsid".to_string(), serde_json::json!(obsid));
+        }
+        if let Some(code) = e.http_code() {
+            json.insert("http_code".to_string(), code.into());
+        }
+        e.kind()
+    } else if e.downcast_ref::<ParseError>().is_some() {
+        "ParseError"
+    } else if e.downcast_ref::<obsid::ObsidError>().is_some() {
+        "ObsidError"
+    } else {
+        "Other"
+    };
+    json.insert("kind".to_string(), kind.into());
+    json.insert("message".to_string(), e.to_string().into());
+    serde_json::Value::Object(json)
+}
+
+/// Get the exit code for an error. These are documented in the README, so
+/// don't change them lightly!
+fn exit_code(e: &anyhow::Error) -> i32 {
+    match e.downcast_ref::<AsvoError>() {
+        Some(e) => asvo_exit_code(e),
+        None => 1,
+    }
+}
+
+/// The exit code for an [AsvoError]; see [exit_code].
+fn asvo_exit_code(e: &AsvoError) -> i32 {
+    match e {
+        e if e.http_code() == Some(429) => 3,
+        AsvoError::MissingAuthKey => 2,
+        e if matches!(e.http_code(), Some(401) | Some(403) | Some(407)) => 2,
+        AsvoError::NotReady { .. } => 4,
+        AsvoError::HashMismatch { .. } | AsvoError::SizeMismatch { .. } => 5,
+        AsvoError::IO(_) => 6,
+        AsvoError::Reqwest(_)
+        | AsvoError::Unavailable(_)
+        | AsvoError::ServerUnavailable { .. }
+        | AsvoError::ServiceOutage { .. }
+        | AsvoError::Stalled { .. }
+        | AsvoError::JobTimeout { .. } => 7,
+        AsvoError::Interrupted => 130,
+        // If all of a job's failed files failed the same way, exit as they
+        // would have.
+        AsvoError::FilesFailed { failed, .. } => {
+            let mut codes = failed.iter().map(|(_, e)| asvo_exit_code(e));
+            match codes.next() {
+                Some(code) if codes.all(|c| c == code) => code,
+                _ => 1,
             }
         }
-        // Our lock variable is set if we broke out of the loop.
-        if any_not_ready {
-            std::thread::sleep(Duration::from_secs(60));
-        } else {
-            // If we reach here, all jobs are ready.
-            break;
+        _ => 1,
+    }
+}
+
+/// Whether a download failed verification (its hash or size didn't match the
+/// upstream one). A job with several files failed verification if all of its
+/// failed files did.
+fn is_verification_failure(e: &AsvoError) -> bool {
+    match e {
+        AsvoError::HashMismatch { .. } | AsvoError::SizeMismatch { .. } => true,
+        AsvoError::FilesFailed { failed, .. } => {
+            failed.iter().all(|(_, e)| is_verification_failure(e))
         }
+        _ => false,
     }
-    info!("All {} ASVO jobs are ready for download.", jobids.len());
-    Ok(())
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    match Args::parse() {
+fn main() {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            // Usage errors get an exit code of 1, but --help and --version
+            // are successful.
+            e.print().expect("Unable to print to the terminal");
+            std::process::exit(if e.use_stderr() { 1 } else { 0 });
+        }
+    };
+    let client_opts = ClientOptions {
+        max_connections_per_host: cli.max_connections_per_host,
+        print_requests: cli.print_requests,
+        retries: cli.retries,
+        pool_idle_timeout: cli.pool_idle_timeout.map(Duration::from_secs),
+    };
+    if let Err(e) = run(
+        cli.command,
+        cli.colour.resolve(),
+        cli.colour_scheme,
+        &client_opts,
+        cli.api_key.as_deref(),
+        &IdFileOptions {
+            column: cli.obsid_column,
+            first_column: cli.first_column,
+        },
+    ) {
+        match cli.error_format {
+            ErrorFormat::Text => eprintln!("Error: {:?}", e),
+            ErrorFormat::Json => eprintln!("{}", error_to_json(&e)),
+        }
+        std::process::exit(exit_code(&e));
+    }
+}
+
+fn run(
+    args: Args,
+    colour: Option<bool>,
+    colour_scheme: ColourScheme,
+    client_opts: &ClientOptions,
+    api_key: Option<&str>,
+    id_file_opts: &IdFileOptions,
+) -> Result<(), anyhow::Error> {
+    match args {
         Args::List {
             verbosity,
             json,
             jobids_or_obsids,
             states,
             types: job_types,
+            expect,
+            field,
+            jobid_min,
+            jobid_max,
+            markdown,
+            fields,
+            only_new,
+            marker,
         } => {
             init_logger(verbosity);
 
-            let (jobids, obsids) = parse_many_jobids_or_obsids(&jobids_or_obsids)?;
-            let client = AsvoClient::new()?;
+            if let (Some(min), Some(max)) = (jobid_min, jobid_max) {
+                if min > max {
+                    bail!("--jobid-min ({}) is bigger than --jobid-max ({})", min, max);
+                }
+            }
+            let (jobids, obsids) =
+                parse_many_jobids_or_obsids_with_options(&jobids_or_obsids, id_file_opts)?;
+            if let Some(expected) = expect {
+                check_expected_ids(expected, &jobids, &obsids)?;
+            }
+            let marker = match (only_new, marker) {
+                (false, _) => None,
+                (true, Some(marker)) => Some(marker),
+                (true, None) => Some(default_list_marker_path().context(
+                    "Couldn't find a config dir for the --only-new marker; please use --marker",
+                )?),
+            };
+            let client = connect(client_opts, api_key)?;
             let mut jobs = client.get_jobs()?;
+            // The marker moves on to the newest job in the queue, even if it's
+            // filtered out below.
+            let mut new_marker = None;
+            if let Some(marker) = &marker {
+                let last_seen = read_list_marker(marker)?;
+                let newest = jobs.0.iter().map(|j| j.jobid).max();
+                if newest > last_seen {
+                    new_marker = newest;
+                }
+                if let Some(last_seen) = last_seen {
+                    debug!("Only listing jobs newer than ASVO job ID {}", last_seen);
+                    jobs = jobs.retain(|j| j.jobid > last_seen);
+                }
+            }
             match (jobids, obsids) {
                 (jobids, obsids) if !jobids.is_empty() && !obsids.is_empty() => {
                     bail!("You can't specify both job IDs and obsIDs. Please use one or the other.")
                 }
                 (jobids, _) if !jobids.is_empty() => {
-                    jobs = jobs.retain(|j| jobids.contains(&j.jobid))
+                    jobs = jobs.retain(|j| jobids.contains(&j.jobid));
+                    let found: Vec<AsvoJobID> = jobs.0.iter().map(|j| j.jobid).collect();
+                    warn_unmatched("job IDs", &unmatched_ids(&jobids, &found));
                 }
                 (_, obsids) if !obsids.is_empty() => {
-                    jobs = jobs.retain(|j| obsids.contains(&j.obsid))
+                    jobs = jobs.retain(|j| obsids.contains(&j.obsid));
+                    let found: Vec<Obsid> = jobs.0.iter().map(|j| j.obsid).collect();
+                    warn_unmatched("obsids", &unmatched_ids(&obsids, &found));
                 }
                 _ => (),
             };
 
+            if jobid_min.is_some() || jobid_max.is_some() {
+                jobs = jobs.retain_jobid_range(
+                    jobid_min.unwrap_or(AsvoJobID::MIN)..=jobid_max.unwrap_or(AsvoJobID::MAX),
+                );
+            }
+
             if !job_types.is_empty() {
                 jobs = jobs.retain(|j| job_types.contains(&j.jtype))
             }
@@ -403,16 +1637,65 @@ fn main() -> Result<(), anyhow::Error> {
                 });
             }
 
-            if json {
+            if json && !fields.is_empty() {
+                println!("{}", jobs.json_fields(&fields)?);
+            } else if json {
                 println!("{}", jobs.json()?);
+            } else if let Some(field) = field {
+                jobs.list_field(field);
+            } else if markdown {
+                print!("{}", jobs.markdown());
+            } else {
+                jobs.list_with_style(colour, colour_scheme);
+            }
+
+            if let (Some(marker), Some(jobid)) = (&marker, new_marker) {
+                write_list_marker(marker, jobid).with_context(|| {
+                    format!("Couldn't update the --only-new marker {}", marker.display())
+                })?;
+            }
+        }
+
+        Args::Show {
+            json,
+            verbosity,
+            jobid,
+        } => {
+            init_logger(verbosity);
+            let client = connect(client_opts, api_key)?;
+            let job = client
+                .get_jobs()?
+                .0
+                .into_iter()
+                .find(|j| j.jobid == jobid)
+                .ok_or(AsvoError::NoAsvoJob(jobid))?;
+            if json {
+                println!("{}", serde_json::to_string(&job)?);
             } else {
-                jobs.list();
+                println!("{}", job.describe());
             }
         }
 
         Args::Download {
             keep_zip,
             skip_hash,
+            report_all,
+            flatten,
+            preserve_mtime,
+            preserve_permissions,
+            save_job_json,
+            output_template,
+            prefer,
+            delivery,
+            resume_batch,
+            max_total_size,
+            stall_timeout,
+            job_timeout,
+            verify_partial,
+            write_checksums,
+            verify_against,
+            perf_log,
+            print_paths,
             dry_run,
             verbosity,
             jobids_or_obsids,
@@ -424,8 +1707,13 @@ fn main() -> Result<(), anyhow::Error> {
             }
             init_logger(verbosity);
 
-            let (jobids, obsids) = parse_many_jobids_or_obsids(&jobids_or_obsids)?;
+            let (jobids, obsids) =
+                parse_many_jobids_or_obsids_with_options(&jobids_or_obsids, id_file_opts)?;
             let hash = !skip_hash;
+            let download_dir = download_dir
+                .or_else(|| std::env::var("GIANT_SQUID_DOWNLOAD_DIR").ok())
+                .unwrap_or_else(|| ".".to_string());
+            debug!("Using {} for the download directory", download_dir);
             if dry_run {
                 if !jobids.is_empty() {
                     debug!("Parsed job IDs: {:#?}", jobids);
@@ -434,19 +1722,169 @@ fn main() -> Result<(), anyhow::Error> {
                     debug!("Parsed obsids: {:#?}", obsids);
                 }
                 info!(
-                    "Parsed {} jobids and {} obsids for download. keep_zip={:?}, hash={:?}",
+                    "Parsed {} jobids and {} obsids for download. keep_zip={:?}, hash={:?}, flatten={:?}",
                     jobids.len(),
                     obsids.len(),
                     keep_zip,
                     hash,
+                    flatten,
                 );
             } else {
-                let client = AsvoClient::new()?;
-                for j in jobids {
-                    client.download_job(j, keep_zip, hash, &download_dir)?;
+                create_download_dir(&download_dir)?;
+                let opts = DownloadOptions {
+                    keep_tar: keep_zip,
+                    hash,
+                    flatten,
+                    preserve_mtime,
+                    preserve_permissions,
+                    save_job_json,
+                    output_template,
+                    download_dir,
+                    prefer,
+                    delivery,
+                    max_total_size: max_total_size.map(|s| s.as_u64()),
+                    stall_timeout: stall_timeout.map(Duration::from_secs),
+                    job_timeout: job_timeout.map(Duration::from_secs),
+                    verify_partial,
+                    file_checksums: write_checksums.is_some(),
+                    perf_log,
+                    ..Default::default()
+                };
+                let expected_checksums = match &verify_against {
+                    Some(path) => Some(read_checksums(path).with_context(|| {
+                        format!("Couldn't read checksum file {}", path.display())
+                    })?),
+                    None => None,
+                };
+                let client = connect(client_opts, api_key)?;
+                let start_time = Instant::now();
+                let mut total_bytes = 0;
+                let mut targets: Vec<DownloadTarget> = jobids
+                    .iter()
+                    .map(|&j| DownloadTarget::JobID(j))
+                    .chain(obsids.iter().map(|&o| DownloadTarget::Obsid(o)))
+                    .collect();
+                if let Some(manifest) = &resume_batch {
+                    let done = read_download_manifest(manifest)?;
+                    targets.retain(|target| {
+                        let skip = match *target {
+                            DownloadTarget::JobID(j) => done.iter().any(|&(dj, _)| dj == j),
+                            DownloadTarget::Obsid(o) => done.iter().any(|&(_, d_o)| d_o == o),
+                        };
+                        if skip {
+                            info!("Skipping {}, which has already been downloaded", target);
+                        }
+                        !skip
+                    });
+                }
+                let interrupted = client.interrupt_handle();
+                install_interrupt_handler(&client);
+                let mut skipped = vec![];
+                let mut mismatches = vec![];
+                let mut timed_out = vec![];
+                for (i, target) in targets.iter().enumerate() {
+                    if interrupted.load(Ordering::Relaxed) {
+                        log_interrupted(&targets[..i], &[], &targets[i..]);
+                        return Err(AsvoError::Interrupted.into());
+                    }
+                    let result = match *target {
+                        DownloadTarget::JobID(j) => client.download_job(j, &opts),
+                        DownloadTarget::Obsid(o) => client.download_obsid(o, &opts),
+                    };
+                    let result = match (result, &expected_checksums) {
+                        (Ok(d), Some(checksums)) => verify_download(&d, checksums).map(|_| d),
+                        (result, _) => result,
+                    };
+                    match result {
+                        Ok(d) => {
+                            total_bytes += d.bytes;
+                            if print_paths {
+                                let cwd = std::env::current_dir()?;
+                                for path in &d.paths {
+                                    println!("{}", cwd.join(path).display());
+                                }
+                            }
+                            if let Some(checksum_file) = &write_checksums {
+                                append_checksums(checksum_file, &d.checksums).with_context(
+                                    || format!("Couldn't write to checksum file {}", checksum_file),
+                                )?;
+                            }
+                            if let (Some(manifest), Some(obsid)) = (&resume_batch, d.obsid) {
+                                append_to_download_manifest(manifest, d.jobid, obsid)
+                                    .with_context(|| {
+                                        format!("Couldn't write to download manifest {}", manifest)
+                                    })?;
+                            }
+                        }
+                        Err(AsvoError::Interrupted) => {
+                            log_interrupted(&targets[..i], &targets[i..=i], &targets[i + 1..]);
+                            return Err(AsvoError::Interrupted.into());
+                        }
+                        Err(e @ AsvoError::MaxTotalSizeExceeded { .. }) => {
+                            // Smaller jobs may still fit.
+                            warn!("{}", e);
+                            skipped.push(target);
+                        }
+                        Err(e @ AsvoError::JobTimeout { .. }) => {
+                            // Don't let one slow job hold up the rest.
+                            error!("{}", e);
+                            timed_out.push(e);
+                        }
+                        Err(e) if report_all && is_verification_failure(&e) => {
+                            error!("{}", e);
+                            mismatches.push(e);
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
                 }
-                for o in obsids {
-                    client.download_obsid(o, keep_zip, hash, &download_dir)?;
+                log_download_summary(
+                    targets.len() - skipped.len() - mismatches.len() - timed_out.len(),
+                    total_bytes,
+                    start_time.elapsed(),
+                );
+                let skipped_message = format!(
+                    "Skipped {} jobs to stay under --max-total-size: {}",
+                    skipped.len(),
+                    skipped
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                if (!mismatches.is_empty() || !timed_out.is_empty()) && !skipped.is_empty() {
+                    warn!("{}", skipped_message);
+                }
+                if !timed_out.is_empty() {
+                    let summary = format!(
+                        "{} jobs were abandoned after --job-timeout: {}",
+                        timed_out.len(),
+                        timed_out
+                            .iter()
+                            .filter_map(|e| e.jobid())
+                            .map(|j| j.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    if mismatches.is_empty() {
+                        return Err(anyhow::Error::new(timed_out.swap_remove(0)).context(summary));
+                    }
+                    warn!("{}", summary);
+                }
+                if !mismatches.is_empty() {
+                    let summary = format!(
+                        "{} jobs failed verification:\n{}",
+                        mismatches.len(),
+                        mismatches
+                            .iter()
+                            .map(|e| e.to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    );
+                    // Keep the first mismatch as the cause, for its exit code.
+                    return Err(anyhow::Error::new(mismatches.swap_remove(0)).context(summary));
+                }
+                if !skipped.is_empty() {
+                    bail!(skipped_message);
                 }
             }
         }
@@ -454,22 +1892,26 @@ fn main() -> Result<(), anyhow::Error> {
         Args::SubmitVis {
             delivery,
             delivery_format,
+            concurrent_submits,
+            submit_delay,
+            continue_on_error,
+            print_ids,
+            write_ids,
+            summary_json,
             wait,
+            download,
             dry_run,
             allow_resubmit,
+            extra,
             verbosity,
             obsids,
         } => {
             init_logger(verbosity);
 
-            let (parsed_jobids, parsed_obsids) = parse_many_jobids_or_obsids(&obsids)?;
+            let (parsed_jobids, parsed_obsids) =
+                parse_many_jobids_or_obsids_with_options(&obsids, id_file_opts)?;
             // There shouldn't be any job IDs here.
-            if !parsed_jobids.is_empty() {
-                bail!(
-                    "Expected only obsids, but found these exceptions: {:?}",
-                    parsed_jobids
-                );
-            }
+            check_expected_ids(ExpectedIds::Obsids, &parsed_jobids, &parsed_obsids)?;
             if parsed_obsids.is_empty() {
                 bail!("No obsids specified!");
             }
@@ -477,8 +1919,10 @@ fn main() -> Result<(), anyhow::Error> {
             let delivery = Delivery::validate(delivery)?;
             debug!("Using {} for delivery", delivery);
 
+            let extra_fields = parse_extra_fields(&extra)?;
+
             let delivery_format: Option<DeliveryFormat> =
-                DeliveryFormat::validate(delivery_format)?;
+                DeliveryFormat::validate_for(delivery_format, &AsvoJobType::DownloadVisibilities)?;
             debug!("Using {:#?} for delivery format", delivery_format);
 
             if dry_run {
@@ -487,54 +1931,72 @@ fn main() -> Result<(), anyhow::Error> {
                     obsids.len()
                 );
             } else {
-                let client = AsvoClient::new()?;
-                let mut jobids: Vec<AsvoJobID> = Vec::with_capacity(obsids.len());
-                let mut submitted_count = 0;
-
-                for o in parsed_obsids {
-                    let j = client.submit_vis(o, delivery, delivery_format, allow_resubmit)?;
-
-                    if j.is_some() {
-                        let jobid = j.unwrap();
-                        info!("Submitted {} as ASVO job ID {}", o, jobid);
-                        jobids.push(jobid);
-                        submitted_count += 1;
-                    }
-                    // for the none case- the "submit_asvo" function
-                    // will have already provided user some feedback
+                if let Some(dir) = &download {
+                    create_download_dir(dir)?;
                 }
+                let client = connect(client_opts, api_key)?;
+                let jobs: Vec<(Obsid, ())> = parsed_obsids.iter().map(|&o| (o, ())).collect();
+                let submitted = submit_jobs(
+                    &jobs,
+                    concurrent_submits,
+                    submit_delay,
+                    continue_on_error,
+                    summary_json.as_deref(),
+                    print_ids,
+                    write_ids.as_deref(),
+                    |o, _| {
+                        client.submit_vis_with_extra(
+                            o,
+                            delivery.clone(),
+                            delivery_format,
+                            allow_resubmit,
+                            &extra_fields,
+                        )
+                    },
+                )?;
                 info!(
                     "Submitted {} obsids for visibility download.",
-                    submitted_count
+                    submitted.jobids.len()
                 );
 
-                if wait {
+                if wait || download.is_some() {
                     // Endlessly loop over the newly-supplied job IDs until
                     // they're all ready.
-                    wait_loop(&client, &jobids)?;
+                    wait_loop(&client, &submitted.jobids, download.as_deref(), false)?;
                 }
+                submitted.finish()?;
             }
         }
 
         Args::SubmitConv {
             parameters,
+            no_defaults,
             delivery,
             delivery_format,
+            from_csv,
+            concurrent_submits,
+            submit_delay,
+            continue_on_error,
+            print_ids,
+            write_ids,
+            summary_json,
             wait,
+            download,
             dry_run,
             allow_resubmit,
+            extra,
             verbosity,
             obsids,
         } => {
-            let (parsed_jobids, parsed_obsids) = parse_many_jobids_or_obsids(&obsids)?;
+            let (parsed_jobids, parsed_obsids) =
+                parse_many_jobids_or_obsids_with_options(&obsids, id_file_opts)?;
             // There shouldn't be any job IDs here.
-            if !parsed_jobids.is_empty() {
-                bail!(
-                    "Expected only obsids, but found these exceptions: {:?}",
-                    parsed_jobids
-                );
-            }
-            if parsed_obsids.is_empty() {
+            check_expected_ids(ExpectedIds::Obsids, &parsed_jobids, &parsed_obsids)?;
+            let csv_rows = match &from_csv {
+                Some(f) => parse_conversion_csv(f)?,
+                None => vec![],
+            };
+            if parsed_obsids.is_empty() && csv_rows.is_empty() {
                 bail!("No obsids specified!");
             }
             init_logger(verbosity);
@@ -542,80 +2004,126 @@ fn main() -> Result<(), anyhow::Error> {
             let delivery = Delivery::validate(delivery)?;
             debug!("Using {} for delivery", delivery);
 
+            let extra_fields = parse_extra_fields(&extra)?;
+
             let delivery_format: Option<DeliveryFormat> =
-                DeliveryFormat::validate(delivery_format)?;
+                DeliveryFormat::validate_for(delivery_format, &AsvoJobType::Conversion)?;
             debug!("Using {:#?} for delivery format", delivery_format);
 
-            // Get the user parameters and set any defaults that the user has not set.
+            // Get the user parameters and set any defaults that the user has
+            // not set (unless told not to).
             let params = {
                 let mut params = match &parameters {
                     Some(s) => parse_key_value_pairs(s)?,
                     None => BTreeMap::new(),
                 };
-                for (&key, &value) in DEFAULT_CONVERSION_PARAMETERS.iter() {
-                    if !params.contains_key(key) {
-                        params.insert(key, value);
+                if !no_defaults {
+                    for (&key, &value) in DEFAULT_CONVERSION_PARAMETERS.iter() {
+                        if !params.contains_key(key) {
+                            params.insert(key, value);
+                        }
                     }
                 }
                 params
             };
 
+            // Pair each obsid with its delivery and parameters. Rows from a CSV
+            // file override the command-line delivery and parameters.
+            let mut jobs = Vec::with_capacity(parsed_obsids.len() + csv_rows.len());
+            for o in parsed_obsids {
+                jobs.push((o, (delivery.clone(), params.clone())));
+            }
+            for row in &csv_rows {
+                let row_delivery = match &row.delivery {
+                    Some(d) => Delivery::validate(Some(d))?,
+                    None => delivery.clone(),
+                };
+                let mut row_params = params.clone();
+                if !row.parameters.is_empty() {
+                    row_params.extend(parse_key_value_pairs(&row.parameters)?);
+                }
+                jobs.push((row.obsid, (row_delivery, row_params)));
+            }
+            if no_defaults {
+                // Without the defaults, the MWA ASVO can't tell what to
+                // convert to.
+                if let Some((o, _)) = jobs.iter().find(|(_, (_, p))| !p.contains_key("output")) {
+                    bail!(
+                        "No \"output\" conversion parameter for obsid {}; it's required with --no-defaults",
+                        o
+                    );
+                }
+            }
+
             if dry_run {
                 info!(
                     "Would have submitted {} obsids for conversion, using these parameters:\n{:?}",
-                    obsids.len(),
+                    jobs.len(),
                     params
                 );
+                for (o, (d, p)) in jobs.iter().skip(jobs.len() - csv_rows.len()) {
+                    info!("Would have submitted {} for conversion to {}, using these parameters:\n{:?}", o, d, p);
+                }
             } else {
-                let client = AsvoClient::new()?;
-                let mut jobids: Vec<AsvoJobID> = Vec::with_capacity(obsids.len());
-                let mut submitted_count = 0;
-
-                for o in parsed_obsids {
-                    let j = client.submit_conv(
-                        o,
-                        delivery,
-                        delivery_format,
-                        &params,
-                        allow_resubmit,
-                    )?;
-
-                    if j.is_some() {
-                        let jobid = j.unwrap();
-                        info!("Submitted {} as ASVO job ID {}", o, jobid);
-                        jobids.push(jobid);
-                        submitted_count += 1;
-                    }
-                    // for the none case- the "submit_asvo" function
-                    // will have already provided user some feedback
+                if let Some(dir) = &download {
+                    create_download_dir(dir)?;
                 }
-                info!("Submitted {} obsids for conversion.", submitted_count);
+                let client = connect(client_opts, api_key)?;
+                let submitted = submit_jobs(
+                    &jobs,
+                    concurrent_submits,
+                    submit_delay,
+                    continue_on_error,
+                    summary_json.as_deref(),
+                    print_ids,
+                    write_ids.as_deref(),
+                    |o, (d, p)| {
+                        client.submit_conv_with_extra(
+                            o,
+                            d.clone(),
+                            delivery_format,
+                            p,
+                            !no_defaults,
+                            allow_resubmit,
+                            &extra_fields,
+                        )
+                    },
+                )?;
+                info!(
+                    "Submitted {} obsids for conversion.",
+                    submitted.jobids.len()
+                );
 
-                if wait {
+                if wait || download.is_some() {
                     // Endlessly loop over the newly-supplied job IDs until
                     // they're all ready.
-                    wait_loop(&client, &jobids)?;
+                    wait_loop(&client, &submitted.jobids, download.as_deref(), false)?;
                 }
+                submitted.finish()?;
             }
         }
 
         Args::SubmitMeta {
             delivery,
             delivery_format,
+            concurrent_submits,
+            submit_delay,
+            continue_on_error,
+            print_ids,
+            write_ids,
+            summary_json,
             wait,
+            download,
             dry_run,
             allow_resubmit,
+            extra,
             verbosity,
             obsids,
         } => {
-            let (parsed_jobids, parsed_obsids) = parse_many_jobids_or_obsids(&obsids)?;
+            let (parsed_jobids, parsed_obsids) =
+                parse_many_jobids_or_obsids_with_options(&obsids, id_file_opts)?;
             // There shouldn't be any job IDs here.
-            if !parsed_jobids.is_empty() {
-                bail!(
-                    "Expected only obsids, but found these exceptions: {:?}",
-                    parsed_jobids
-                );
-            }
+            check_expected_ids(ExpectedIds::Obsids, &parsed_jobids, &parsed_obsids)?;
             if parsed_obsids.is_empty() {
                 bail!("No obsids specified!");
             }
@@ -624,8 +2132,10 @@ fn main() -> Result<(), anyhow::Error> {
             let delivery = Delivery::validate(delivery)?;
             debug!("Using {} for delivery", delivery);
 
+            let extra_fields = parse_extra_fields(&extra)?;
+
             let delivery_format: Option<DeliveryFormat> =
-                DeliveryFormat::validate(delivery_format)?;
+                DeliveryFormat::validate_for(delivery_format, &AsvoJobType::DownloadMetadata)?;
             debug!("Using {:#?} for delivery format", delivery_format);
 
             if dry_run {
@@ -634,31 +2144,49 @@ fn main() -> Result<(), anyhow::Error> {
                     obsids.len()
                 );
             } else {
-                let client = AsvoClient::new()?;
-                let mut jobids: Vec<AsvoJobID> = Vec::with_capacity(obsids.len());
-
-                let mut submitted_count = 0;
-                for o in parsed_obsids {
-                    let j = client.submit_meta(o, delivery, delivery_format, allow_resubmit)?;
-                    if j.is_some() {
-                        let jobid = j.unwrap();
-                        info!("Submitted {} as ASVO job ID {}", o, jobid);
-                        jobids.push(jobid);
-                        submitted_count += 1;
-                    }
-                    // for the none case- the "submit_asvo" function
-                    // will have already provided user some feedback
+                if let Some(dir) = &download {
+                    create_download_dir(dir)?;
                 }
+                let client = connect(client_opts, api_key)?;
+                let jobs: Vec<(Obsid, ())> = parsed_obsids.iter().map(|&o| (o, ())).collect();
+                let submitted = submit_jobs(
+                    &jobs,
+                    concurrent_submits,
+                    submit_delay,
+                    continue_on_error,
+                    summary_json.as_deref(),
+                    print_ids,
+                    write_ids.as_deref(),
+                    |o, _| {
+                        client.submit_meta_with_extra(
+                            o,
+                            delivery.clone(),
+                            delivery_format,
+                            allow_resubmit,
+                            &extra_fields,
+                        )
+                    },
+                )?;
                 info!(
                     "Submitted {} obsids for metadata download.",
-                    submitted_count
+                    submitted.jobids.len()
                 );
 
-                if wait {
+                if wait || download.is_some() {
                     // Endlessly loop over the newly-supplied job IDs until
                     // they're all ready.
-                    wait_loop(&client, &jobids)?;
+                    let downloaded =
+                        wait_loop(&client, &submitted.jobids, download.as_deref(), false)?;
+                    // Print the paths of the metafits files, so they can be
+                    // easily used in scripts.
+                    for path in downloaded.iter().flat_map(|d| &d.paths) {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        if name.contains("metafits") {
+                            println!("{}", std::fs::canonicalize(path)?.display());
+                        }
+                    }
                 }
+                submitted.finish()?;
             }
         }
 
@@ -668,20 +2196,22 @@ fn main() -> Result<(), anyhow::Error> {
             duration,
             from_channel,
             to_channel,
+            submit_delay,
+            continue_on_error,
+            print_ids,
+            write_ids,
+            summary_json,
             wait,
             dry_run,
             allow_resubmit,
+            extra,
             verbosity,
             obsids,
         } => {
-            let (parsed_jobids, parsed_obsids) = parse_many_jobids_or_obsids(&obsids)?;
+            let (parsed_jobids, parsed_obsids) =
+                parse_many_jobids_or_obsids_with_options(&obsids, id_file_opts)?;
             // There shouldn't be any job IDs here.
-            if !parsed_jobids.is_empty() {
-                bail!(
-                    "Expected only obsids, but found these exceptions: {:?}",
-                    parsed_jobids
-                );
-            }
+            check_expected_ids(ExpectedIds::Obsids, &parsed_jobids, &parsed_obsids)?;
             if parsed_obsids.is_empty() {
                 bail!("No obsids specified!");
             }
@@ -690,43 +2220,48 @@ fn main() -> Result<(), anyhow::Error> {
             let delivery = Delivery::validate(delivery)?;
             debug!("Using {} for delivery", delivery);
 
+            let extra_fields = parse_extra_fields(&extra)?;
+
             if dry_run {
                 info!(
                     "Would have submitted {} obsids for voltage download.",
                     obsids.len()
                 );
             } else {
-                let client = AsvoClient::new()?;
-                let mut jobids: Vec<AsvoJobID> = Vec::with_capacity(obsids.len());
-                let mut submitted_count = 0;
-
-                for o in parsed_obsids {
-                    let j = client.submit_volt(
-                        o,
-                        delivery,
-                        offset,
-                        duration,
-                        from_channel,
-                        to_channel,
-                        allow_resubmit,
-                    )?;
-
-                    if j.is_some() {
-                        let jobid = j.unwrap();
-                        info!("Submitted {} as ASVO job ID {}", o, jobid);
-                        jobids.push(jobid);
-                        submitted_count += 1;
-                    }
-                    // for the none case- the "submit_asvo" function
-                    // will have already provided user some feedback
-                }
-                info!("Submitted {} obsids for voltage download.", submitted_count);
+                let client = connect(client_opts, api_key)?;
+                let jobs: Vec<(Obsid, ())> = parsed_obsids.iter().map(|&o| (o, ())).collect();
+                let submitted = submit_jobs(
+                    &jobs,
+                    1,
+                    submit_delay,
+                    continue_on_error,
+                    summary_json.as_deref(),
+                    print_ids,
+                    write_ids.as_deref(),
+                    |o, _| {
+                        client.submit_volt_with_extra(
+                            o,
+                            delivery.clone(),
+                            offset,
+                            duration,
+                            from_channel,
+                            to_channel,
+                            allow_resubmit,
+                            &extra_fields,
+                        )
+                    },
+                )?;
+                info!(
+                    "Submitted {} obsids for voltage download.",
+                    submitted.jobids.len()
+                );
 
                 if wait {
                     // Endlessly loop over the newly-supplied job IDs until
                     // they're all ready.
-                    wait_loop(&client, &jobids)?;
+                    wait_loop(&client, &submitted.jobids, None, false)?;
                 }
+                submitted.finish()?;
             }
         }
 
@@ -734,16 +2269,29 @@ fn main() -> Result<(), anyhow::Error> {
             verbosity,
             jobs,
             json,
+            events_json,
         } => {
-            let (parsed_jobids, _) = parse_many_jobids_or_obsids(&jobs)?;
-            if parsed_jobids.is_empty() {
-                bail!("No obsids specified!");
+            let (mut parsed_jobids, parsed_obsids) =
+                parse_many_jobids_or_obsids_with_options(&jobs, id_file_opts)?;
+            if parsed_jobids.is_empty() && parsed_obsids.is_empty() {
+                bail!("No jobs specified!");
             }
             init_logger(verbosity);
-            let client = AsvoClient::new()?;
+            let client = connect(client_opts, api_key)?;
+            if !parsed_obsids.is_empty() {
+                // Wait on the most recent job of each obsid.
+                let jobs = client.get_jobs()?;
+                for obsid in parsed_obsids {
+                    let jobid = jobs.latest_jobid(obsid).ok_or(AsvoError::NoObsid(obsid))?;
+                    debug!("Waiting on ASVO job ID {} for obsid {}", jobid, obsid);
+                    if !parsed_jobids.contains(&jobid) {
+                        parsed_jobids.push(jobid);
+                    }
+                }
+            }
             // Endlessly loop over the newly-supplied job IDs until
             // they're all ready.
-            wait_loop(&client, &parsed_jobids)?;
+            wait_loop(&client, &parsed_jobids, None, events_json)?;
 
             let mut jobs = client.get_jobs()?;
             if !parsed_jobids.is_empty() {
@@ -751,10 +2299,191 @@ fn main() -> Result<(), anyhow::Error> {
             }
 
             if json {
-                println!("{}", jobs.json()?);
+                println!("{}", jobs.final_state_json()?);
+            } else {
+                jobs.list_with_style(colour, colour_scheme);
+            }
+        }
+
+        Args::Cancel {
+            json,
+            dry_run,
+            verbosity,
+            jobs,
+        } => {
+            init_logger(verbosity);
+            let (parsed_jobids, parsed_obsids) =
+                parse_many_jobids_or_obsids_with_options(&jobs, id_file_opts)?;
+            // There shouldn't be any obsids here.
+            check_expected_ids(ExpectedIds::JobIds, &parsed_jobids, &parsed_obsids)?;
+            if parsed_jobids.is_empty() {
+                bail!("No jobs specified!");
+            }
+
+            let client = connect(client_opts, api_key)?;
+            if dry_run {
+                let jobs = client
+                    .get_jobs()?
+                    .retain(|j| parsed_jobids.contains(&j.jobid));
+                let found: Vec<AsvoJobID> = jobs.0.iter().map(|j| j.jobid).collect();
+                warn_unmatched("job IDs", &unmatched_ids(&parsed_jobids, &found));
+                info!("Would cancel {} jobs", jobs.0.len());
+                if json {
+                    println!("{}", jobs.json()?);
+                } else {
+                    jobs.list_with_style(colour, colour_scheme);
+                }
+                return Ok(());
+            }
+
+            let mut results = vec![];
+            let mut num_failed = 0;
+            for (jobid, result) in client.cancel_jobs(&parsed_jobids) {
+                let result = match result {
+                    Ok(Some(_)) => {
+                        info!("Cancelled ASVO job ID {}", jobid);
+                        "cancelled"
+                    }
+                    // cancel_asvo_job has already warned the user.
+                    Ok(None) => "not_found",
+                    Err(e @ AsvoError::BadRequest { .. }) => {
+                        error!("Failed to cancel ASVO job ID {}: {}", jobid, e);
+                        num_failed += 1;
+                        "validation_error"
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                results.push(serde_json::json!({ "jobid": jobid, "result": result }));
+            }
+
+            if json {
+                println!("{}", serde_json::Value::Array(results));
+            }
+            if num_failed > 0 {
+                bail!(
+                    "Failed to cancel {} of {} jobs",
+                    num_failed,
+                    parsed_jobids.len()
+                );
+            }
+        }
+
+        Args::Size {
+            json,
+            prefer,
+            delivery,
+            verbosity,
+            jobs,
+        } => {
+            init_logger(verbosity);
+            let (parsed_jobids, parsed_obsids) =
+                parse_many_jobids_or_obsids_with_options(&jobs, id_file_opts)?;
+            if parsed_jobids.is_empty() && parsed_obsids.is_empty() {
+                bail!("No jobs specified!");
+            }
+            let client = connect(client_opts, api_key)?;
+            let opts = DownloadOptions {
+                prefer,
+                delivery,
+                ..Default::default()
+            };
+            let sizes = client.download_sizes(&parsed_jobids, &parsed_obsids, &opts)?;
+            let total: u64 = sizes.iter().map(|(_, _, size)| size).sum();
+            if json {
+                let jobs: Vec<_> = sizes
+                    .iter()
+                    .map(|(jobid, obsid, size)| {
+                        serde_json::json!({ "jobid": jobid, "obsid": obsid, "size": size })
+                    })
+                    .collect();
+                println!("{}", serde_json::json!({ "jobs": jobs, "total": total }));
+            } else {
+                for (jobid, obsid, size) in &sizes {
+                    println!(
+                        "{}\t{}\t{}",
+                        jobid,
+                        obsid,
+                        bytesize::ByteSize(*size).to_string_as(true)
+                    );
+                }
+                println!("total\t\t{}", bytesize::ByteSize(total).to_string_as(true));
+            }
+        }
+
+        Args::ConvParams { json } => {
+            let default = |key| DEFAULT_CONVERSION_PARAMETERS.get(key).copied();
+            if json {
+                let params: Vec<_> = KNOWN_CONVERSION_PARAMETERS
+                    .iter()
+                    .map(|p| {
+                        serde_json::json!({
+                            "key": p.key,
+                            "default": default(p.key),
+                            "values": p.values,
+                            "description": p.description,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::Value::Array(params));
+            } else {
+                for p in KNOWN_CONVERSION_PARAMETERS {
+                    println!("{}", p.key);
+                    println!("    {}", p.description);
+                    println!("    Values:  {}", p.values);
+                    println!(
+                        "    Default: {}",
+                        default(p.key).unwrap_or("(the MWA ASVO's)")
+                    );
+                }
+            }
+        }
+
+        Args::Clean {
+            dir,
+            dry_run,
+            verbosity,
+            jobids_or_obsids,
+        } => {
+            init_logger(verbosity);
+            let names = if jobids_or_obsids.is_empty() {
+                None
             } else {
-                jobs.list();
+                // Only the names of the jobs' files are needed, which the job
+                // listing has.
+                let (jobids, obsids) =
+                    parse_many_jobids_or_obsids_with_options(&jobids_or_obsids, id_file_opts)?;
+                let client = connect(client_opts, api_key)?;
+                let jobs = client
+                    .get_jobs()?
+                    .retain(|j| jobids.contains(&j.jobid) || obsids.contains(&j.obsid));
+                let mut names = vec![];
+                for job in jobs.0 {
+                    for f in job.files.unwrap_or_default() {
+                        names.push(f.output_filename()?);
+                    }
+                }
+                Some(names)
+            };
+
+            let partials = find_partial_downloads(&dir, names.as_deref())
+                .with_context(|| format!("Couldn't search {} for partial downloads", dir))?;
+            let mut total_bytes = 0;
+            for path in &partials {
+                total_bytes += std::fs::metadata(path).map_or(0, |m| m.len());
+                if dry_run {
+                    println!("{}", path.display());
+                } else {
+                    std::fs::remove_file(path)
+                        .with_context(|| format!("Couldn't remove {}", path.display()))?;
+                    debug!("Removed {}", path.display());
+                }
             }
+            info!(
+                "{} {} partial download files ({})",
+                if dry_run { "Would remove" } else { "Removed" },
+                partials.len(),
+                bytesize::ByteSize(total_bytes).to_string_as(true)
+            );
         }
     }
 
@@ -5,13 +5,18 @@
 //! Small helper utility functions.
 
 use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::io::BufRead;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
+use log::warn;
+use sha1::{Digest, Sha1};
 use thiserror::Error;
 
 use crate::asvo::*;
-use crate::obsid::Obsid;
+use crate::obsid::{Obsid, ObsidError};
 
 enum ObsidOrJobID {
     /// This is an obsid.
@@ -20,46 +25,242 @@ enum ObsidOrJobID {
     J(AsvoJobID),
 }
 
-fn parse_jobid_or_obsid(s: &str) -> Option<ObsidOrJobID> {
+/// Parse a job ID or an obsid. An int in the obsid range is an obsid, and
+/// any other int is a job ID, unless it has a "j:" or "o:" prefix, which
+/// forces it to be read as a job ID or obsid (e.g. "j:1234567890"). `None` if
+/// the string isn't an int at all (e.g. it's a file).
+fn parse_jobid_or_obsid(s: &str) -> Result<Option<ObsidOrJobID>, ParseError> {
+    if let Some(jobid) = s.strip_prefix("j:") {
+        return match jobid.parse() {
+            Ok(jobid) => Ok(Some(ObsidOrJobID::J(jobid))),
+            Err(_) => Err(ParseError::InvalidPrefixedId(s.to_string())),
+        };
+    }
+    if let Some(obsid) = s.strip_prefix("o:") {
+        return match obsid.parse() {
+            Ok(obsid) => Ok(Some(ObsidOrJobID::O(Obsid::validate(obsid)?))),
+            Err(_) => Err(ParseError::InvalidPrefixedId(s.to_string())),
+        };
+    }
     match s.parse::<u64>() {
         // We successfully parsed an int.
         Ok(i) => {
             match Obsid::validate(i) {
                 // This int is an obsid.
-                Ok(o) => Some(ObsidOrJobID::O(o)),
+                Ok(o) => Ok(Some(ObsidOrJobID::O(o))),
                 // This int isn't an obsid; assume it is a jobid.
-                Err(_) => Some(ObsidOrJobID::J(i as AsvoJobID)),
+                Err(ObsidError::OutOfBounds { .. } | ObsidError::WrongNumDigits(_)) => {
+                    Ok(Some(ObsidOrJobID::J(i as AsvoJobID)))
+                }
+                // e.g. the obsid bounds in the environment are invalid.
+                Err(e) => Err(e.into()),
             }
         }
-        // Could not parse the string as an int; we must fail.
-        Err(_) => None,
+        // Could not parse the string as an int.
+        Err(_) => Ok(None),
+    }
+}
+
+/// The CSV column that job IDs and obsids are read from by default.
+pub const DEFAULT_OBSID_COLUMN: &str = "obsid";
+
+/// How to read job IDs and obsids from files.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct IdFileOptions {
+    /// The column that job IDs and obsids are read from in CSV files.
+    pub column: String,
+    /// In files which aren't CSV, only read the first whitespace-delimited
+    /// token of each line, ignoring the rest (e.g. notes about an obsid).
+    /// Otherwise, every token must be a job ID or obsid.
+    pub first_column: bool,
+}
+
+impl Default for IdFileOptions {
+    fn default() -> Self {
+        IdFileOptions {
+            column: DEFAULT_OBSID_COLUMN.to_string(),
+            first_column: false,
+        }
     }
 }
 
 /// Read a file, and return two vectors of ASVO job IDs and obsids. Fail if any
-/// string in the file cannot be parsed as either.
+/// string in the file cannot be parsed as either. Files with a ".csv"
+/// extension are read as CSV, taking the job IDs and obsids from the
+/// [DEFAULT_OBSID_COLUMN] column.
 pub fn parse_jobids_and_obsids_from_file<T: AsRef<Path>>(
     f: T,
+) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
+    parse_jobids_and_obsids_from_file_with_column(f, DEFAULT_OBSID_COLUMN)
+}
+
+/// As [parse_jobids_and_obsids_from_file], but CSV files are read from the
+/// column named `column`.
+pub fn parse_jobids_and_obsids_from_file_with_column<T: AsRef<Path>>(
+    f: T,
+    column: &str,
+) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
+    let opts = IdFileOptions {
+        column: column.to_string(),
+        ..Default::default()
+    };
+    parse_jobids_and_obsids_from_file_with_options(f, &opts)
+}
+
+/// As [parse_jobids_and_obsids_from_file], but the file is read as described
+/// by `opts`.
+pub fn parse_jobids_and_obsids_from_file_with_options<T: AsRef<Path>>(
+    f: T,
+    opts: &IdFileOptions,
+) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
+    let f = f.as_ref();
+    // Open the file.
+    let reader = std::io::BufReader::new(std::fs::File::open(f)?);
+    let name = f.display().to_string();
+    let is_csv = f.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        parse_jobids_and_obsids_from_csv(reader, &name, &opts.column)
+    } else {
+        parse_jobids_and_obsids_from_reader(reader, &name, opts.first_column)
+    }
+}
+
+/// Read ASVO job IDs and obsids from the column named `column` of a CSV file,
+/// e.g. an export from an observation database. The first non-blank line must
+/// be the header. Empty cells are ignored, and values may be quoted.
+fn parse_jobids_and_obsids_from_csv<R: BufRead>(
+    reader: R,
+    name: &str,
+    column: &str,
+) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
+    let (header, rows) = read_csv(reader)?;
+    let col = match header.iter().position(|c| c.eq_ignore_ascii_case(column)) {
+        Some(i) => i,
+        None => {
+            return Err(ParseError::CsvNoColumn {
+                file: name.to_string(),
+                column: column.to_string(),
+            })
+        }
+    };
+
+    let mut obsids = vec![];
+    let mut jobids = vec![];
+    for (line, cells) in rows {
+        let text = match cells.get(col) {
+            Some(cell) => cell.clone(),
+            None => {
+                return Err(ParseError::CsvWrongNumColumns {
+                    file: name.to_string(),
+                    line,
+                    found: cells.len(),
+                    expected: header.len(),
+                })
+            }
+        };
+        if text.is_empty() {
+            continue;
+        }
+        match parse_jobid_or_obsid(&text) {
+            Ok(Some(ObsidOrJobID::O(obsid))) => obsids.push(obsid),
+            Ok(Some(ObsidOrJobID::J(jobid))) => jobids.push(jobid),
+            // A problem with the obsid bounds isn't a problem with the file.
+            Err(
+                e @ ParseError::Obsid(
+                    ObsidError::InvalidBoundVar { .. } | ObsidError::InvalidBounds { .. },
+                ),
+            ) => return Err(e),
+            Ok(None) | Err(_) => {
+                return Err(ParseError::InsideFile {
+                    file: name.to_string(),
+                    text,
+                })
+            }
+        }
+    }
+
+    Ok((jobids, obsids))
+}
+
+/// A row of a CSV file: its line number, and its cells.
+type CsvRow = (usize, Vec<String>);
+
+/// Read a CSV file, returning its header (the first non-blank line) and each
+/// of the following non-blank rows. Rows are split with [split_csv_row]. A
+/// file without a header has an empty header.
+fn read_csv<R: BufRead>(reader: R) -> std::io::Result<(Vec<String>, Vec<CsvRow>)> {
+    let mut header = None;
+    let mut rows = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let cells = split_csv_row(&line);
+        match header {
+            None => header = Some(cells),
+            Some(_) => rows.push((i + 1, cells)),
+        }
+    }
+    Ok((header.unwrap_or_default(), rows))
+}
+
+/// Split a line of a CSV file into its cells, trimming whitespace around
+/// them. Cells may be quoted, so that they can contain commas; inside quotes,
+/// a doubled quote is a literal quote.
+fn split_csv_row(line: &str) -> Vec<String> {
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+/// Read whitespace-delimited ASVO job IDs and obsids from a reader, and return
+/// them as two vectors. If `first_column` is set, only the first token of each
+/// line is read. `name` is used to describe the reader in errors.
+fn parse_jobids_and_obsids_from_reader<R: BufRead>(
+    mut reader: R,
+    name: &str,
+    first_column: bool,
 ) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
     let mut obsids = vec![];
     let mut jobids = vec![];
 
-    // Open the file.
-    let mut reader = std::io::BufReader::new(std::fs::File::open(&f)?);
     let mut line = String::new();
     // For each line...
     while reader.read_line(&mut line)? > 0 {
         // ... split the whitespace and try to parse
         // obsids. Fail if whitespace-delimited text
         // can't be parsed into an int.
-        for text in line.split_whitespace() {
+        let tokens = line.split_whitespace();
+        let tokens = tokens.take(if first_column { 1 } else { usize::MAX });
+        for text in tokens {
             match parse_jobid_or_obsid(text) {
-                Some(ObsidOrJobID::O(obsid)) => obsids.push(obsid),
-                Some(ObsidOrJobID::J(jobid)) => jobids.push(jobid),
+                Ok(Some(ObsidOrJobID::O(obsid))) => obsids.push(obsid),
+                Ok(Some(ObsidOrJobID::J(jobid))) => jobids.push(jobid),
+                // A problem with the obsid bounds isn't a problem with the file.
+                Err(
+                    e @ ParseError::Obsid(
+                        ObsidError::InvalidBoundVar { .. } | ObsidError::InvalidBounds { .. },
+                    ),
+                ) => return Err(e),
                 // `text` could not be parsed; so we must fail.
-                None => {
+                Ok(None) | Err(_) => {
                     return Err(ParseError::InsideFile {
-                        file: f.as_ref().display().to_string(),
+                        file: name.to_string(),
                         text: text.to_string(),
                     })
                 }
@@ -72,9 +273,34 @@ pub fn parse_jobids_and_obsids_from_file<T: AsRef<Path>>(
 }
 
 /// Parse a string of ASVO job IDs, obsids, or files containing job IDs or
-/// obsids into two vectors of job IDs and obsids.
+/// obsids into two vectors of job IDs and obsids. A string of "-" reads job IDs
+/// and obsids from stdin. Identifiers which are given more than once (e.g. in
+/// overlapping files) are only kept once, in the order they were first seen,
+/// and a warning is logged.
 pub fn parse_many_jobids_or_obsids(
     strings: &[String],
+) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
+    parse_many_jobids_or_obsids_with_column(strings, DEFAULT_OBSID_COLUMN)
+}
+
+/// As [parse_many_jobids_or_obsids], but CSV files are read from the column
+/// named `column`.
+pub fn parse_many_jobids_or_obsids_with_column(
+    strings: &[String],
+    column: &str,
+) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
+    let opts = IdFileOptions {
+        column: column.to_string(),
+        ..Default::default()
+    };
+    parse_many_jobids_or_obsids_with_options(strings, &opts)
+}
+
+/// As [parse_many_jobids_or_obsids], but files (and stdin) are read as
+/// described by `opts`.
+pub fn parse_many_jobids_or_obsids_with_options(
+    strings: &[String],
+    opts: &IdFileOptions,
 ) -> Result<(Vec<AsvoJobID>, Vec<Obsid>), ParseError> {
     // Attempt to parse all arguments as ints. If they aren't 10
     // digits long, assume they are ASVO job IDs. If any argument is
@@ -82,20 +308,113 @@ pub fn parse_many_jobids_or_obsids(
     let mut jobids = vec![];
     let mut obsids = vec![];
     for s in strings {
-        match parse_jobid_or_obsid(s) {
+        match parse_jobid_or_obsid(s)? {
             Some(ObsidOrJobID::O(obsid)) => obsids.push(obsid),
             Some(ObsidOrJobID::J(jobid)) => jobids.push(jobid),
+            // "-" means read from stdin.
+            None if s == "-" => {
+                let (mut j, mut o) = parse_jobids_and_obsids_from_reader(
+                    std::io::stdin().lock(),
+                    "stdin",
+                    opts.first_column,
+                )?;
+                jobids.append(&mut j);
+                obsids.append(&mut o);
+            }
             // Could not parse the string as an int; assume it is a
             // file and unpack it.
             None => {
-                let (mut j, mut o) = parse_jobids_and_obsids_from_file(s)?;
+                let (mut j, mut o) = parse_jobids_and_obsids_from_file_with_options(s, opts)?;
                 jobids.append(&mut j);
                 obsids.append(&mut o);
             }
         }
     }
 
-    Ok((jobids, obsids))
+    Ok((dedup_ids(jobids, "job IDs"), dedup_ids(obsids, "obsids")))
+}
+
+/// Remove repeated identifiers, keeping the first of each, and warn about any
+/// that were removed.
+fn dedup_ids<T: PartialEq + Copy + Display>(ids: Vec<T>, kind: &str) -> Vec<T> {
+    let mut unique = Vec::with_capacity(ids.len());
+    let mut duplicates = vec![];
+    for id in ids {
+        if !unique.contains(&id) {
+            unique.push(id);
+        } else if !duplicates.contains(&id) {
+            duplicates.push(id);
+        }
+    }
+    if !duplicates.is_empty() {
+        warn!(
+            "Ignoring duplicate {}: {}",
+            kind,
+            duplicates
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    unique
+}
+
+/// The kind of identifiers expected from [parse_many_jobids_or_obsids].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedIds {
+    /// Only obsids are expected.
+    Obsids,
+    /// Only ASVO job IDs are expected.
+    JobIds,
+}
+
+impl FromStr for ExpectedIds {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "obsids" => Ok(ExpectedIds::Obsids),
+            "jobids" => Ok(ExpectedIds::JobIds),
+            _ => Err(ParseError::InvalidExpectedIds(s.to_string())),
+        }
+    }
+}
+
+/// Check that the output of [parse_many_jobids_or_obsids] only contains the
+/// expected kind of identifiers. If it doesn't, the offending values are
+/// returned in the error.
+pub fn check_expected_ids(
+    expected: ExpectedIds,
+    jobids: &[AsvoJobID],
+    obsids: &[Obsid],
+) -> Result<(), ParseError> {
+    match expected {
+        ExpectedIds::Obsids if !jobids.is_empty() => {
+            Err(ParseError::UnexpectedJobIds(jobids.to_vec()))
+        }
+        ExpectedIds::JobIds if !obsids.is_empty() => {
+            Err(ParseError::UnexpectedObsids(obsids.to_vec()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse a list of individual "key=value" strings into a [BTreeMap]. Unlike
+/// [parse_key_value_pairs], only the first '=' separates the key from the
+/// value, so values may contain commas and '=' characters.
+pub fn parse_extra_fields<S: AsRef<str>>(fields: &[S]) -> Result<BTreeMap<&str, &str>, ParseError> {
+    let mut map = BTreeMap::new();
+    for field in fields {
+        let field = field.as_ref();
+        match field.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                map.insert(key.trim(), value.trim());
+            }
+            _ => return Err(ParseError::NotKeyValue(field.to_string())),
+        }
+    }
+    Ok(map)
 }
 
 /// Parse a string of key-value pairs (e.g. "avg_time_res=0.5,avg_freq_res=10") into a
@@ -130,6 +449,317 @@ pub fn parse_key_value_pairs(s: &str) -> Result<BTreeMap<&str, &str>, ParseError
     Ok(map)
 }
 
+/// A single row of a conversion CSV file; an obsid with its own delivery and
+/// conversion parameters.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConversionCsvRow {
+    /// The obsid to be converted.
+    pub obsid: Obsid,
+    /// Where the job should be delivered, if specified for this row.
+    pub delivery: Option<String>,
+    /// All other non-empty columns as key-value pairs (e.g.
+    /// "avg_time_res=0.5,avg_freq_res=10"), to be parsed with
+    /// [parse_key_value_pairs].
+    pub parameters: String,
+}
+
+/// Read a CSV file of conversion jobs. The first line must be a header, and
+/// must contain an "obsid" column. A "delivery" column is optional; all other
+/// columns are treated as conversion parameters. Empty cells are ignored.
+pub fn parse_conversion_csv<T: AsRef<Path>>(f: T) -> Result<Vec<ConversionCsvRow>, ParseError> {
+    let file = f.as_ref().display().to_string();
+    let reader = std::io::BufReader::new(std::fs::File::open(&f)?);
+    let (header, rows) = read_csv(reader)?;
+    let obsid_col = match header.iter().position(|c| c == "obsid") {
+        Some(i) => i,
+        None => return Err(ParseError::CsvNoObsidColumn(file)),
+    };
+
+    let mut jobs = vec![];
+    for (line, cells) in rows {
+        if cells.len() != header.len() {
+            return Err(ParseError::CsvWrongNumColumns {
+                file,
+                line,
+                found: cells.len(),
+                expected: header.len(),
+            });
+        }
+
+        let mut delivery = None;
+        let mut parameters = vec![];
+        for (col, cell) in header.iter().zip(cells.iter()) {
+            if cell.is_empty() || col == "obsid" {
+                continue;
+            }
+            if col == "delivery" {
+                delivery = Some(cell.to_string());
+            } else {
+                parameters.push(format!("{}={}", col, cell));
+            }
+        }
+        jobs.push(ConversionCsvRow {
+            obsid: cells[obsid_col].parse()?,
+            delivery,
+            parameters: parameters.join(","),
+        });
+    }
+
+    Ok(jobs)
+}
+
+/// Find the identifiers in `wanted` that aren't in `found`, e.g. to report
+/// obsids that have no ASVO jobs. The order of `wanted` is kept, and duplicates
+/// are only reported once.
+pub fn unmatched_ids<T: PartialEq + Copy>(wanted: &[T], found: &[T]) -> Vec<T> {
+    let mut unmatched = vec![];
+    for id in wanted {
+        if !found.contains(id) && !unmatched.contains(id) {
+            unmatched.push(*id);
+        }
+    }
+    unmatched
+}
+
+/// Read the jobs recorded in a download manifest (as written by
+/// [append_to_download_manifest]) as pairs of job IDs and obsids. A manifest
+/// which doesn't exist yet has no jobs.
+pub fn read_download_manifest<T: AsRef<Path>>(f: T) -> Result<Vec<(AsvoJobID, Obsid)>, ParseError> {
+    let f = f.as_ref();
+    let contents = match std::fs::read_to_string(f) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut jobs = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let invalid = || ParseError::InvalidManifestLine {
+            file: f.display().to_string(),
+            line: i + 1,
+            text: text.to_string(),
+        };
+        match text.split_whitespace().collect::<Vec<_>>()[..] {
+            [jobid, obsid] => jobs.push((
+                jobid.parse().map_err(|_| invalid())?,
+                obsid.parse().map_err(|_| invalid())?,
+            )),
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(jobs)
+}
+
+/// Record a fully downloaded (and verified) job in a download manifest, so
+/// that it can be skipped when the download is run again.
+pub fn append_to_download_manifest<T: AsRef<Path>>(
+    f: T,
+    jobid: AsvoJobID,
+    obsid: Obsid,
+) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(f)?;
+    writeln!(file, "{} {}", jobid, obsid)
+}
+
+/// The default file in which `list --only-new` records the biggest job ID it
+/// has seen: "giant-squid/last_listed_jobid" in $XDG_CONFIG_HOME, or in
+/// $HOME/.config. `None` if neither is set.
+pub fn default_list_marker_path() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("giant-squid").join("last_listed_jobid"))
+}
+
+/// Read the job ID recorded in a list marker (as written by
+/// [write_list_marker]). A marker which doesn't exist yet has no job ID.
+pub fn read_list_marker<T: AsRef<Path>>(f: T) -> Result<Option<AsvoJobID>, ParseError> {
+    let f = f.as_ref();
+    let contents = match std::fs::read_to_string(f) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let text = contents.trim();
+    match text.parse() {
+        Ok(jobid) => Ok(Some(jobid)),
+        Err(_) => Err(ParseError::InsideFile {
+            file: f.display().to_string(),
+            text: text.to_string(),
+        }),
+    }
+}
+
+/// Record the biggest job ID seen by `list --only-new` in a list marker,
+/// creating its directory if necessary.
+pub fn write_list_marker<T: AsRef<Path>>(f: T, jobid: AsvoJobID) -> Result<(), std::io::Error> {
+    let f = f.as_ref();
+    if let Some(dir) = f.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(f, format!("{}\n", jobid))
+}
+
+/// Append ASVO job IDs to a file `f`, one per line, e.g. to download the jobs
+/// later. The file can be read back with [parse_jobids_and_obsids_from_file],
+/// as long as it isn't named like a CSV file.
+pub fn append_jobids<T: AsRef<Path>>(f: T, jobids: &[AsvoJobID]) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(f)?;
+    for jobid in jobids {
+        writeln!(file, "{}", jobid)?;
+    }
+    Ok(())
+}
+
+/// Append SHA-1 hashes of files to a checksum file `f`, in the format written
+/// by `sha1sum` (one "<hash>  <path>" line per file), so the files can be
+/// checked later with `sha1sum -c`.
+pub fn append_checksums<T: AsRef<Path>>(
+    f: T,
+    checksums: &[(PathBuf, String)],
+) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(f)?;
+    for (path, hash) in checksums {
+        writeln!(file, "{}  {}", hash, path.display())?;
+    }
+    Ok(())
+}
+
+/// Read a checksum file in the format written by `sha1sum` (one
+/// "<hash>  <path>" line per file; a "*" before the path, for files hashed in
+/// binary mode, is ignored), e.g. as written by [append_checksums]. Blank
+/// lines and lines starting with "#" are skipped.
+pub fn read_checksums<T: AsRef<Path>>(f: T) -> Result<Vec<(PathBuf, String)>, ParseError> {
+    let f = f.as_ref();
+    let contents = std::fs::read_to_string(f)?;
+    let mut checksums = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(' ') {
+            Some((hash, path))
+                if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                let path = path.strip_prefix([' ', '*']).unwrap_or(path);
+                checksums.push((PathBuf::from(path), hash.to_ascii_lowercase()));
+            }
+            _ => {
+                return Err(ParseError::InvalidChecksumLine {
+                    file: f.display().to_string(),
+                    line: i + 1,
+                    text: line.to_string(),
+                })
+            }
+        }
+    }
+    Ok(checksums)
+}
+
+/// Find the checksum of `path` in a list read by [read_checksums]: the
+/// checksum of the same path or, failing that, of the only file with the same
+/// name, as the checksums may have been made in another directory.
+pub fn find_checksum<'a>(checksums: &'a [(PathBuf, String)], path: &Path) -> Option<&'a str> {
+    if let Some((_, hash)) = checksums.iter().find(|(p, _)| p == path) {
+        return Some(hash);
+    }
+    let name = path.file_name()?;
+    let mut same_name = checksums
+        .iter()
+        .filter(|(p, _)| p.file_name() == Some(name));
+    match (same_name.next(), same_name.next()) {
+        (Some((_, hash)), None) => Some(hash),
+        _ => None,
+    }
+}
+
+/// Get the SHA-1 hash of a file, as lowercase hex.
+pub fn sha1_file<T: AsRef<Path>>(f: T) -> Result<String, std::io::Error> {
+    let mut hasher = Sha1::new();
+    std::io::copy(&mut std::fs::File::open(f)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Find the partial downloads (".part" files, and the ".part.etag" files
+/// kept alongside them) that giant-squid left in `dir` or its subdirectories,
+/// e.g. after interrupted downloads. If `names` is given, only partial
+/// downloads of files with these names (e.g. a job's archives) are found. The
+/// paths are sorted.
+pub fn find_partial_downloads<T: AsRef<Path>>(
+    dir: T,
+    names: Option<&[String]>,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut found = vec![];
+    let mut dirs = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            let name = match file_name
+                .strip_suffix(".part")
+                .or_else(|| file_name.strip_suffix(".part.etag"))
+            {
+                Some(name) => name,
+                None => continue,
+            };
+            if names.map_or(true, |names| names.iter().any(|n| n == name)) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Format a duration for humans, e.g. "2min3.45s" or "12.345s".
+pub fn format_duration(d: Duration) -> String {
+    if d.as_secs() > 60 {
+        format!(
+            "{}min{:.2}s",
+            d.as_secs() / 60,
+            (d.as_millis() as f64 / 1e3) % 60.0
+        )
+    } else {
+        format!("{}s", d.as_millis() as f64 / 1e3)
+    }
+}
+
+/// Format the average rate of transferring `bytes` in the duration `d`, e.g.
+/// "1.2 GiB/s".
+pub fn format_rate(bytes: u64, d: Duration) -> String {
+    // Avoid dividing by zero for very quick transfers.
+    let millis = d.as_millis().max(1);
+    format!(
+        "{}/s",
+        bytesize::ByteSize((bytes as u128 * 1000 / millis) as u64).to_string_as(true)
+    )
+}
+
 #[derive(Error, Debug)]
 pub enum ParseError {
     /// When a whitespace-delimited string inside a file isn't an integer, this
@@ -137,10 +767,64 @@ pub enum ParseError {
     #[error("'{text}' in file {file} could not be parsed as an int.")]
     InsideFile { file: String, text: String },
 
+    /// A job ID or obsid with a "j:" or "o:" prefix isn't an int.
+    #[error("'{0}' could not be parsed as a job ID ('j:') or obsid ('o:').")]
+    InvalidPrefixedId(String),
+
     /// Invalid number of items when parsing key-value pairs.
     #[error("Could not parse {0} into a key-value pair.")]
     NotKeyValue(String),
 
+    /// An invalid kind of expected identifiers.
+    #[error("Could not parse {0} as an expectation; expected 'obsids' or 'jobids'.")]
+    InvalidExpectedIds(String),
+
+    /// Expected only obsids, but found job IDs.
+    #[error("Expected only obsids, but found these exceptions: {0:?}")]
+    UnexpectedJobIds(Vec<AsvoJobID>),
+
+    /// Expected only job IDs, but found obsids.
+    #[error("Expected only job IDs, but found these exceptions: {0:?}")]
+    UnexpectedObsids(Vec<Obsid>),
+
+    /// A CSV file doesn't have an "obsid" column in its header.
+    #[error("CSV file {0} doesn't have an 'obsid' column in its header.")]
+    CsvNoObsidColumn(String),
+
+    /// A CSV file of job IDs or obsids doesn't have the requested column.
+    #[error("CSV file {file} doesn't have a '{column}' column in its header.")]
+    CsvNoColumn { file: String, column: String },
+
+    /// A line of a CSV file doesn't have the same number of columns as the
+    /// header.
+    #[error("Line {line} of CSV file {file} has {found} columns, but the header has {expected}.")]
+    CsvWrongNumColumns {
+        file: String,
+        line: usize,
+        found: usize,
+        expected: usize,
+    },
+
+    /// A line of a download manifest isn't a job ID and an obsid.
+    #[error("Line {line} of download manifest {file} isn't a job ID and an obsid: '{text}'")]
+    InvalidManifestLine {
+        file: String,
+        line: usize,
+        text: String,
+    },
+
+    /// A line of a checksum file isn't a SHA-1 hash and a path.
+    #[error("Line {line} of checksum file {file} isn't a SHA-1 hash and a path: '{text}'")]
+    InvalidChecksumLine {
+        file: String,
+        line: usize,
+        text: String,
+    },
+
+    /// An obsid error.
+    #[error("{0}")]
+    Obsid(#[from] ObsidError),
+
     /// An IO error.
     #[error("{0}")]
     IO(#[from] std::io::Error),
@@ -159,6 +843,27 @@ mod tests {
         assert_eq!(map.get("avg_freq_res"), Some(&"10"));
     }
 
+    #[test]
+    fn parse_extra_fields_simple() {
+        let result = parse_extra_fields(&["new_param=1", "list=a,b=c"]);
+        assert!(result.is_ok());
+        let map = result.unwrap();
+        assert_eq!(map.get("new_param"), Some(&"1"));
+        assert_eq!(map.get("list"), Some(&"a,b=c"));
+    }
+
+    #[test]
+    fn parse_extra_fields_invalid() {
+        assert!(matches!(
+            parse_extra_fields(&["new_param"]),
+            Err(ParseError::NotKeyValue(_))
+        ));
+        assert!(matches!(
+            parse_extra_fields(&["=1"]),
+            Err(ParseError::NotKeyValue(_))
+        ));
+    }
+
     #[test]
     fn parse_map_complex() {
         let result = parse_key_value_pairs(
@@ -172,6 +877,367 @@ mod tests {
         assert_eq!(map.get("avg_freq_res"), Some(&"10"));
     }
 
+    #[test]
+    fn parse_conversion_csv_works() {
+        let path = std::env::temp_dir().join("giant_squid_parse_conversion_csv_works.csv");
+        std::fs::write(
+            &path,
+            "obsid,avg_freq_res,avg_time_res,delivery\n\
+             1061311664,10,0.5,scratch\n\
+             \n\
+             1061311784,, 2,\n",
+        )
+        .unwrap();
+        let result = parse_conversion_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        let rows = result.unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].obsid, "1061311664".parse().unwrap());
+        assert_eq!(rows[0].delivery.as_deref(), Some("scratch"));
+        assert_eq!(rows[0].parameters, "avg_freq_res=10,avg_time_res=0.5");
+        assert_eq!(rows[1].delivery, None);
+        let map = parse_key_value_pairs(&rows[1].parameters).unwrap();
+        assert_eq!(map.get("avg_time_res"), Some(&"2"));
+        assert_eq!(map.get("avg_freq_res"), None);
+    }
+
+    #[test]
+    fn bad_parse_conversion_csv() {
+        let path = std::env::temp_dir().join("giant_squid_bad_parse_conversion_csv.csv");
+        std::fs::write(&path, "avg_freq_res,delivery\n10,scratch\n").unwrap();
+        let result = parse_conversion_csv(&path);
+        assert!(matches!(result, Err(ParseError::CsvNoObsidColumn(_))));
+
+        std::fs::write(&path, "obsid,avg_freq_res\n1061311664,10,scratch\n").unwrap();
+        let result = parse_conversion_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(ParseError::CsvWrongNumColumns { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_from_reader() {
+        let text = "1061311664 12345\n67890\n\n1061311784\n";
+        let (jobids, obsids) =
+            parse_jobids_and_obsids_from_reader(text.as_bytes(), "test", false).unwrap();
+        assert_eq!(jobids, vec![12345, 67890]);
+        assert_eq!(obsids, Obsid::from_string("1061311664 1061311784").unwrap());
+
+        let result = parse_jobids_and_obsids_from_reader("12345 abc".as_bytes(), "test", false);
+        assert!(matches!(result, Err(ParseError::InsideFile { text, .. }) if text == "abc"));
+    }
+
+    #[test]
+    fn parse_from_reader_first_column() {
+        let text = "1061311664\tEoR0, good\n12345 resubmitted\n\n1061311784\n";
+        let (jobids, obsids) =
+            parse_jobids_and_obsids_from_reader(text.as_bytes(), "test", true).unwrap();
+        assert_eq!(jobids, vec![12345]);
+        assert_eq!(obsids, Obsid::from_string("1061311664 1061311784").unwrap());
+
+        // The first column is still checked.
+        let result =
+            parse_jobids_and_obsids_from_reader("1o61311664 note".as_bytes(), "test", true);
+        assert!(matches!(result, Err(ParseError::InsideFile { text, .. }) if text == "1o61311664"));
+    }
+
+    #[test]
+    fn parse_many_prefixed() {
+        let strings = [
+            "j:1234567890".to_string(),
+            "o:1061311664".to_string(),
+            "j:12345".to_string(),
+            "1061311784".to_string(),
+        ];
+        let (jobids, obsids) = parse_many_jobids_or_obsids(&strings).unwrap();
+        assert_eq!(jobids, vec![1234567890, 12345]);
+        assert_eq!(obsids, Obsid::from_string("1061311664 1061311784").unwrap());
+
+        // The prefixed text must still be a job ID or obsid.
+        assert!(matches!(
+            parse_many_jobids_or_obsids(&["j:abc".to_string()]),
+            Err(ParseError::InvalidPrefixedId(s)) if s == "j:abc"
+        ));
+        assert!(matches!(
+            parse_many_jobids_or_obsids(&["o:12345".to_string()]),
+            Err(ParseError::Obsid(_))
+        ));
+
+        let (jobids, obsids) = parse_jobids_and_obsids_from_reader(
+            "j:1061311664 o:1061311784".as_bytes(),
+            "test",
+            false,
+        )
+        .unwrap();
+        assert_eq!(jobids, vec![1061311664]);
+        assert_eq!(obsids, Obsid::from_string("1061311784").unwrap());
+    }
+
+    #[test]
+    fn parse_many_dedups() {
+        let path = std::env::temp_dir().join("giant_squid_parse_many_dedups.txt");
+        std::fs::write(&path, "1061311784 12345\n1061311664\n").unwrap();
+        let path_str = path.display().to_string();
+        let strings = [
+            "1061311664".to_string(),
+            path_str.clone(),
+            "67890".to_string(),
+            path_str,
+            "12345".to_string(),
+        ];
+        let result = parse_many_jobids_or_obsids(&strings);
+        std::fs::remove_file(&path).unwrap();
+        let (jobids, obsids) = result.unwrap();
+        assert_eq!(jobids, vec![12345, 67890]);
+        assert_eq!(obsids, Obsid::from_string("1061311664 1061311784").unwrap());
+    }
+
+    #[test]
+    fn parse_many_reads_csv_column() {
+        let path = std::env::temp_dir().join("giant_squid_parse_many_reads_csv_column.csv");
+        std::fs::write(
+            &path,
+            "\nname,\"Obs_ID\",duration\nEoR0,\"1061311664\",112\nEoR1,,112\nEoR2,1061311784,112\n",
+        )
+        .unwrap();
+        let strings = [path.display().to_string()];
+        let result = parse_many_jobids_or_obsids_with_column(&strings, "obs_id");
+        let missing = parse_many_jobids_or_obsids(&strings);
+        std::fs::remove_file(&path).unwrap();
+
+        let (jobids, obsids) = result.unwrap();
+        assert!(jobids.is_empty());
+        assert_eq!(obsids, Obsid::from_string("1061311664 1061311784").unwrap());
+        assert!(matches!(
+            missing,
+            Err(ParseError::CsvNoColumn { column, .. }) if column == "obsid"
+        ));
+    }
+
+    #[test]
+    fn parse_many_reads_csv_quoted_commas() {
+        let path = std::env::temp_dir().join("giant_squid_parse_many_reads_csv_quoted_commas.csv");
+        std::fs::write(
+            &path,
+            "notes,obsid\n\"EoR0, night 1\",1061311664\n\"a \"\"quoted\"\", note\",1061311784\n",
+        )
+        .unwrap();
+        let strings = [path.display().to_string()];
+        let result = parse_many_jobids_or_obsids(&strings);
+        std::fs::remove_file(&path).unwrap();
+
+        let (jobids, obsids) = result.unwrap();
+        assert!(jobids.is_empty());
+        assert_eq!(obsids, Obsid::from_string("1061311664 1061311784").unwrap());
+        assert_eq!(
+            split_csv_row(r#" "a, ""b""" , c,"#),
+            vec![r#"a, "b""#, "c", ""]
+        );
+    }
+
+    #[test]
+    fn check_expected_ids_works() {
+        let obsids = Obsid::from_string("1061311664 1061311784").unwrap();
+        let jobids = vec![12345, 67890];
+        assert!(check_expected_ids(ExpectedIds::Obsids, &[], &obsids).is_ok());
+        assert!(check_expected_ids(ExpectedIds::JobIds, &jobids, &[]).is_ok());
+        assert!(matches!(
+            check_expected_ids(ExpectedIds::Obsids, &jobids, &obsids),
+            Err(ParseError::UnexpectedJobIds(j)) if j == jobids
+        ));
+        assert!(matches!(
+            check_expected_ids(ExpectedIds::JobIds, &jobids, &obsids),
+            Err(ParseError::UnexpectedObsids(o)) if o == obsids
+        ));
+        assert_eq!(
+            "JobIDs".parse::<ExpectedIds>().unwrap(),
+            ExpectedIds::JobIds
+        );
+        assert!("both".parse::<ExpectedIds>().is_err());
+    }
+
+    #[test]
+    fn unmatched_ids_works() {
+        assert_eq!(unmatched_ids(&[3, 1, 2, 3, 4], &[2, 5]), vec![3, 1, 4]);
+        assert!(unmatched_ids(&[1, 2], &[2, 1]).is_empty());
+        assert!(unmatched_ids::<AsvoJobID>(&[], &[1]).is_empty());
+    }
+
+    #[test]
+    fn download_manifest_works() {
+        let path = std::env::temp_dir().join("giant_squid_download_manifest_works.txt");
+        let _ = std::fs::remove_file(&path);
+        assert!(read_download_manifest(&path).unwrap().is_empty());
+
+        let obsid = Obsid::validate(1061311664).unwrap();
+        append_to_download_manifest(&path, 12345, obsid).unwrap();
+        append_to_download_manifest(&path, 67890, obsid).unwrap();
+        let result = read_download_manifest(&path);
+        assert_eq!(result.unwrap(), vec![(12345, obsid), (67890, obsid)]);
+
+        std::fs::write(&path, "12345 1061311664\n12345\n").unwrap();
+        let result = read_download_manifest(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(ParseError::InvalidManifestLine { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn list_marker_works() {
+        let dir = std::env::temp_dir().join("giant_squid_list_marker_works");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("last_listed_jobid");
+        assert_eq!(read_list_marker(&path).unwrap(), None);
+
+        write_list_marker(&path, 12345).unwrap();
+        assert_eq!(read_list_marker(&path).unwrap(), Some(12345));
+        write_list_marker(&path, 67890).unwrap();
+        assert_eq!(read_list_marker(&path).unwrap(), Some(67890));
+
+        std::fs::write(&path, "not a job ID\n").unwrap();
+        let result = read_list_marker(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(ParseError::InsideFile { .. })));
+    }
+
+    #[test]
+    fn verify_against_checksums_works() {
+        let dir = std::env::temp_dir().join("giant_squid_verify_against_checksums_works");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a/1.fits"), b"one").unwrap();
+        let one = "fe05bcdcdc4928012781a5f1a2a77cbb5398e106".to_string();
+        let two = "ad782ecdac770fc6eb9a62e44f90873fb97fb26b".to_string();
+        let sums = dir.join("sums.sha1");
+        append_checksums(
+            &sums,
+            &[
+                (PathBuf::from("elsewhere/1.fits"), one.clone()),
+                (PathBuf::from("x/2.fits"), two.clone()),
+                (PathBuf::from("y/2.fits"), one.clone()),
+            ],
+        )
+        .unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&sums)
+            .and_then(|mut f| {
+                use std::io::Write;
+                writeln!(f, "\n# binary mode\n{} *3.fits", two.to_uppercase())
+            })
+            .unwrap();
+
+        let checksums = read_checksums(&sums).unwrap();
+        assert_eq!(checksums.len(), 4);
+        assert_eq!(checksums[3], (PathBuf::from("3.fits"), two.clone()));
+        // Found by name.
+        assert_eq!(
+            find_checksum(&checksums, &dir.join("a/1.fits")),
+            Some(one.as_str())
+        );
+        assert_eq!(sha1_file(dir.join("a/1.fits")).unwrap(), one);
+        // Found by path, even though the name is ambiguous.
+        assert_eq!(
+            find_checksum(&checksums, Path::new("x/2.fits")),
+            Some(two.as_str())
+        );
+        assert_eq!(find_checksum(&checksums, Path::new("z/2.fits")), None);
+        assert_eq!(find_checksum(&checksums, Path::new("4.fits")), None);
+
+        std::fs::write(&sums, "not a hash  1.fits\n").unwrap();
+        let result = read_checksums(&sums);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            result,
+            Err(ParseError::InvalidChecksumLine { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn append_jobids_works() {
+        let path = std::env::temp_dir().join("giant_squid_append_jobids_works.txt");
+        let _ = std::fs::remove_file(&path);
+        append_jobids(&path, &[12345, 67890]).unwrap();
+        append_jobids(&path, &[]).unwrap();
+        append_jobids(&path, &[13579]).unwrap();
+        let result = parse_jobids_and_obsids_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (jobids, obsids) = result.unwrap();
+        assert_eq!(jobids, vec![12345, 67890, 13579]);
+        assert!(obsids.is_empty());
+    }
+
+    #[test]
+    fn append_checksums_works() {
+        let path = std::env::temp_dir().join("giant_squid_append_checksums_works.sha1");
+        let _ = std::fs::remove_file(&path);
+        let hash = "fe05bcdcdc4928012781a5f1a2a77cbb5398e106".to_string();
+        append_checksums(&path, &[(PathBuf::from("a/1.fits"), hash.clone())]).unwrap();
+        append_checksums(&path, &[(PathBuf::from("2.fits"), hash.clone())]).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            format!("{hash}  a/1.fits\n{hash}  2.fits\n", hash = hash)
+        );
+    }
+
+    #[test]
+    fn find_partial_downloads_works() {
+        let dir = std::env::temp_dir().join("giant_squid_find_partial_downloads_works");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("1065880128")).unwrap();
+        for name in [
+            "1065880128_1.tar.part",
+            "1065880128_1.tar.part.etag",
+            "1065880128_2.tar",
+            "1065880128/1065880128_3.tar.part",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let result = find_partial_downloads(&dir, None);
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                dir.join("1065880128/1065880128_3.tar.part"),
+                dir.join("1065880128_1.tar.part"),
+                dir.join("1065880128_1.tar.part.etag"),
+            ]
+        );
+
+        let names = vec![
+            "1065880128_1.tar".to_string(),
+            "1065880128_2.tar".to_string(),
+        ];
+        let result = find_partial_downloads(&dir, Some(&names));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                dir.join("1065880128_1.tar.part"),
+                dir.join("1065880128_1.tar.part.etag"),
+            ]
+        );
+    }
+
+    #[test]
+    fn format_duration_works() {
+        assert_eq!(format_duration(Duration::from_millis(12345)), "12.345s");
+        assert_eq!(format_duration(Duration::from_millis(123450)), "2min3.45s");
+    }
+
+    #[test]
+    fn format_rate_works() {
+        assert_eq!(format_rate(2048, Duration::from_secs(2)), "1.0 kiB/s");
+        assert_eq!(format_rate(1, Duration::ZERO), "1000 B/s");
+    }
+
     #[test]
     fn bad_parse_map() {
         let result = parse_key_value_pairs("avg_time_res=0.5=1,avg_freq_res=10");
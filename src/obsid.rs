@@ -6,6 +6,7 @@
 
 use std::num::ParseIntError;
 use std::str::FromStr;
+use std::sync::OnceLock;
 
 use serde::Serialize;
 use thiserror::Error;
@@ -15,17 +16,45 @@ use thiserror::Error;
 #[derive(Serialize, PartialEq, Eq, Clone, Copy)]
 pub struct Obsid(u64);
 
+/// The smallest valid obsid (inclusive).
+pub const OBSID_MIN: u64 = 1_000_000_000;
+
+/// The largest valid obsid (exclusive).
+pub const OBSID_MAX: u64 = 10_000_000_000;
+
 impl Obsid {
     /// Given a [u64], return it as an MWA [Obsid] if it is valid.
+    ///
+    /// Valid obsids are between [OBSID_MIN] and [OBSID_MAX]. These bounds can
+    /// be overridden with the environment variables `GIANT_SQUID_OBSID_MIN`
+    /// and `GIANT_SQUID_OBSID_MAX`.
     pub fn validate(o: u64) -> Result<Obsid, ObsidError> {
-        // Valid obsids are between 1e9 and 1e10.
-        if o >= 1e9 as u64 && o < 1e10 as u64 {
+        let (min, max) = obsid_bounds()?;
+        match Obsid::validate_with_bounds(o, min, max) {
+            // Keep the more helpful error when using the standard bounds.
+            Err(ObsidError::OutOfBounds { .. }) if min == OBSID_MIN && max == OBSID_MAX => {
+                Err(ObsidError::WrongNumDigits(o))
+            }
+            r => r,
+        }
+    }
+
+    /// Given a [u64], return it as an MWA [Obsid] if it is between `min`
+    /// (inclusive) and `max` (exclusive).
+    pub fn validate_with_bounds(o: u64, min: u64, max: u64) -> Result<Obsid, ObsidError> {
+        if o >= min && o < max {
             Ok(Obsid(o))
         } else {
-            Err(ObsidError::WrongNumDigits(o))
+            Err(ObsidError::OutOfBounds { obsid: o, min, max })
         }
     }
 
+    /// Given a GPS time [s], return it as an MWA [Obsid] if it is in the valid
+    /// obsid range. MWA obsids are the GPS start time of the observation.
+    pub fn from_gps(gps: u64) -> Result<Obsid, ObsidError> {
+        Obsid::validate(gps)
+    }
+
     /// Convert a string of whitespace-delimited (e.g. spaces, tabs, newlines)
     /// integers to a [Vec<Obsid>]. If any of the integers are invalid as
     /// obsids, an error is returned.
@@ -34,6 +63,34 @@ impl Obsid {
     }
 }
 
+/// The bounds of valid obsids, taking `GIANT_SQUID_OBSID_MIN` and
+/// `GIANT_SQUID_OBSID_MAX` into account. The environment is only read once.
+fn obsid_bounds() -> Result<(u64, u64), ObsidError> {
+    static BOUNDS: OnceLock<Result<(u64, u64), ObsidError>> = OnceLock::new();
+    BOUNDS
+        .get_or_init(|| {
+            let min = bound_from_env("GIANT_SQUID_OBSID_MIN", OBSID_MIN)?;
+            let max = bound_from_env("GIANT_SQUID_OBSID_MAX", OBSID_MAX)?;
+            if min >= max {
+                return Err(ObsidError::InvalidBounds { min, max });
+            }
+            Ok((min, max))
+        })
+        .clone()
+}
+
+/// Read an obsid bound from the environment variable `var`, or use `default`
+/// if it isn't set.
+fn bound_from_env(var: &'static str, default: u64) -> Result<u64, ObsidError> {
+    match std::env::var(var) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| ObsidError::InvalidBoundVar { var, value }),
+        Err(_) => Ok(default),
+    }
+}
+
 impl FromStr for Obsid {
     type Err = ObsidError;
 
@@ -55,12 +112,25 @@ impl std::fmt::Debug for Obsid {
     }
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum ObsidError {
     /// If an int doesn't have 10 digits, it's not a valid obsid.
     #[error("'{0}' doesn't have 10 digits and cannot be used as an MWA obsid")]
     WrongNumDigits(u64),
 
+    /// An int outside of user-specified bounds can't be used as an obsid.
+    #[error("'{obsid}' is not between {min} and {max} and cannot be used as an MWA obsid")]
+    OutOfBounds { obsid: u64, min: u64, max: u64 },
+
+    /// An obsid bound in the environment (e.g. GIANT_SQUID_OBSID_MIN) isn't
+    /// an int.
+    #[error("{var} ({value}) is not a valid obsid bound; it must be a non-negative integer")]
+    InvalidBoundVar { var: &'static str, value: String },
+
+    /// The obsid bounds in the environment don't leave any valid obsids.
+    #[error("GIANT_SQUID_OBSID_MIN ({min}) must be less than GIANT_SQUID_OBSID_MAX ({max})")]
+    InvalidBounds { min: u64, max: u64 },
+
     /// An error associated with string parsing.
     #[error("{0}")]
     Parse(#[from] ParseIntError),
@@ -86,6 +156,37 @@ mod tests {
         assert!(Obsid::validate(10658801288).is_err());
     }
 
+    #[test]
+    fn validation_with_bounds_works() {
+        assert!(Obsid::validate_with_bounds(106588012, 1, 1e9 as u64).is_ok());
+        assert!(Obsid::validate_with_bounds(1065880128, 1, 1e9 as u64).is_err());
+    }
+
+    #[test]
+    fn validation_with_bounds_fails() {
+        assert!(matches!(
+            Obsid::validate_with_bounds(5, 10, 20),
+            Err(ObsidError::OutOfBounds {
+                obsid: 5,
+                min: 10,
+                max: 20
+            })
+        ));
+    }
+
+    #[test]
+    fn from_gps_works() {
+        assert_eq!(Obsid::from_gps(1065880128).unwrap(), Obsid(1065880128));
+    }
+
+    #[test]
+    fn from_gps_fails() {
+        assert!(matches!(
+            Obsid::from_gps(106588012),
+            Err(ObsidError::WrongNumDigits(106588012))
+        ));
+    }
+
     #[test]
     fn batch_spaces() {
         let result = Obsid::from_string("1061311664 1061311784 1061312032");